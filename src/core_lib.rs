@@ -1,16 +1,127 @@
-use crate::pipeline::{Function, Module};
-use crate::value::Value;
+use crate::error::{OmgError, Position, Result};
+use crate::pipeline::ast::{DataDef, Exp};
+use crate::pipeline::{
+    data_type, lexer, parse_block, Constraint, Function, Module, Signature, Source, Type,
+};
+use crate::value::{RecordKind, RecordType, Value, Variant};
 use im::Vector;
+use std::fs;
+use std::sync::Arc;
+
+/// The event that is emitted once when the program starts.
+pub const MAIN_EVENT: &str = "Main";
+
+pub const RESULT: &str = "Result";
+pub const OPTION: &str = "Option";
+
+/// The data types every program can use. The first variant of each is the
+/// one `?` continues with.
+const PRELUDE: &str = "
+/// The value of something that may fail.
+type Result<T, E> = Ok(T value) | Err(E error);
+/// A value that may be missing.
+type Option<T> = Some(T value) | None;
+";
+
+/// The declarations of the data types in the prelude.
+pub fn prelude() -> Vec<Arc<DataDef>> {
+    let source = Source {
+        path: "prelude".to_string(),
+        source: PRELUDE.to_string(),
+    };
+    let mut tokens = lexer(source).expect("the prelude has invalid tokens");
+    match parse_block(&mut tokens).expect("the prelude does not parse") {
+        Exp::Block(block) => block
+            .statements
+            .into_iter()
+            .filter_map(|statement| match statement {
+                Exp::DataDef(def) => Some(def),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Native {
     Print,
+    Len,
+    ReadFile,
+    WriteFile,
+    ParseNumber,
+    ParseInt,
+    Int,
+    Float,
 }
 
 impl Native {
-    pub fn call(self, args: Vector<Value>) -> Value {
-        match self {
+    pub fn call(self, module: &Module, args: Vector<Value>, pos: &Position) -> Result<Value> {
+        Ok(match self {
             Native::Print => print(args),
+            Native::Len => len(args),
+            Native::ReadFile => read_file(module, args),
+            Native::WriteFile => write_file(module, args),
+            Native::ParseNumber => parse_number(module, args),
+            Native::ParseInt => parse_int(module, args),
+            Native::Int => int(args, pos)?,
+            Native::Float => float(args),
+        })
+    }
+
+    /// What the first argument must be when its type in the signature is
+    /// `Any`, as it may be one of a few types.
+    pub fn constraint(self) -> Option<Constraint> {
+        match self {
+            Native::Len => Some(Constraint::Sized),
+            Native::Int | Native::Float => Some(Constraint::Number),
+            _ => None,
+        }
+    }
+
+    /// The types the type checker holds calls to the native to.
+    pub fn signature(self) -> Signature {
+        match self {
+            Native::Print => Signature {
+                params: Vec::new(),
+                rest: Some(Type::Any),
+                returns: Type::Nothing,
+            },
+            Native::Len => Signature {
+                params: vec![Type::Any],
+                rest: None,
+                returns: Type::Int,
+            },
+            Native::ReadFile => Signature {
+                params: vec![Type::String],
+                rest: None,
+                returns: result_type(Type::String),
+            },
+            Native::WriteFile => Signature {
+                params: vec![Type::String, Type::String],
+                rest: None,
+                returns: result_type(Type::Nothing),
+            },
+            Native::ParseNumber => Signature {
+                params: vec![Type::String],
+                rest: None,
+                returns: Type::Data(OPTION.to_string(), vec![Type::Float]),
+            },
+            Native::ParseInt => Signature {
+                params: vec![Type::String],
+                rest: None,
+                returns: Type::Data(OPTION.to_string(), vec![Type::Int]),
+            },
+            Native::Int => Signature {
+                params: vec![Type::Any],
+                rest: None,
+                returns: Type::Int,
+            },
+            Native::Float => Signature {
+                params: vec![Type::Any],
+                rest: None,
+                returns: Type::Float,
+            },
         }
     }
 }
@@ -26,9 +137,181 @@ fn print(args: Vector<Value>) -> Value {
     Value::Nothing
 }
 
+fn len(args: Vector<Value>) -> Value {
+    match args.front() {
+        Some(Value::List(items)) if args.len() == 1 => Value::Int(items.len() as i64),
+        Some(Value::String(s)) if args.len() == 1 => Value::Int(s.chars().count() as i64),
+        _ => Value::Nothing,
+    }
+}
+
+/// The errors of natives are strings that describe them.
+fn result_type(t: Type) -> Type {
+    Type::Data(RESULT.to_string(), vec![t, Type::String])
+}
+
+/// Creates a variant of the prelude, like `Ok(value)`.
+fn variant(module: &Module, name: &str, values: Vector<Value>) -> Value {
+    match module.get_function(name) {
+        Some(Function::Constructor(data_type, index)) => Value::Variant(Variant {
+            data_type,
+            index,
+            values,
+        }),
+        _ => Value::Nothing,
+    }
+}
+
+fn read_file(module: &Module, args: Vector<Value>) -> Value {
+    match args.front() {
+        Some(Value::String(path)) => match fs::read_to_string(&**path) {
+            Ok(contents) => variant(module, "Ok", vec![Value::from_string(contents)].into()),
+            Err(e) => variant(module, "Err", vec![Value::from_string(e.to_string())].into()),
+        },
+        _ => Value::Nothing,
+    }
+}
+
+fn write_file(module: &Module, args: Vector<Value>) -> Value {
+    match (args.get(0), args.get(1)) {
+        (Some(Value::String(path)), Some(Value::String(contents))) => {
+            match fs::write(&**path, contents.as_bytes()) {
+                Ok(()) => variant(module, "Ok", vec![Value::Nothing].into()),
+                Err(e) => variant(module, "Err", vec![Value::from_string(e.to_string())].into()),
+            }
+        }
+        _ => Value::Nothing,
+    }
+}
+
+fn parse_number(module: &Module, args: Vector<Value>) -> Value {
+    match args.front() {
+        Some(Value::String(s)) => match s.trim().parse() {
+            Ok(n) => variant(module, "Some", vec![Value::Float(n)].into()),
+            Err(_) => variant(module, "None", Vector::new()),
+        },
+        _ => Value::Nothing,
+    }
+}
+
+fn parse_int(module: &Module, args: Vector<Value>) -> Value {
+    match args.front() {
+        Some(Value::String(s)) => match s.trim().parse() {
+            Ok(n) => variant(module, "Some", vec![Value::Int(n)].into()),
+            Err(_) => variant(module, "None", Vector::new()),
+        },
+        _ => Value::Nothing,
+    }
+}
+
+/// Floats are rounded towards zero, it is an error if they don't fit.
+fn int(args: Vector<Value>, pos: &Position) -> Result<Value> {
+    match args.front() {
+        Some(Value::Int(n)) => Ok(Value::Int(*n)),
+        Some(Value::Float(n)) if fits_int(*n) => Ok(Value::Int(*n as i64)),
+        Some(value) => Err(OmgError::new(
+            format!("Can't convert {} to an Int", value),
+            pos.clone(),
+        )),
+        None => Ok(Value::Nothing),
+    }
+}
+
+/// If the Float rounded towards zero is in the range of an Int.
+fn fits_int(n: f64) -> bool {
+    // The largest Int is not a Float, but one more than it is.
+    let limit = 2f64.powi(63);
+    n.is_finite() && n.trunc() >= -limit && n < limit
+}
+
+fn float(args: Vector<Value>) -> Value {
+    match args.front() {
+        Some(Value::Int(n)) => Value::Float(*n as f64),
+        Some(Value::Float(n)) => Value::Float(*n),
+        _ => Value::Nothing,
+    }
+}
+
 pub fn add_std_lib(module: &Module) -> Module {
-    module.add_function("print", Function::NativeFunction(Native::Print))
+    let mut module = module
+        .add_function("print", Function::NativeFunction(Native::Print))
+        .add_function("len", Function::NativeFunction(Native::Len))
+        .add_function("read_file", Function::NativeFunction(Native::ReadFile))
+        .add_function("write_file", Function::NativeFunction(Native::WriteFile))
+        .add_function("parse_number", Function::NativeFunction(Native::ParseNumber))
+        .add_function("parse_int", Function::NativeFunction(Native::ParseInt))
+        .add_function("int", Function::NativeFunction(Native::Int))
+        .add_function("float", Function::NativeFunction(Native::Float))
+        .add_type(Arc::new(RecordType {
+            name: MAIN_EVENT.to_string(),
+            kind: RecordKind::Event,
+            fields: Vec::new(),
+        }));
+    for def in prelude() {
+        module = module.add_data(&data_type(&def));
+    }
+    module
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    fn call(native: Native, args: Vec<Value>) -> Value {
+        let module = add_std_lib(&Module::new());
+        native.call(&module, args.into(), &Position::new("test")).unwrap()
+    }
+
+    #[test]
+    fn len_list() {
+        let list = Value::List(vec![Value::Nothing, Value::Nothing].into());
+        assert_eq!(call(Native::Len, vec![list]), Value::Int(2));
+    }
+
+    #[test]
+    fn len_string() {
+        let string = Value::from_string("wörld");
+        assert_eq!(call(Native::Len, vec![string]), Value::Int(5));
+    }
+
+    #[test]
+    fn len_wrong_args() {
+        assert_eq!(call(Native::Len, Vec::new()), Value::Nothing);
+        assert_eq!(call(Native::Len, vec![Value::Float(1.0)]), Value::Nothing);
+    }
+
+    #[test]
+    fn parse_numbers() {
+        let some = call(Native::ParseNumber, vec![Value::from_string(" 1.5 ")]);
+        assert_eq!(some.to_string(), "Some(1.5)");
+        let none = call(Native::ParseNumber, vec![Value::from_string("one")]);
+        assert_eq!(none.to_string(), "None");
+        let some = call(Native::ParseInt, vec![Value::from_string("12")]);
+        assert_eq!(some.to_string(), "Some(12)");
+        let none = call(Native::ParseInt, vec![Value::from_string("1.5")]);
+        assert_eq!(none.to_string(), "None");
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(call(Native::Int, vec![Value::Float(-2.5)]), Value::Int(-2));
+        assert_eq!(call(Native::Float, vec![Value::Int(3)]), Value::Float(3.0));
+        let module = add_std_lib(&Module::new());
+        let err = Native::Int
+            .call(&module, vec![Value::Float(1e20)].into(), &Position::new("test"))
+            .unwrap_err();
+        assert_eq!(err.msg, "Can't convert 100000000000000000000.0 to an Int");
+    }
+
+    #[test]
+    fn files() {
+        let path = std::env::temp_dir().join("omglang_core_lib_files.txt");
+        let path = Value::from_string(path.to_string_lossy().to_string());
+        let written = call(Native::WriteFile, vec![path.clone(), Value::from_string("hi")]);
+        assert_eq!(written.to_string(), "Ok(Nothing)");
+        let read = call(Native::ReadFile, vec![path]);
+        assert_eq!(read.to_string(), "Ok(\"hi\")");
+        let missing = call(Native::ReadFile, vec![Value::from_string("/no/such/file")]);
+        assert!(missing.to_string().starts_with("Err("));
+    }
+}
@@ -2,10 +2,10 @@ use std::path::PathBuf;
 use std::fmt;
 use std::sync::Arc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OmgError {
     pub msg: String,
-    pub pos: String,
+    pub pos: Position,
 }
 
 impl OmgError {
@@ -15,7 +15,7 @@ impl OmgError {
     {
         OmgError {
             msg: msg.into(),
-            pos: pos.to_string(),
+            pos,
         }
     }
 }
@@ -26,11 +26,15 @@ impl fmt::Display for OmgError {
     }
 }
 
+/// The range of source a token or error covers, from the line and column it
+/// starts at up to the line and column just after it.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Position {
     pub src: Arc<String>,
     pub line: u64,
     pub column: u64,
+    pub end_line: u64,
+    pub end_column: u64,
 }
 
 impl Position {
@@ -42,31 +46,42 @@ impl Position {
             src: Arc::new(src.into()),
             line: 1,
             column: 1,
+            end_line: 1,
+            end_column: 1,
         }
     }
 
+    /// An empty range at the line and column.
     pub fn with_pos(&self, line: u64, column: u64) -> Self {
         Position {
             src: Arc::clone(&self.src),
             line,
             column,
+            end_line: line,
+            end_column: column,
         }
     }
 
-    pub fn add(&self, count: u64) -> Self {
+    /// The same start, ending at the line and column.
+    pub fn with_end(&self, end_line: u64, end_column: u64) -> Self {
         Position {
-            src: Arc::clone(&self.src),
-            line: self.line,
-            column: self.column + count,
+            end_line,
+            end_column,
+            ..self.clone()
         }
     }
 
+    pub fn add(&self, count: u64) -> Self {
+        self.with_pos(self.line, self.column + count)
+    }
+
     pub fn newline(&self) -> Self {
-        Position {
-            src: Arc::clone(&self.src),
-            line: self.line + 1,
-            column: 1,
-        }
+        self.with_pos(self.line + 1, 1)
+    }
+
+    /// If the line and column is in the range, or right at its end.
+    pub fn contains(&self, line: u64, column: u64) -> bool {
+        (self.line, self.column) <= (line, column) && (line, column) <= (self.end_line, self.end_column)
     }
 }
 
@@ -90,6 +105,47 @@ impl From<PathBuf> for Position {
 
 pub type Result<T> = std::result::Result<T, OmgError>;
 
+/// Ends an error message about an unknown name with the closest of the known
+/// names, or returns an empty string when none of them are close.
+pub fn did_you_mean<'a, I>(name: &str, known: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    // Allow about one typo for every three characters.
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = known
+        .into_iter()
+        .map(|known| (edit_distance(name, known), known))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    close.sort();
+    close.dedup();
+    let names: Vec<&str> = close.iter().take(3).map(|(_, known)| *known).collect();
+    match names.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!(", did you mean {}?", last),
+        Some((last, rest)) => format!(", did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+/// The Levenshtein distance, the number of characters that have to be
+/// inserted, removed or replaced to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let replace = previous[j] + if a == *b { 0 } else { 1 };
+            let insert = current[j] + 1;
+            let remove = previous[j + 1] + 1;
+            current.push(replace.min(insert).min(remove));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,6 +157,35 @@ mod tests {
         assert_eq!(display, "test.omg:1:2");
     }
 
+    #[test]
+    fn ranges() {
+        let pos = Position::new("test.omg").with_pos(1, 2).with_end(1, 5);
+        assert!(pos.contains(1, 2));
+        assert!(pos.contains(1, 5));
+        assert!(!pos.contains(1, 6));
+        assert!(!pos.contains(0, 3));
+        assert_eq!(pos.add(1), Position::new("test.omg").with_pos(1, 3));
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("hello", "hello"), 0);
+        assert_eq!(edit_distance("helo", "hello"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let known = vec!["hello", "help", "world", "print"];
+        assert_eq!(
+            did_you_mean("helo", known.clone()),
+            ", did you mean hello or help?"
+        );
+        assert_eq!(did_you_mean("prnt", known.clone()), ", did you mean print?");
+        assert_eq!(did_you_mean("x", known), "");
+    }
+
     #[test]
     fn omg_error() {
         let pos = Position::new("test.omg").with_pos(1, 2);
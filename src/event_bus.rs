@@ -0,0 +1,521 @@
+use crate::{
+    error::{OmgError, Result},
+    pipeline::{Handler, Module},
+    runtime::Runtime,
+    value::{Record, RecordKind, Value},
+};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::prelude::{
+    task::{self, Task},
+    Future,
+};
+
+/// Delivers events to the `run` handlers and to the runtimes waiting in
+/// `Event.next()`.
+///
+/// A runtime only sees the events emitted after it started, and `next` hands
+/// them out in the order they were emitted, one at a time for each event
+/// type. The bus keeps an event until every runtime has either seen it or
+/// stopped.
+pub struct EventBus {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    handlers: HashMap<String, Vec<(Arc<Module>, Handler)>>,
+    events: VecDeque<Record>,
+    /// The number of the first event in `events`.
+    first: u64,
+    listeners: HashMap<u64, Listening>,
+    next_id: u64,
+    /// Runtimes that are not waiting for an event.
+    running: usize,
+    /// Set when every runtime is waiting, as no event can ever arrive then.
+    closed: bool,
+    /// Set when a handler stopped with an error.
+    failed: bool,
+}
+
+struct Listening {
+    /// The number of the first event emitted after the runtime started.
+    start: u64,
+    /// The number of the next event to look at for each event type.
+    cursors: HashMap<String, u64>,
+    waiting: Option<(String, Task)>,
+    /// Waiting on the runtimes of an `async` block instead of an event.
+    suspended: bool,
+    /// The runtime of the `async` block this runtime runs a statement of. It
+    /// gets the events seen by this runtime when it leaves.
+    parent: Option<u64>,
+}
+
+impl Listening {
+    fn cursor(&self, event: &str) -> u64 {
+        self.cursors.get(event).cloned().unwrap_or(self.start)
+    }
+}
+
+pub enum Next {
+    Event(Record),
+    /// The runtime is woken up when an event of the type is emitted.
+    Waiting,
+    /// Every runtime is waiting, so the event will never be emitted.
+    Closed,
+}
+
+impl EventBus {
+    pub fn new() -> Arc<EventBus> {
+        Arc::new(EventBus {
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("event bus lock poisoned")
+    }
+
+    /// Runs the handler every time its event is emitted. The handler runs
+    /// with the functions and types found in `module`.
+    pub fn add_handler(&self, module: &Arc<Module>, handler: Handler) -> Result<()> {
+        let def = &handler.def;
+        match module.get_type(&def.event) {
+            Some(ref record_type) if record_type.kind == RecordKind::Event => (),
+            Some(_) => {
+                return Err(OmgError::new(
+                    format!("{} is a record and not an event", def.event),
+                    def.pos.clone(),
+                ))
+            }
+            None => {
+                return Err(OmgError::new(
+                    format!("Cant find event named {} to run on", def.event),
+                    def.pos.clone(),
+                ))
+            }
+        }
+        self.lock()
+            .handlers
+            .entry(def.event.clone())
+            .or_default()
+            .push((Arc::clone(module), handler));
+        Ok(())
+    }
+
+    /// Registers a runtime that sees the events emitted from now on.
+    pub fn listen(self: &Arc<Self>) -> Listener {
+        let mut state = self.lock();
+        let start = state.end();
+        Listener {
+            bus: Arc::clone(self),
+            id: state.join(start),
+        }
+    }
+
+    /// Spawns a task with a fresh runtime for every handler of the event and
+    /// wakes the runtimes waiting for it. Returns the number of handlers.
+    /// Must be called from within a tokio executor if the event has handlers.
+    pub fn emit(self: &Arc<Self>, event: Record) -> usize {
+        let mut runtimes = Vec::new();
+        {
+            let mut state = self.lock();
+            let state = &mut *state;
+            let start = state.end() + 1;
+            let name = &event.record_type.name;
+            let handlers = state.handlers.get(name).cloned().unwrap_or_default();
+            for (module, handler) in &handlers {
+                let listener = Listener {
+                    bus: Arc::clone(self),
+                    id: state.join(start),
+                };
+                let mut runtime = Runtime::listening(module, listener);
+                if let Some(name) = &handler.def.name {
+                    runtime.set_variable(name.clone(), Value::Record(event.clone()));
+                }
+                runtime.start(&handler.code);
+                runtimes.push(runtime);
+            }
+            state.wake(name);
+            state.events.push_back(event);
+            state.prune();
+        }
+        let count = runtimes.len();
+        for runtime in runtimes {
+            let bus = Arc::clone(self);
+            tokio::spawn(runtime.map(|_| ()).map_err(move |error| {
+                eprint!("{}", error);
+                bus.lock().failed = true;
+            }));
+        }
+        count
+    }
+
+    /// If any handler has stopped with an error.
+    pub fn failed(&self) -> bool {
+        self.lock().failed
+    }
+}
+
+impl State {
+    /// The number the next event emitted gets.
+    fn end(&self) -> u64 {
+        self.first + self.events.len() as u64
+    }
+
+    fn join(&mut self, start: u64) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.listeners.insert(
+            id,
+            Listening {
+                start,
+                cursors: HashMap::new(),
+                waiting: None,
+                suspended: false,
+                parent: None,
+            },
+        );
+        self.running += 1;
+        id
+    }
+
+    /// Joins with the same events seen as the parent.
+    fn fork(&mut self, parent: u64) -> u64 {
+        let (start, cursors) = match self.listeners.get(&parent) {
+            Some(listening) => (listening.start, listening.cursors.clone()),
+            None => (self.end(), HashMap::new()),
+        };
+        let id = self.join(start);
+        if let Some(listening) = self.listeners.get_mut(&id) {
+            listening.cursors = cursors;
+            listening.parent = Some(parent);
+        }
+        id
+    }
+
+    fn leave(&mut self, id: u64) {
+        let listening = match self.listeners.remove(&id) {
+            Some(listening) => listening,
+            None => return,
+        };
+        if listening.waiting.is_none() && !listening.suspended {
+            self.stop_running();
+        }
+        let parent = listening.parent.and_then(|id| self.listeners.get_mut(&id));
+        if let Some(parent) = parent {
+            for (event, cursor) in listening.cursors {
+                let parent_cursor = parent.cursor(&event).max(cursor);
+                parent.cursors.insert(event, parent_cursor);
+            }
+        }
+        self.prune();
+    }
+
+    fn suspend(&mut self, id: u64, suspended: bool) {
+        if let Some(listening) = self.listeners.get_mut(&id) {
+            if listening.suspended == suspended {
+                return;
+            }
+            listening.suspended = suspended;
+            if suspended {
+                self.stop_running();
+            } else {
+                self.running += 1;
+            }
+        }
+    }
+
+    fn next(&mut self, id: u64, event: &str) -> Next {
+        let listening = self
+            .listeners
+            .get_mut(&id)
+            .expect("listener has left the event bus");
+        let skip = listening.cursor(event).saturating_sub(self.first) as usize;
+        let found = self
+            .events
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .find(|(_, record)| record.record_type.name == event);
+        if let Some((i, record)) = found {
+            let record = record.clone();
+            if listening.waiting.take().is_some() {
+                self.running += 1;
+            }
+            listening
+                .cursors
+                .insert(event.to_string(), self.first + i as u64 + 1);
+            self.prune();
+            return Next::Event(record);
+        }
+        if self.closed {
+            return Next::Closed;
+        }
+        let was_waiting = listening
+            .waiting
+            .replace((event.to_string(), task::current()))
+            .is_some();
+        if !was_waiting {
+            self.stop_running();
+        }
+        if self.closed {
+            Next::Closed
+        } else {
+            Next::Waiting
+        }
+    }
+
+    fn stop_running(&mut self) {
+        self.running -= 1;
+        if self.running == 0 {
+            self.close();
+        }
+    }
+
+    /// Wakes up every waiting runtime to let them fail, as nothing is left
+    /// running that could emit the events they wait for.
+    fn close(&mut self) {
+        let tasks: Vec<Task> = self
+            .listeners
+            .values_mut()
+            .filter_map(|listening| listening.waiting.take())
+            .map(|(_, task)| task)
+            .collect();
+        if tasks.is_empty() {
+            return;
+        }
+        self.closed = true;
+        self.running += tasks.len();
+        for task in tasks {
+            task.notify();
+        }
+    }
+
+    fn wake(&mut self, event: &str) {
+        for listening in self.listeners.values_mut() {
+            let waiting_for_event = match &listening.waiting {
+                Some((name, _)) => name == event,
+                None => false,
+            };
+            if waiting_for_event {
+                if let Some((_, task)) = listening.waiting.take() {
+                    self.running += 1;
+                    task.notify();
+                }
+            }
+        }
+    }
+
+    /// Drops the oldest events once no runtime can receive them anymore.
+    fn prune(&mut self) {
+        while let Some(event) = self.events.front() {
+            let name = &event.record_type.name;
+            let first = self.first;
+            if self
+                .listeners
+                .values()
+                .any(|listening| listening.cursor(name) <= first)
+            {
+                return;
+            }
+            self.events.pop_front();
+            self.first += 1;
+        }
+    }
+}
+
+/// A runtime's connection to the bus, leaves the bus when dropped.
+pub struct Listener {
+    bus: Arc<EventBus>,
+    id: u64,
+}
+
+impl Listener {
+    /// The next event of the type this runtime has not seen yet. When there
+    /// is none the current task is woken up once there might be.
+    pub fn next(&self, event: &str) -> Next {
+        self.bus.lock().next(self.id, event)
+    }
+
+    pub fn emit(&self, event: Record) -> usize {
+        self.bus.emit(event)
+    }
+
+    /// A listener for a statement of an `async` block that starts out having
+    /// seen the same events as this one. The events it sees are seen by this
+    /// one as well when it leaves.
+    pub fn fork(&self) -> Listener {
+        Listener {
+            bus: Arc::clone(&self.bus),
+            id: self.bus.lock().fork(self.id),
+        }
+    }
+
+    /// Marks the runtime as waiting on the statements of an `async` block.
+    pub fn suspend(&self) {
+        self.bus.lock().suspend(self.id, true);
+    }
+
+    pub fn resume(&self) {
+        self.bus.lock().suspend(self.id, false);
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.bus.lock().leave(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_lib::{add_std_lib, MAIN_EVENT};
+    use crate::pipeline::{compile, lexer, parse_block, Source};
+    use crate::value::RecordType;
+    use tokio::prelude::future::lazy;
+
+    fn bus(source: &str) -> Result<(Arc<Module>, Arc<EventBus>)> {
+        let mut tokens = lexer(Source {
+            path: "test".to_string(),
+            source: source.to_string(),
+        })?;
+        let exp = parse_block(&mut tokens)?;
+        let mut runtime = Runtime::new(&Arc::new(add_std_lib(&Module::new())));
+        runtime.run(&exp)?;
+        let module = Arc::clone(runtime.module());
+        let bus = EventBus::new();
+        for handler in compile(&exp)?.handlers {
+            bus.add_handler(&module, handler)?;
+        }
+        Ok((module, bus))
+    }
+
+    fn event(module: &Module, name: &str, values: Vec<Value>) -> Record {
+        Record {
+            record_type: module.get_type(name).unwrap(),
+            values: values.into(),
+        }
+    }
+
+    #[test]
+    fn emit_main() {
+        let (module, bus) = bus("event Hello { hello; }
+            run (Main) { }
+            run (Hello) { }
+            run (Main main) { print(main); }")
+        .unwrap();
+        let main = event(&module, MAIN_EVENT, Vec::new());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let fired = runtime
+            .block_on_all(lazy(move || Ok::<_, ()>(bus.emit(main))))
+            .unwrap();
+        assert_eq!(fired, 2);
+    }
+
+    /// Emits `Main` and waits until every handler is done.
+    fn run_main(source: &str) -> Arc<EventBus> {
+        let (module, bus) = bus(source).unwrap();
+        let main = event(&module, MAIN_EVENT, Vec::new());
+        let emitting = Arc::clone(&bus);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on_all(lazy(move || Ok::<_, ()>(emitting.emit(main))))
+            .unwrap();
+        bus
+    }
+
+    #[test]
+    fn failed_handler() {
+        assert!(!run_main("run (Main) { print(1); }").failed());
+        assert!(run_main("run (Main) { print(1 / 0); }").failed());
+        assert!(run_main("event Never { }\nrun (Main) { Never.next(); }").failed());
+    }
+
+    #[test]
+    fn failed_async_statement_stops_the_others() {
+        let (module, bus) = bus("event Hello { }
+            run (Main) { async { Hello.next(); print(1 / 0); } }")
+        .unwrap();
+        // Keeps the bus open, so the statement waiting for Hello is not woken
+        // up by the bus closing.
+        let _open = bus.listen();
+        let main = event(&module, MAIN_EVENT, Vec::new());
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        let emitting = Arc::clone(&bus);
+        runtime.spawn(lazy(move || {
+            emitting.emit(main);
+            Ok(())
+        }));
+        for _ in 0..200 {
+            if bus.failed() && bus.lock().listeners.len() == 1 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(bus.failed());
+        {
+            let state = bus.lock();
+            assert_eq!(state.listeners.len(), 1);
+            assert_eq!(state.running, 1);
+            assert!(!state.closed);
+        }
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn unknown_event() {
+        let err = bus("run (Missing) { }").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:0:0");
+    }
+
+    #[test]
+    fn record_is_not_event() {
+        let err = bus("record A { }\nrun (A) { }").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:0");
+    }
+
+    fn hello(n: f64) -> Record {
+        Record {
+            record_type: Arc::new(RecordType {
+                name: "Hello".to_string(),
+                kind: RecordKind::Event,
+                fields: vec!["n".to_string()],
+            }),
+            values: vec![Value::Float(n)].into(),
+        }
+    }
+
+    #[test]
+    fn next_sees_events_after_start() {
+        let bus = EventBus::new();
+        let early = bus.listen();
+        bus.emit(hello(1.0));
+        let late = bus.listen();
+        bus.emit(hello(2.0));
+        for n in &[1.0, 2.0] {
+            match early.next("Hello") {
+                Next::Event(record) => assert_eq!(record, hello(*n)),
+                _ => panic!("Expected event"),
+            }
+        }
+        match late.next("Hello") {
+            Next::Event(record) => assert_eq!(record, hello(2.0)),
+            _ => panic!("Expected event"),
+        }
+        drop(late);
+        assert_eq!(bus.lock().events.len(), 0);
+    }
+
+    #[test]
+    fn next_closes_when_all_wait() {
+        let bus = EventBus::new();
+        let listener = bus.listen();
+        let next = lazy(move || Ok::<_, ()>(listener.next("Hello")));
+        match next.wait().unwrap() {
+            Next::Closed => (),
+            _ => panic!("Expected the bus to be closed"),
+        }
+    }
+}
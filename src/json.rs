@@ -0,0 +1,440 @@
+use std::fmt;
+
+static NULL: Json = Json::Null;
+
+/// Values that can be written as JSON, for tools that read what the pipeline
+/// makes of a file.
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(ToJson::to_json).collect())
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().map_or(Json::Null, ToJson::to_json)
+    }
+}
+
+impl<T: ToJson> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl<T: ToJson> ToJson for std::sync::Arc<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl ToJson for String {
+    fn to_json(&self) -> Json {
+        Json::String(self.clone())
+    }
+}
+
+/// A JSON value. Objects keep their keys in the order they were added, so the
+/// same value is always written the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// A whole number, kept apart from `Number` so it is written exactly.
+    Int(i64),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<'a, I>(entries: I) -> Json
+    where
+        I: IntoIterator<Item = (&'a str, Json)>,
+    {
+        Json::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The value of the key in an object, `Null` when there is none.
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(entries) => entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map_or(&NULL, |(_, value)| value),
+            _ => &NULL,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Int(n) if *n >= 0 => Some(*n as u64),
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    /// The value with every item and key on a line of its own, indented by
+    /// two spaces.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let (open, close, children): (char, char, Vec<(Option<&String>, &Json)>) = match self {
+            Json::Array(items) if !items.is_empty() => {
+                ('[', ']', items.iter().map(|item| (None, item)).collect())
+            }
+            Json::Object(entries) if !entries.is_empty() => (
+                '{',
+                '}',
+                entries
+                    .iter()
+                    .map(|(key, value)| (Some(key), value))
+                    .collect(),
+            ),
+            _ => return out.push_str(&self.to_string()),
+        };
+        out.push(open);
+        for (i, (key, value)) in children.into_iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push('\n');
+            out.push_str(&"  ".repeat(indent + 1));
+            if let Some(key) = key {
+                out.push_str(&Json::String(key.clone()).to_string());
+                out.push_str(": ");
+            }
+            value.write_pretty(out, indent + 1);
+        }
+        out.push('\n');
+        out.push_str(&"  ".repeat(indent));
+        out.push(close);
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            index: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.chars.get(parser.index) {
+            None => Ok(value),
+            Some(c) => Err(format!("Expected end of JSON found {}", c)),
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(n: u64) -> Self {
+        Json::Int(n as i64)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(n: i64) -> Self {
+        Json::Int(n)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Json::Array(items)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Json::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            // Floats always keep a fraction, so they read back as floats.
+            Json::Number(n) if n.fract() == 0.0 && n.is_finite() => write!(f, "{}.0", n),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl Parser {
+    fn whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek() {
+            self.index += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or("Unexpected end of JSON")?;
+        self.index += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(format!("Expected {}", word));
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek().ok_or("Unexpected end of JSON")? {
+            'n' => self.expect("null").map(|_| Json::Null),
+            't' => self.expect("true").map(|_| Json::Bool(true)),
+            'f' => self.expect("false").map(|_| Json::Bool(false)),
+            '"' => self.string().map(Json::String),
+            '[' => {
+                self.index += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.index += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        ']' => return Ok(Json::Array(items)),
+                        c => return Err(format!("Expected , or ] found {}", c)),
+                    }
+                }
+            }
+            '{' => {
+                self.index += 1;
+                let mut entries = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.index += 1;
+                    return Ok(Json::Object(entries));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    if self.next()? != ':' {
+                        return Err("Expected :".to_string());
+                    }
+                    entries.push((key, self.value()?));
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        '}' => return Ok(Json::Object(entries)),
+                        c => return Err(format!("Expected , or }} found {}", c)),
+                    }
+                }
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.index;
+        while let Some('0'..='9') | Some('-') | Some('+') | Some('.') | Some('e') | Some('E') =
+            self.peek()
+        {
+            self.index += 1;
+        }
+        let number: String = self.chars[start..self.index].iter().collect();
+        number
+            .parse()
+            .map(Json::Int)
+            .or_else(|_| number.parse().map(Json::Number))
+            .map_err(|_| format!("Invalid JSON value {}", number))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.next()? != '"' {
+            return Err("Expected \"".to_string());
+        }
+        let mut string = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(string),
+                '\\' => match self.next()? {
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex()?;
+                        // Characters outside the basic plane come as two
+                        // escaped UTF-16 surrogates.
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.hex()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00));
+                        }
+                        string.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    c => string.push(c),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next()?.to_digit(16).ok_or("Invalid \\u escape")?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"id":1,"list":[true,false,null,-1.5,"a\"b\n"],"empty":{}}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("id").as_u64(), Some(1));
+        assert_eq!(json.get("list").as_array().len(), 5);
+        assert_eq!(json.get("missing"), &Json::Null);
+        assert_eq!(json.to_string(), text);
+    }
+
+    #[test]
+    fn numbers() {
+        let text = "[9007199254740993,-3,1.0,1e20,0.1]";
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.as_array()[0], Json::Int(9_007_199_254_740_993));
+        assert_eq!(
+            json.to_string(),
+            "[9007199254740993,-3,1.0,100000000000000000000.0,0.1]"
+        );
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+    }
+
+    #[test]
+    fn strings() {
+        let json = Json::parse(r#" "\u00e9\ud83d\ude00\t" "#).unwrap();
+        assert_eq!(json.as_str(), Some("é😀\t"));
+        assert_eq!(Json::from("\u{1}").to_string(), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn pretty() {
+        let json = Json::parse(r#"{"a":[1,{}],"b":[]}"#).unwrap();
+        assert_eq!(
+            json.pretty(),
+            "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": []\n}"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Json::parse("[1,").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("nul").is_err());
+    }
+}
@@ -1,22 +1,32 @@
 #![warn(clippy::all)]
 mod core_lib;
 mod error;
+mod event_bus;
+mod json;
+mod lsp;
 mod pipeline;
+mod repl;
 mod runtime;
 mod value;
 
-use crate::core_lib::add_std_lib;
-use crate::pipeline::Module;
+use crate::core_lib::{add_std_lib, MAIN_EVENT};
+use crate::event_bus::EventBus;
+use crate::pipeline::{check, compile, Module, Program};
+use crate::value::Record;
+use im::Vector;
 use pipeline::parse_block;
 use runtime::Runtime;
-use tokio::prelude::Future;
+use tokio::prelude::{future, Async, Future};
 
 use std::sync::Arc;
 
 pub use error::OmgError;
+pub use lsp::LanguageServer;
+pub use repl::{is_incomplete, Repl};
 
 pub struct OmgLang {
     module: Arc<Module>,
+    bus: Arc<EventBus>,
 }
 
 impl Default for OmgLang {
@@ -31,21 +41,166 @@ impl OmgLang {
         let module = add_std_lib(&module);
         OmgLang {
             module: Arc::new(module),
+            bus: EventBus::new(),
         }
     }
 
+    /// Parses, type checks and compiles the file without running any of it,
+    /// giving every error found.
+    pub fn check_file(&self, file: &str) -> impl Future<Item = (), Error = Vec<OmgError>> {
+        load_program(file, &self.module).map(|_| ())
+    }
+
+    /// Type checks the file, runs its top level and then emits `Main` to
+    /// start its `run` handlers. The handlers keep running on the executor
+    /// after the future has completed.
     #[cfg_attr(tarpaulin, skip)]
-    pub fn run_file(&self, file: &str) -> impl Future<Item = (), Error = OmgError> {
+    pub fn run_file(&self, file: &str) -> impl Future<Item = (), Error = Vec<OmgError>> {
         let module = Arc::clone(&self.module);
-        pipeline::loader(file.to_string()).and_then(move |source| {
-            let mut tokens = pipeline::lexer(source)?;
-            let exp = parse_block(&mut tokens)?;
-            let mut runtime = Runtime::new(&module);
-            runtime.run(&exp)?;
-            Ok(())
+        let bus = Arc::clone(&self.bus);
+        load_program(file, &self.module).and_then(move |program| {
+            let mut runtime = Runtime::listening(&module, bus.listen());
+            runtime.start(&program.code);
+            let top_level = future::poll_fn(move || match runtime.poll()? {
+                Async::Ready(_) => Ok(Async::Ready(Arc::clone(runtime.module()))),
+                Async::NotReady => Ok(Async::NotReady),
+            });
+            let top_level = top_level.and_then(move |module| {
+                for handler in program.handlers {
+                    bus.add_handler(&module, handler)?;
+                }
+                if let Some(record_type) = module.get_type(MAIN_EVENT) {
+                    bus.emit(Record {
+                        record_type,
+                        values: Vector::new(),
+                    });
+                }
+                Ok(())
+            });
+            top_level.map_err(|e| vec![e])
         })
     }
+
+    /// If a handler started by `run_file` stopped with an error, which it
+    /// has already printed.
+    pub fn failed(&self) -> bool {
+        self.bus.failed()
+    }
+}
+
+/// Lays out the source the one way omg code is written, keeping comments and
+/// blank lines. The path is only used in errors.
+pub fn format(path: &str, source: &str) -> Result<String, OmgError> {
+    pipeline::format(pipeline::Source {
+        path: path.to_string(),
+        source: source.to_string(),
+    })
+}
+
+/// The tokens of the source as pretty printed JSON, for debugging the lexer
+/// and for tools. The path is only used in errors.
+pub fn dump_tokens(path: &str, source: &str) -> Result<String, OmgError> {
+    pipeline::dump_tokens(pipeline::Source {
+        path: path.to_string(),
+        source: source.to_string(),
+    })
+    .map(|json| json.pretty())
+}
+
+/// The syntax tree of the source as pretty printed JSON, for debugging the
+/// parser and for tools. The path is only used in errors.
+pub fn dump_ast(path: &str, source: &str) -> Result<String, OmgError> {
+    pipeline::dump_ast(pipeline::Source {
+        path: path.to_string(),
+        source: source.to_string(),
+    })
+    .map(|json| json.pretty())
+}
+
+/// Loads the file and takes it through every pass up to compiling it.
+fn load_program(
+    file: &str,
+    module: &Arc<Module>,
+) -> impl Future<Item = Program, Error = Vec<OmgError>> {
+    let module = Arc::clone(module);
+    pipeline::loader(file.to_string())
+        .map_err(|e| vec![e])
+        .and_then(move |source| {
+            let mut tokens = pipeline::lexer(source).map_err(|e| vec![e])?;
+            let exp = parse_block(&mut tokens).map_err(|e| vec![e])?;
+            check(&exp, &module)?;
+            compile(&exp).map_err(|e| vec![e])
+        })
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    fn check_source(name: &str, source: &str) -> Result<(), Vec<String>> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, source).unwrap();
+        let file = path.to_str().unwrap().to_string();
+        let mut executor = tokio::runtime::Runtime::new().unwrap();
+        let result = executor.block_on(OmgLang::new().check_file(&file));
+        std::fs::remove_file(&path).unwrap();
+        result.map_err(|errors| errors.into_iter().map(|e| e.msg).collect())
+    }
+
+    #[test]
+    fn check_file() {
+        check_source("omg_check_ok.omg", "let a = 1;\nprint(a + 1);").unwrap();
+        assert_eq!(
+            check_source("omg_check_types.omg", "let a = 1;\na = 2;\nprint(a + true);"),
+            Err(vec![
+                "Can't assign to a which wasn't declared with var".to_string(),
+                "Can't use + on Int and Boolean".to_string(),
+            ])
+        );
+        assert_eq!(
+            check_source("omg_check_syntax.omg", "let = 1;"),
+            Err(vec!["Expected variable found =".to_string()])
+        );
+        assert_eq!(
+            check_source("omg_check_compile.omg", "break;"),
+            Err(vec!["Found break outside of a loop".to_string()])
+        );
+    }
+
+    #[test]
+    fn showcase_files_run() {
+        for file in &["goal.omg", "main.omg"] {
+            let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), file);
+            let omg = OmgLang::new();
+            let mut executor = tokio::runtime::Runtime::new().unwrap();
+            let result = executor.block_on(omg.run_file(&path));
+            executor.shutdown_on_idle().wait().unwrap();
+            if let Err(errors) = result {
+                panic!("{} failed: {:?}", file, errors);
+            }
+            assert!(!omg.failed(), "a handler of {} failed", file);
+        }
+    }
+
+    #[test]
+    fn failed_handler() {
+        let path = std::env::temp_dir().join("omg_failed_handler.omg");
+        std::fs::write(&path, "run (Main) { print(1 / 0); }").unwrap();
+        let omg = OmgLang::new();
+        let mut executor = tokio::runtime::Runtime::new().unwrap();
+        let result = executor.block_on(omg.run_file(path.to_str().unwrap()));
+        executor.shutdown_on_idle().wait().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+        assert!(omg.failed());
+    }
+
+    #[test]
+    fn check_missing_file() {
+        let mut executor = tokio::runtime::Runtime::new().unwrap();
+        let errors = executor
+            .block_on(OmgLang::new().check_file("does/not/exist.omg"))
+            .unwrap_err();
+        assert!(errors[0].msg.starts_with("Can't open does/not/exist.omg"));
+    }
+}
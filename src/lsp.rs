@@ -0,0 +1,690 @@
+use crate::core_lib::add_std_lib;
+use crate::error::{OmgError, Position};
+use crate::json::Json;
+use crate::pipeline::{
+    check_types, compile, lexer, library, parse_block, symbols, Definition, Module, Source, Symbol,
+    SymbolKind, Token, TriviaKind, Type,
+};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The kinds of semantic tokens, a token is sent as its index in this list.
+const TOKEN_TYPES: &[&str] = &[
+    "keyword",
+    "variable",
+    "parameter",
+    "function",
+    "type",
+    "enumMember",
+    "property",
+    "number",
+    "string",
+    "comment",
+    "operator",
+];
+
+/// Serves editors over the Language Server Protocol, with diagnostics, go to
+/// definition, hover, completion and semantic tokens for open files.
+///
+/// Lines and columns are counted from 0, and columns count characters.
+pub struct LanguageServer {
+    module: Arc<Module>,
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+    exited: bool,
+}
+
+/// An open file and what was found in it the last time it changed.
+struct Document {
+    text: String,
+    symbols: Vec<Symbol>,
+    /// The types of variables, by the line and column they are declared or
+    /// used at.
+    types: HashMap<(u64, u64), Type>,
+}
+
+impl Default for LanguageServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        LanguageServer {
+            module: Arc::new(add_std_lib(&Module::new())),
+            documents: HashMap::new(),
+            shut_down: false,
+            exited: false,
+        }
+    }
+
+    /// Answers messages from the input until the editor says `exit` or the
+    /// input ends. Returns if the editor asked to shut down first, as it
+    /// should.
+    pub fn serve<R, W>(&mut self, mut input: R, mut output: W) -> io::Result<bool>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        while let Some(body) = read_message(&mut input)? {
+            let replies = match Json::parse(&body) {
+                Ok(message) => self.handle(&message),
+                Err(e) => vec![error(Json::Null, PARSE_ERROR, e)],
+            };
+            for reply in replies {
+                let body = reply.to_string();
+                write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+            }
+            output.flush()?;
+            if self.exited {
+                break;
+            }
+        }
+        Ok(self.shut_down)
+    }
+
+    /// Handles one request or notification, giving the messages to send
+    /// back.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        match message.get("id") {
+            Json::Null => self.notification(method, params).into_iter().collect(),
+            id => vec![match self.request(method, params) {
+                Ok(result) => Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("result", result),
+                ]),
+                Err((code, message)) => error(id.clone(), code, message),
+            }],
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        if self.shut_down {
+            return Err((INVALID_REQUEST, "The server is shut down".to_string()));
+        }
+        Ok(match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shut_down = true;
+                Json::Null
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ => return Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        })
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Option<Json> {
+        if method == "exit" {
+            self.exited = true;
+            return None;
+        }
+        let uri = params.get("textDocument").get("uri").as_str()?.to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str()?;
+                Some(self.update(uri, text.to_string()))
+            }
+            // Whole files are sent on every change.
+            "textDocument/didChange" => {
+                let change = params.get("contentChanges").as_array().last()?;
+                Some(self.update(uri, change.get("text").as_str()?.to_string()))
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                Some(diagnostics(&uri, &[]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Looks at the changed file and gives the errors found in it.
+    fn update(&mut self, uri: String, text: String) -> Json {
+        let (document, errors) = analyze(&self.module, &uri, text);
+        self.documents.insert(uri.clone(), document);
+        diagnostics(&uri, &errors)
+    }
+
+    /// The open document and the symbol under the cursor.
+    fn symbol_at(&self, params: &Json) -> Option<(&Document, &Symbol)> {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let document = self.documents.get(uri)?;
+        let line = params.get("position").get("line").as_u64()?;
+        let column = params.get("position").get("character").as_u64()?;
+        let symbol = document
+            .symbols
+            .iter()
+            .find(|symbol| symbol.pos.contains(line, column))?;
+        Some((document, symbol))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let uri = params.get("textDocument").get("uri").clone();
+        self.symbol_at(params)
+            .and_then(|(_, symbol)| symbol.declaration.pos.as_ref())
+            .map_or(Json::Null, |pos| {
+                Json::object(vec![("uri", uri), ("range", range(pos))])
+            })
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let (document, symbol) = match self.symbol_at(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let declaration = &symbol.declaration;
+        if declaration.detail.is_empty() {
+            return Json::Null;
+        }
+        let mut detail = declaration.detail.clone();
+        let key = (symbol.pos.line, symbol.pos.column);
+        // Types that are not fully worked out read badly, like `List<?>`.
+        match document.types.get(&key).map(Type::to_string) {
+            Some(t) if is_value(declaration) && !t.contains('?') => {
+                // The detail ends with the name, the type goes in front of it.
+                let keyword = &detail[..detail.len() - declaration.name.len()];
+                detail = format!("{}{} {}", keyword, t, declaration.name);
+            }
+            _ => (),
+        }
+        let mut value = format!("```omg\n{}\n```", detail);
+        if let Some(doc) = &declaration.doc {
+            value.push_str("\n\n");
+            value.push_str(doc);
+        }
+        Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![("kind", "markdown".into()), ("value", value.into())]),
+            ),
+            ("range", range(&symbol.pos)),
+        ])
+    }
+
+    /// The functions and types of the standard library and the names declared
+    /// in the file.
+    fn completion(&self, params: &Json) -> Json {
+        let uri = params.get("textDocument").get("uri").as_str();
+        let declared = uri
+            .and_then(|uri| self.documents.get(uri))
+            .into_iter()
+            .flat_map(|document| &document.symbols)
+            .filter(|symbol| symbol.declaration.pos.as_ref() == Some(&symbol.pos))
+            .map(|symbol| Arc::clone(&symbol.declaration));
+        let mut items = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        for declaration in library(&self.module).into_iter().chain(declared) {
+            if !seen.insert(declaration.name.clone()) {
+                continue;
+            }
+            let kind: u64 = match declaration.kind {
+                SymbolKind::Variable | SymbolKind::Parameter => 6,
+                SymbolKind::Function => 3,
+                SymbolKind::Type => 22,
+                SymbolKind::Variant => 20,
+                SymbolKind::Field => 5,
+            };
+            items.push(Json::object(vec![
+                ("label", declaration.name.as_str().into()),
+                ("kind", kind.into()),
+                ("detail", declaration.detail.as_str().into()),
+                ("documentation", declaration.doc.clone().into()),
+            ]));
+        }
+        items.into()
+    }
+
+    fn semantic_tokens(&self, params: &Json) -> Json {
+        let uri = params.get("textDocument").get("uri").as_str();
+        let data = match uri.and_then(|uri| Some((uri, self.documents.get(uri)?))) {
+            Some((uri, document)) => semantic_tokens(uri, document),
+            None => Vec::new(),
+        };
+        Json::object(vec![("data", data.into())])
+    }
+}
+
+fn capabilities() -> Json {
+    let legend = Json::object(vec![
+        (
+            "tokenTypes",
+            TOKEN_TYPES
+                .iter()
+                .map(|&t| t.into())
+                .collect::<Vec<Json>>()
+                .into(),
+        ),
+        ("tokenModifiers", Json::Array(Vec::new())),
+    ]);
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                ("textDocumentSync", 1u64.into()),
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                ("completionProvider", Json::object(vec![])),
+                (
+                    "semanticTokensProvider",
+                    Json::object(vec![("legend", legend), ("full", true.into())]),
+                ),
+            ]),
+        ),
+        ("serverInfo", Json::object(vec![("name", "omglang".into())])),
+    ])
+}
+
+/// Takes the file through every pass up to compiling it, stopping at the
+/// first pass with errors.
+fn analyze(module: &Arc<Module>, uri: &str, text: String) -> (Document, Vec<OmgError>) {
+    let mut document = Document {
+        text,
+        symbols: Vec::new(),
+        types: HashMap::new(),
+    };
+    let tokens = match lexer(source(uri, &document.text)) {
+        Ok(tokens) => tokens,
+        Err(e) => return (document, vec![e]),
+    };
+    let exp = match parse_block(&mut tokens.clone()) {
+        Ok(exp) => exp,
+        Err(e) => return (document, vec![e]),
+    };
+    document.symbols = symbols(&exp, &tokens, module);
+    let (types, mut errors) = check_types(&exp, module);
+    document.types = types
+        .into_iter()
+        .map(|(pos, t)| ((pos.line, pos.column), t))
+        .collect();
+    if errors.is_empty() {
+        if let Err(e) = compile(&exp) {
+            errors.push(e);
+        }
+    }
+    (document, errors)
+}
+
+fn source(uri: &str, text: &str) -> Source {
+    Source {
+        path: uri.to_string(),
+        source: text.to_string(),
+    }
+}
+
+fn is_value(declaration: &Definition) -> bool {
+    matches!(
+        declaration.kind,
+        SymbolKind::Variable | SymbolKind::Parameter
+    )
+}
+
+/// Every token of the file as five numbers, each relative to the token
+/// before it, the way the protocol sends them.
+fn semantic_tokens(uri: &str, document: &Document) -> Vec<Json> {
+    let mut tokens = match lexer(source(uri, &document.text)) {
+        Ok(tokens) => tokens,
+        Err(_) => return Vec::new(),
+    };
+    let kinds: HashMap<(u64, u64), SymbolKind> = document
+        .symbols
+        .iter()
+        .map(|symbol| {
+            (
+                (symbol.pos.line, symbol.pos.column),
+                symbol.declaration.kind,
+            )
+        })
+        .collect();
+    let mut spans: Vec<(u64, u64, &str, String)> = Vec::new();
+    let mut previous = Token::EndOfFile;
+    loop {
+        for trivia in tokens.trivia().iter() {
+            if trivia.kind != TriviaKind::BlankLine {
+                spans.push((trivia.line, trivia.column, "comment", trivia.slice.clone()));
+            }
+        }
+        let token = tokens.current();
+        if token == Token::EndOfFile {
+            break;
+        }
+        let pos = tokens.position();
+        let token_type = match token {
+            Token::Identifier => match kinds.get(&(pos.line, pos.column)) {
+                Some(SymbolKind::Variable) => Some("variable"),
+                Some(SymbolKind::Parameter) => Some("parameter"),
+                Some(SymbolKind::Function) => Some("function"),
+                Some(SymbolKind::Type) => Some("type"),
+                Some(SymbolKind::Variant) => Some("enumMember"),
+                Some(SymbolKind::Field) => Some("property"),
+                // Names that could not be resolved, like in a file that
+                // does not parse, are told apart by what is around them.
+                None if previous == Token::Dot => Some("property"),
+                None if tokens.peek() == Token::ParenthesesOpen => Some("function"),
+                None if tokens.slice().starts_with(char::is_uppercase) => Some("type"),
+                None if tokens.slice() == "_" => None,
+                None => Some("variable"),
+            },
+            Token::Number => Some("number"),
+            Token::String => Some("string"),
+            Token::True
+            | Token::False
+            | Token::If
+            | Token::Else
+            | Token::While
+            | Token::For
+            | Token::In
+            | Token::Break
+            | Token::Continue
+            | Token::Fn
+            | Token::Return
+            | Token::Record
+            | Token::Event
+            | Token::New
+            | Token::Run
+            | Token::Async
+            | Token::Type
+            | Token::Match
+            | Token::Let
+            | Token::Var => Some("keyword"),
+            Token::ParenthesesOpen
+            | Token::ParenthesesClose
+            | Token::BraceOpen
+            | Token::BraceClose
+            | Token::BracketOpen
+            | Token::BracketClose
+            | Token::Comma
+            | Token::Dot
+            | Token::Semicolon
+            | Token::Colon
+            | Token::EndOfFile => None,
+            _ => Some("operator"),
+        };
+        if let Some(token_type) = token_type {
+            spans.push((pos.line, pos.column, token_type, tokens.slice().to_string()));
+        }
+        previous = token;
+        tokens.next();
+    }
+    spans.sort_by_key(|&(line, column, _, _)| (line, column));
+
+    let mut data = Vec::new();
+    let (mut last_line, mut last_column) = (0, 0);
+    for (line, column, token_type, slice) in spans {
+        let index = TOKEN_TYPES
+            .iter()
+            .position(|&t| t == token_type)
+            .unwrap_or(0);
+        // Tokens can't span lines, so strings and comments that do are sent
+        // a line at a time.
+        for (i, part) in slice.split('\n').enumerate() {
+            let line = line + i as u64;
+            let column = if i == 0 { column } else { 0 };
+            let length = part.trim_end_matches('\r').chars().count() as u64;
+            if length == 0 {
+                continue;
+            }
+            let delta_column = if line == last_line {
+                column - last_column
+            } else {
+                column
+            };
+            for n in &[line - last_line, delta_column, length, index as u64, 0] {
+                data.push((*n).into());
+            }
+            last_line = line;
+            last_column = column;
+        }
+    }
+    data
+}
+
+fn range(pos: &Position) -> Json {
+    let position = |line: u64, column: u64| {
+        Json::object(vec![("line", line.into()), ("character", column.into())])
+    };
+    Json::object(vec![
+        ("start", position(pos.line, pos.column)),
+        ("end", position(pos.end_line, pos.end_column)),
+    ])
+}
+
+fn diagnostics(uri: &str, errors: &[OmgError]) -> Json {
+    let diagnostics: Vec<Json> = errors
+        .iter()
+        .map(|e| {
+            Json::object(vec![
+                ("range", range(&e.pos)),
+                ("severity", 1u64.into()),
+                ("source", "omg".into()),
+                ("message", e.msg.as_str().into()),
+            ])
+        })
+        .collect();
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", diagnostics.into()),
+            ]),
+        ),
+    ])
+}
+
+fn error(id: Json, code: i64, message: String) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            Json::object(vec![("code", Json::Int(code)), ("message", message.into())]),
+        ),
+    ])
+}
+
+/// Reads the headers and body of the next message, `None` when the input has
+/// ended.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.omg";
+
+    fn open(server: &mut LanguageServer, text: &str) -> Json {
+        let message = Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/didOpen".into()),
+            (
+                "params",
+                Json::object(vec![(
+                    "textDocument",
+                    Json::object(vec![("uri", URI.into()), ("text", text.into())]),
+                )]),
+            ),
+        ]);
+        server.handle(&message).remove(0)
+    }
+
+    fn request(server: &mut LanguageServer, method: &str, line: u64, column: u64) -> Json {
+        let message = Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", 1u64.into()),
+            ("method", method.into()),
+            (
+                "params",
+                Json::object(vec![
+                    ("textDocument", Json::object(vec![("uri", URI.into())])),
+                    (
+                        "position",
+                        Json::object(vec![("line", line.into()), ("character", column.into())]),
+                    ),
+                ]),
+            ),
+        ]);
+        server.handle(&message).remove(0).get("result").clone()
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut server = LanguageServer::new();
+        let published = open(&mut server, "let a = 1;\nlet b = a + \"\";");
+        let diagnostics = published.get("params").get("diagnostics").as_array();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0]
+                .get("range")
+                .get("start")
+                .get("line")
+                .as_u64(),
+            Some(1)
+        );
+        let published = open(&mut server, "let a = (1;");
+        let diagnostics = published.get("params").get("diagnostics").as_array();
+        assert_eq!(diagnostics.len(), 1);
+        let published = open(&mut server, "let a = 1;");
+        assert!(published
+            .get("params")
+            .get("diagnostics")
+            .as_array()
+            .is_empty());
+    }
+
+    #[test]
+    fn definition_and_hover() {
+        let mut server = LanguageServer::new();
+        open(
+            &mut server,
+            "/// Doubles.\nfn double(n) { n * 2 }\nlet x = double(2);\nprint(x);",
+        );
+        let definition = request(&mut server, "textDocument/definition", 3, 6);
+        assert_eq!(
+            definition.get("range").to_string(),
+            r#"{"start":{"line":2,"character":4},"end":{"line":2,"character":5}}"#
+        );
+        let hover = request(&mut server, "textDocument/hover", 2, 9);
+        assert_eq!(
+            hover.get("contents").get("value").as_str(),
+            Some("```omg\nfn double(n)\n```\n\nDoubles.")
+        );
+        let hover = request(&mut server, "textDocument/hover", 3, 6);
+        assert_eq!(
+            hover.get("contents").get("value").as_str(),
+            Some("```omg\nlet Int x\n```")
+        );
+        let hover = request(&mut server, "textDocument/hover", 3, 1);
+        assert_eq!(
+            hover.get("contents").get("value").as_str(),
+            Some("```omg\nfn print(Any...) -> Nothing\n```")
+        );
+        assert_eq!(
+            request(&mut server, "textDocument/definition", 3, 1),
+            Json::Null
+        );
+    }
+
+    #[test]
+    fn completion() {
+        let mut server = LanguageServer::new();
+        open(&mut server, "let total = 1;");
+        let items = request(&mut server, "textDocument/completion", 0, 0);
+        let labels: Vec<&str> = items
+            .as_array()
+            .iter()
+            .filter_map(|item| item.get("label").as_str())
+            .collect();
+        for name in &["print", "parse_int", "Option", "Some", "Main", "total"] {
+            assert!(labels.contains(name), "{} is missing", name);
+        }
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let mut server = LanguageServer::new();
+        open(&mut server, "// Hi\nfn f(a) {\n  print(\"a\", a)\n}");
+        let data = request(&mut server, "textDocument/semanticTokens/full", 0, 0);
+        let data: Vec<u64> = data
+            .get("data")
+            .as_array()
+            .iter()
+            .filter_map(Json::as_u64)
+            .collect();
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 0, 5, 9, 0,
+            1, 0, 2, 0, 0,
+            0, 3, 1, 3, 0,
+            0, 2, 1, 2, 0,
+            1, 2, 5, 3, 0,
+            0, 6, 3, 8, 0,
+            0, 5, 1, 2, 0,
+        ];
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn protocol() {
+        let mut server = LanguageServer::new();
+        let unknown = request(&mut server, "textDocument/unknown", 0, 0);
+        assert_eq!(unknown, Json::Null);
+        let message = Json::parse(r#"{"jsonrpc":"2.0","id":7,"method":"nope"}"#).unwrap();
+        let reply = server.handle(&message).remove(0);
+        assert_eq!(reply.get("error").get("code"), &Json::Int(-32601));
+
+        let mut input = Vec::new();
+        for body in &[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            "{",
+            r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ] {
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        let mut output = Vec::new();
+        assert!(LanguageServer::new()
+            .serve(&input[..], &mut output)
+            .unwrap());
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<&str> = output.split("Content-Length: ").skip(1).collect();
+        assert_eq!(replies.len(), 3);
+        assert!(replies[0].contains(r#""hoverProvider":true"#));
+        assert!(replies[1].contains(r#""code":-32700"#));
+        assert!(replies[2].ends_with(r#"{"jsonrpc":"2.0","id":2,"result":null}"#));
+    }
+}
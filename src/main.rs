@@ -1,8 +1,22 @@
 #![warn(clippy::all)]
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup, SubCommand};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use tokio::prelude::Future;
 
-use omglang::OmgLang;
+use omglang::{is_incomplete, LanguageServer, OmgError, OmgLang, Repl};
+
+const HELP: &str = "Type code to run it, the value of an expression is printed.
+Input goes on at the next line while brackets are left open.
+
+:help         show this help
+:reset        forget all variables, functions and types
+:type <exp>   show the type of the expression without running it
+
+Press Ctrl-D to quit.";
 
 #[cfg_attr(tarpaulin, skip)]
 fn main() {
@@ -10,17 +24,294 @@ fn main() {
         .version("0.0.0")
         .author("Ole Martin Gjersvik")
         .about("The multi core language.")
+        .after_help("Starts a REPL when no subcommand or file is given.")
         .arg(
             Arg::with_name("SRC_FILE")
-                .help("the .omg file to run")
-                .required(true)
+                .help("the .omg file to run, the same as the run subcommand")
                 .index(1),
         )
+        .subcommand(
+            SubCommand::with_name("run").about("Runs a file").arg(
+                Arg::with_name("SRC_FILE")
+                    .help("the .omg file to run")
+                    .required(true)
+                    .index(1),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Reports the errors in files without running them")
+                .arg(
+                    Arg::with_name("SRC_FILES")
+                        .help("the .omg files to check")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats files in place")
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("lists the files that are not formatted instead of changing them"),
+                )
+                .arg(
+                    Arg::with_name("SRC_FILES")
+                        .help("the .omg files to format")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dump")
+                .about("Prints the tokens or syntax tree of a file as JSON")
+                .arg(
+                    Arg::with_name("tokens")
+                        .long("tokens")
+                        .help("prints the tokens"),
+                )
+                .arg(
+                    Arg::with_name("ast")
+                        .long("ast")
+                        .help("prints the syntax tree"),
+                )
+                .group(
+                    ArgGroup::with_name("what")
+                        .args(&["tokens", "ast"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("SRC_FILE")
+                        .help("the .omg file to dump")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lsp")
+                .about("Serves editors over the Language Server Protocol on stdin and stdout"),
+        )
         .get_matches();
 
+    match matches.subcommand() {
+        ("run", Some(run)) => run_file(run.value_of("SRC_FILE").unwrap()),
+        ("check", Some(check)) => {
+            if !check_files(check.values_of("SRC_FILES").unwrap()) {
+                std::process::exit(1);
+            }
+        }
+        ("fmt", Some(fmt)) => {
+            let check = fmt.is_present("check");
+            if !format_files(fmt.values_of("SRC_FILES").unwrap(), check) {
+                std::process::exit(1);
+            }
+        }
+        ("dump", Some(dump)) => {
+            let file = dump.value_of("SRC_FILE").unwrap();
+            if !dump_file(file, dump.is_present("ast")) {
+                std::process::exit(1);
+            }
+        }
+        ("lsp", Some(_)) => lsp(),
+        _ => match matches.value_of("SRC_FILE") {
+            Some(file) => run_file(file),
+            None => repl(),
+        },
+    }
+}
+
+#[cfg_attr(tarpaulin, skip)]
+fn lsp() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    match LanguageServer::new().serve(stdin.lock(), stdout.lock()) {
+        Ok(true) => (),
+        // The editor went away without asking the server to shut down.
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Can't talk to the editor, io error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Runs the file and its handlers, exiting with 1 when it fails to start or
+/// a handler fails.
+#[cfg_attr(tarpaulin, skip)]
+fn run_file(file: &str) {
     let omg = OmgLang::new();
-    let future = omg
-        .run_file(matches.value_of("SRC_FILE").unwrap())
-        .map_err(|e| eprint!("{}", e));
+    let failed = Arc::new(AtomicBool::new(false));
+    let failed_run = Arc::clone(&failed);
+    let future = omg.run_file(file).map_err(move |errors| {
+        print_errors(&errors);
+        failed_run.store(true, Ordering::SeqCst);
+    });
     tokio::run(future);
+    if failed.load(Ordering::SeqCst) || omg.failed() {
+        std::process::exit(1);
+    }
+}
+
+/// Checks every file, returning if they were all free of errors.
+#[cfg_attr(tarpaulin, skip)]
+fn check_files<'a, I>(files: I) -> bool
+where
+    I: Iterator<Item = &'a str>,
+{
+    let omg = OmgLang::new();
+    let mut executor = tokio::runtime::Runtime::new().expect("can't start an executor");
+    let mut ok = true;
+    for file in files {
+        if let Err(errors) = executor.block_on(omg.check_file(file)) {
+            print_errors(&errors);
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Formats every file in place, or with `check` only lists the files that
+/// would change. Returns if all went well.
+#[cfg_attr(tarpaulin, skip)]
+fn format_files<'a, I>(files: I, check: bool) -> bool
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut ok = true;
+    for file in files {
+        let source = match std::fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Can't open {}, io error: {}", file, e);
+                ok = false;
+                continue;
+            }
+        };
+        let formatted = match omglang::format(file, &source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                print_errors(&[e]);
+                ok = false;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", file);
+            ok = false;
+        } else if let Err(e) = std::fs::write(file, formatted) {
+            eprintln!("Can't write {}, io error: {}", file, e);
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Prints the syntax tree of the file as JSON, or its tokens unless `ast` is
+/// set. Returns if all went well.
+#[cfg_attr(tarpaulin, skip)]
+fn dump_file(file: &str, ast: bool) -> bool {
+    let source = match std::fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Can't open {}, io error: {}", file, e);
+            return false;
+        }
+    };
+    let dump = if ast {
+        omglang::dump_ast(file, &source)
+    } else {
+        omglang::dump_tokens(file, &source)
+    };
+    match dump {
+        Ok(json) => {
+            println!("{}", json);
+            true
+        }
+        Err(e) => {
+            print_errors(&[e]);
+            false
+        }
+    }
+}
+
+fn print_errors(errors: &[OmgError]) {
+    for e in errors {
+        eprint!("{}", e);
+    }
+}
+
+/// Reads code from the terminal and runs it, until the input ends.
+#[cfg_attr(tarpaulin, skip)]
+fn repl() {
+    let mut repl = Repl::new();
+    let mut editor = Editor::<()>::new();
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history the first time.
+        let _ = editor.load_history(path);
+    }
+    println!("O.M.G. Language, type :help for help");
+    let mut source = String::new();
+    loop {
+        let prompt = if source.is_empty() { "omg> " } else { "...> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                source.clear();
+                continue;
+            }
+            Err(_) => break,
+        };
+        if source.is_empty() && line.trim_start().starts_with(':') {
+            editor.add_history_entry(line.trim());
+            command(&mut repl, line.trim());
+            continue;
+        }
+        source.push_str(&line);
+        source.push('\n');
+        if is_incomplete(&source) {
+            continue;
+        }
+        editor.add_history_entry(source.trim_end());
+        match repl.eval(&source) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => (),
+            Err(errors) => print_errors(&errors),
+        }
+        source.clear();
+    }
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Can't save the history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg_attr(tarpaulin, skip)]
+fn command(repl: &mut Repl, line: &str) {
+    let (name, arg) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+    match name {
+        ":help" => println!("{}", HELP),
+        ":reset" => repl.reset(),
+        ":type" if arg.is_empty() => eprintln!("Expected an expression after :type"),
+        ":type" => match repl.type_of(arg) {
+            Ok(t) => println!("{}", t),
+            Err(errors) => print_errors(&errors),
+        },
+        _ => eprintln!("Unknown command {}, type :help for help", name),
+    }
+}
+
+/// The history is kept in the home directory, when there is one.
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".omg_history"))
 }
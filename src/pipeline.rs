@@ -1,16 +1,31 @@
 pub mod ast;
+mod checker;
+mod code;
+mod compiler;
+mod dump;
+mod exhaustive;
+mod formatter;
 mod function;
 mod lexer;
 mod loader;
 mod module;
 mod parser;
 mod source;
+mod symbols;
 mod tokens;
+mod types;
 
 pub use loader::loader;
 pub use source::Source;
-pub use tokens::{Token, Tokens};
-pub use lexer::lexer;
+pub use tokens::{Token, Tokens, TriviaKind};
+pub use lexer::{lexer, lexer_at};
 pub use parser::parse_block;
+pub use formatter::format;
+pub use dump::{dump_ast, dump_tokens};
+pub use code::{Code, Handler, Instruction, UserFunction};
+pub use compiler::{compile, data_type, Program};
+pub use checker::{check, check_types, Session};
+pub use types::{Constraint, Signature, Type};
 pub use function::Function;
 pub use module::Module;
+pub use symbols::{library, symbols, Definition, Symbol, SymbolKind};
@@ -1,9 +1,13 @@
-use crate::value::Value;
+use crate::value::{RecordKind, Value};
 use crate::error::Position;
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq)]
 pub struct Block {
     pub statements: Vec<Exp>,
+    /// The last expression when it is not followed by `;`, it becomes the
+    /// value of the block.
+    pub value: Option<Box<Exp>>,
     pub pos: Position,
 }
 
@@ -20,8 +24,27 @@ pub struct Literal {
     pub pos: Position,
 }
 
+/// A type written in the source, like `Int` or `List<String>`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeName {
+    pub name: String,
+    pub args: Vec<TypeName>,
+    pub pos: Position,
+}
+
+/// How a variable is declared, only `var` variables can be assigned again.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Declaration {
+    Let,
+    Var,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Assignment {
+    /// Set when the assignment declares the variable, as in `let name = "";`.
+    pub declaration: Option<Declaration>,
+    /// The type in front of the name, as in `let String name = "";`.
+    pub type_name: Option<TypeName>,
     pub name: String,
     pub value: Box<Exp>,
     pub pos: Position,
@@ -33,15 +56,78 @@ pub struct Variable {
     pub pos: Position,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum OpType {
     Add,
     Subtract,
     Multiply,
+    /// Divides Ints into an Int, rounding towards zero.
     Divide,
+    Remainder,
+    Power,
     Equal,
+    NotEqual,
     GreaterThan,
+    GreaterEqual,
     LessThan,
+    LessEqual,
+    /// Only evaluates the right hand side when the left is `true`.
+    And,
+    /// Only evaluates the right hand side when the left is `false`.
+    Or,
+}
+
+/// Prefix operators bind tighter than any binary operator but `**`, so
+/// `-2 ** 2` is `-(2 ** 2)`.
+pub const UNARY_PRECEDENCE: u8 = 6;
+
+impl OpType {
+    /// How tightly the operator binds, higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            OpType::Or => 1,
+            OpType::And => 2,
+            OpType::Equal
+            | OpType::NotEqual
+            | OpType::GreaterThan
+            | OpType::GreaterEqual
+            | OpType::LessThan
+            | OpType::LessEqual => 3,
+            OpType::Add | OpType::Subtract => 4,
+            OpType::Multiply | OpType::Divide | OpType::Remainder => 5,
+            OpType::Power => 7,
+        }
+    }
+
+    /// `2 ** 3 ** 2` is `2 ** (3 ** 2)`, all other operators group to the
+    /// left.
+    pub fn right_associative(&self) -> bool {
+        *self == OpType::Power
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            OpType::Add => "+",
+            OpType::Subtract => "-",
+            OpType::Multiply => "*",
+            OpType::Divide => "/",
+            OpType::Remainder => "%",
+            OpType::Power => "**",
+            OpType::Equal => "==",
+            OpType::NotEqual => "!=",
+            OpType::GreaterThan => ">",
+            OpType::GreaterEqual => ">=",
+            OpType::LessThan => "<",
+            OpType::LessEqual => "<=",
+            OpType::And => "&&",
+            OpType::Or => "||",
+        }
+    }
+
+    /// If the right hand side is skipped when the left decides the result.
+    pub fn short_circuits(&self) -> bool {
+        matches!(self, OpType::And | OpType::Or)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -49,6 +135,235 @@ pub struct Operator {
     pub op_type: OpType,
     pub lhs: Box<Exp>,
     pub rhs: Box<Exp>,
+    /// Where the operator itself is.
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct If {
+    pub condition: Box<Exp>,
+    pub then: Box<Exp>,
+    pub otherwise: Option<Box<Exp>>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct While {
+    pub condition: Box<Exp>,
+    pub body: Box<Exp>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct For {
+    pub name: String,
+    pub collection: Box<Exp>,
+    pub body: Box<Exp>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub type_name: Option<TypeName>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Box<Exp>,
+    /// The `///` comments in front of the declaration.
+    pub doc: Option<String>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Return {
+    pub value: Option<Box<Exp>>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct List {
+    pub items: Vec<Exp>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    pub exp: Box<Exp>,
+    pub index: Box<Exp>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldDef {
+    pub name: String,
+    pub type_name: Option<TypeName>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RecordDef {
+    pub name: String,
+    pub kind: RecordKind,
+    pub fields: Vec<FieldDef>,
+    /// The `///` comments in front of the declaration.
+    pub doc: Option<String>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct New {
+    pub name: String,
+    pub args: Vec<Exp>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Field {
+    pub exp: Box<Exp>,
+    pub name: String,
+    pub pos: Position,
+}
+
+/// `Type.name(args)`, methods are only found on types, like `Hello.emit(e)`.
+#[derive(Debug, PartialEq)]
+pub struct MethodCall {
+    pub type_name: String,
+    pub name: String,
+    pub args: Vec<Exp>,
+    pub pos: Position,
+}
+
+/// `run (Event name) { body }`, the body runs in a new runtime every time the
+/// event is emitted.
+#[derive(Debug, PartialEq)]
+pub struct RunDef {
+    pub event: String,
+    /// Name the event is bound to in the body.
+    pub name: Option<String>,
+    pub body: Box<Exp>,
+    pub pos: Position,
+}
+
+/// `async { statements }`, every statement runs at the same time and the
+/// block ends when all of them have.
+#[derive(Debug, PartialEq)]
+pub struct Async {
+    pub statements: Vec<Exp>,
+    pub pos: Position,
+}
+
+/// `type Name<T> = Variant(fields) | Other`, a data type that is one of its
+/// variants.
+#[derive(Debug, PartialEq)]
+pub struct DataDef {
+    pub name: String,
+    /// The names of the type parameters.
+    pub params: Vec<String>,
+    pub variants: Vec<VariantDef>,
+    /// The `///` comments in front of the declaration.
+    pub doc: Option<String>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VariantDef {
+    pub name: String,
+    pub fields: Vec<FieldDef>,
+    pub pos: Position,
+}
+
+/// `match exp { pattern if guard => value, }`, the value of the first arm
+/// that matches.
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub exp: Box<Exp>,
+    pub arms: Vec<MatchArm>,
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Arc<Pattern>,
+    pub guard: Option<Box<Exp>>,
+    pub value: Box<Exp>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Pattern {
+    /// `_` matches anything.
+    Wildcard(Position),
+    /// A name binds the value to a variable, unless it is a variant without
+    /// fields.
+    Name(String, Position),
+    Literal(Value, Position),
+    /// `Circle(r)`
+    Variant {
+        name: String,
+        args: Vec<Pattern>,
+        pos: Position,
+    },
+    /// `Point { x, y: 0 }`, fields that are left out match anything.
+    Record {
+        name: String,
+        fields: Vec<(String, Pattern)>,
+        pos: Position,
+    },
+    /// `[first, second, ..rest]`, without `..` the length must match.
+    List {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+        pos: Position,
+    },
+}
+
+impl Pattern {
+    pub fn position(&self) -> Position {
+        match self {
+            Pattern::Wildcard(pos) | Pattern::Name(_, pos) | Pattern::Literal(_, pos) => {
+                pos.clone()
+            }
+            Pattern::Variant { pos, .. }
+            | Pattern::Record { pos, .. }
+            | Pattern::List { pos, .. } => pos.clone(),
+        }
+    }
+}
+
+/// `exp?` continues with the value in an `Ok` or `Some`, and returns an
+/// `Err` or `None` from the function.
+#[derive(Debug, PartialEq)]
+pub struct Try {
+    pub exp: Box<Exp>,
+    /// Where the `?` is.
+    pub pos: Position,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryType {
+    Negate,
+    Plus,
+    Not,
+}
+
+impl UnaryType {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryType::Negate => "-",
+            UnaryType::Plus => "+",
+            UnaryType::Not => "!",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Unary {
+    pub unary_type: UnaryType,
+    pub exp: Box<Exp>,
     pub pos: Position,
 }
 
@@ -60,11 +375,42 @@ pub enum Exp {
     Assignment(Assignment),
     Variable(Variable),
     Operator(Operator),
+    Unary(Unary),
+    If(If),
+    While(While),
+    For(For),
+    Break(Position),
+    Continue(Position),
+    FunctionDef(Arc<FunctionDef>),
+    Return(Return),
+    List(List),
+    Index(Index),
+    RecordDef(Arc<RecordDef>),
+    New(New),
+    Field(Field),
+    MethodCall(MethodCall),
+    Run(Arc<RunDef>),
+    Async(Async),
+    DataDef(Arc<DataDef>),
+    Match(Match),
+    Try(Try),
 }
 
 impl Exp {
     pub fn new_block(statements: Vec<Exp>, pos: Position) -> Exp {
-        Exp::Block(Block { statements, pos })
+        Exp::Block(Block {
+            statements,
+            value: None,
+            pos,
+        })
+    }
+
+    pub fn new_block_value(statements: Vec<Exp>, value: Box<Exp>, pos: Position) -> Exp {
+        Exp::Block(Block {
+            statements,
+            value: Some(value),
+            pos,
+        })
     }
 
     pub fn new_call(name: String, args: Vec<Exp>, pos: Position) -> Exp {
@@ -76,7 +422,29 @@ impl Exp {
     }
 
     pub fn new_assignment(name: String, value: Box<Exp>, pos: Position) -> Exp {
-        Exp::Assignment(Assignment { name, value, pos })
+        Exp::Assignment(Assignment {
+            declaration: None,
+            type_name: None,
+            name,
+            value,
+            pos,
+        })
+    }
+
+    pub fn new_declaration(
+        declaration: Declaration,
+        type_name: Option<TypeName>,
+        name: String,
+        value: Box<Exp>,
+        pos: Position,
+    ) -> Exp {
+        Exp::Assignment(Assignment {
+            declaration: Some(declaration),
+            type_name,
+            name,
+            value,
+            pos,
+        })
     }
 
     pub fn new_variable(name: String, pos: Position) -> Exp {
@@ -92,6 +460,143 @@ impl Exp {
         })
     }
 
+    pub fn new_unary(unary_type: UnaryType, exp: Box<Exp>, pos: Position) -> Exp {
+        Exp::Unary(Unary {
+            unary_type,
+            exp,
+            pos,
+        })
+    }
+
+    pub fn new_if(
+        condition: Box<Exp>,
+        then: Box<Exp>,
+        otherwise: Option<Box<Exp>>,
+        pos: Position,
+    ) -> Exp {
+        Exp::If(If {
+            condition,
+            then,
+            otherwise,
+            pos,
+        })
+    }
+
+    pub fn new_while(condition: Box<Exp>, body: Box<Exp>, pos: Position) -> Exp {
+        Exp::While(While {
+            condition,
+            body,
+            pos,
+        })
+    }
+
+    pub fn new_for(name: String, collection: Box<Exp>, body: Box<Exp>, pos: Position) -> Exp {
+        Exp::For(For {
+            name,
+            collection,
+            body,
+            pos,
+        })
+    }
+
+    pub fn new_function_def(
+        name: String,
+        params: Vec<Param>,
+        body: Box<Exp>,
+        doc: Option<String>,
+        pos: Position,
+    ) -> Exp {
+        Exp::FunctionDef(Arc::new(FunctionDef {
+            name,
+            params,
+            body,
+            doc,
+            pos,
+        }))
+    }
+
+    pub fn new_return(value: Option<Box<Exp>>, pos: Position) -> Exp {
+        Exp::Return(Return { value, pos })
+    }
+
+    pub fn new_list(items: Vec<Exp>, pos: Position) -> Exp {
+        Exp::List(List { items, pos })
+    }
+
+    pub fn new_index(exp: Box<Exp>, index: Box<Exp>, pos: Position) -> Exp {
+        Exp::Index(Index { exp, index, pos })
+    }
+
+    pub fn new_record_def(
+        name: String,
+        kind: RecordKind,
+        fields: Vec<FieldDef>,
+        doc: Option<String>,
+        pos: Position,
+    ) -> Exp {
+        Exp::RecordDef(Arc::new(RecordDef {
+            name,
+            kind,
+            fields,
+            doc,
+            pos,
+        }))
+    }
+
+    pub fn new_new(name: String, args: Vec<Exp>, pos: Position) -> Exp {
+        Exp::New(New { name, args, pos })
+    }
+
+    pub fn new_field(exp: Box<Exp>, name: String, pos: Position) -> Exp {
+        Exp::Field(Field { exp, name, pos })
+    }
+
+    pub fn new_method_call(type_name: String, name: String, args: Vec<Exp>, pos: Position) -> Exp {
+        Exp::MethodCall(MethodCall {
+            type_name,
+            name,
+            args,
+            pos,
+        })
+    }
+
+    pub fn new_run(event: String, name: Option<String>, body: Box<Exp>, pos: Position) -> Exp {
+        Exp::Run(Arc::new(RunDef {
+            event,
+            name,
+            body,
+            pos,
+        }))
+    }
+
+    pub fn new_async(statements: Vec<Exp>, pos: Position) -> Exp {
+        Exp::Async(Async { statements, pos })
+    }
+
+    pub fn new_data_def(
+        name: String,
+        params: Vec<String>,
+        variants: Vec<VariantDef>,
+        doc: Option<String>,
+        pos: Position,
+    ) -> Exp {
+        Exp::DataDef(Arc::new(DataDef {
+            name,
+            params,
+            variants,
+            doc,
+            pos,
+        }))
+    }
+
+    pub fn new_match(exp: Box<Exp>, arms: Vec<MatchArm>, pos: Position) -> Exp {
+        Exp::Match(Match { exp, arms, pos })
+    }
+
+    pub fn new_try(exp: Box<Exp>, pos: Position) -> Exp {
+        Exp::Try(Try { exp, pos })
+    }
+
     pub fn position(&self) -> Position {
         match self {
             Exp::Block(b) => b.pos.clone(),
@@ -100,6 +605,25 @@ impl Exp {
             Exp::Assignment(a) => a.pos.clone(),
             Exp::Variable(v) => v.pos.clone(),
             Exp::Operator(a) => a.pos.clone(),
+            Exp::Unary(u) => u.pos.clone(),
+            Exp::If(i) => i.pos.clone(),
+            Exp::While(w) => w.pos.clone(),
+            Exp::For(f) => f.pos.clone(),
+            Exp::Break(pos) => pos.clone(),
+            Exp::Continue(pos) => pos.clone(),
+            Exp::FunctionDef(f) => f.pos.clone(),
+            Exp::Return(r) => r.pos.clone(),
+            Exp::List(l) => l.pos.clone(),
+            Exp::Index(i) => i.pos.clone(),
+            Exp::RecordDef(r) => r.pos.clone(),
+            Exp::New(n) => n.pos.clone(),
+            Exp::Field(f) => f.pos.clone(),
+            Exp::MethodCall(m) => m.pos.clone(),
+            Exp::Run(r) => r.pos.clone(),
+            Exp::Async(a) => a.pos.clone(),
+            Exp::DataDef(d) => d.pos.clone(),
+            Exp::Match(m) => m.pos.clone(),
+            Exp::Try(t) => t.pos.clone(),
         }
     }
 }
\ No newline at end of file
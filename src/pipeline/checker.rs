@@ -0,0 +1,1620 @@
+use crate::core_lib::{self, OPTION, RESULT};
+use crate::error::{did_you_mean, OmgError, Position};
+use crate::pipeline::ast::*;
+use crate::pipeline::exhaustive::{self, Ctor, Pat};
+use crate::pipeline::types::{Constraint, Signature, Type};
+use crate::pipeline::{Function, Module};
+use crate::value::{RecordKind, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Checks the types of a parsed file before any of it runs. Every error found
+/// is reported, not just the first one.
+pub fn check(exp: &Exp, module: &Arc<Module>) -> Result<(), Vec<OmgError>> {
+    let mut checker = Checker::new(module);
+    checker.check(exp);
+    checker.check_deferred();
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// Checks the file like `check`, also giving the type of each variable where
+/// it is declared or used, for tools that show them.
+pub fn check_types(exp: &Exp, module: &Arc<Module>) -> (Vec<(Position, Type)>, Vec<OmgError>) {
+    let mut checker = Checker::new(module);
+    checker.types = Some(Vec::new());
+    checker.check(exp);
+    checker.check_deferred();
+    let types = checker.types.take().unwrap_or_default();
+    let types = types
+        .into_iter()
+        .map(|(pos, t)| (pos, checker.resolve(&t)))
+        .collect();
+    (types, checker.errors)
+}
+
+/// Checks code that is entered a piece at a time, like the lines of the REPL.
+/// Each piece sees the variables, functions and types declared by the pieces
+/// before it, while a piece with errors leaves no trace.
+#[derive(Clone)]
+pub struct Session {
+    checker: Checker,
+}
+
+impl Session {
+    pub fn new(module: &Arc<Module>) -> Self {
+        Session {
+            checker: Checker::new(module),
+        }
+    }
+
+    /// Checks the piece and keeps what it declares, returning the type of
+    /// its value.
+    pub fn check(&mut self, exp: &Exp) -> Result<Type, Vec<OmgError>> {
+        let mut checker = self.checker.clone();
+        let t = checker.check_piece(exp)?;
+        self.checker = checker;
+        Ok(t)
+    }
+
+    /// The type of the piece's value, without keeping what it declares.
+    pub fn type_of(&self, exp: &Exp) -> Result<Type, Vec<OmgError>> {
+        self.checker.clone().check_piece(exp)
+    }
+}
+
+/// A variable, only those declared with `var` can be assigned to again.
+#[derive(Clone)]
+struct Binding {
+    t: Type,
+    mutable: bool,
+}
+
+type Scope = HashMap<String, Binding>;
+
+#[derive(Clone)]
+struct RecordInfo {
+    kind: RecordKind,
+    fields: Vec<(String, Type)>,
+}
+
+#[derive(Clone)]
+struct DataInfo {
+    params: usize,
+    /// The names of the variants and their number of fields.
+    variants: Arc<Vec<(String, usize)>>,
+}
+
+/// The type of a user function, `vars` are the type variables that are made
+/// fresh for every call.
+#[derive(Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    params: Vec<Type>,
+    returns: Type,
+}
+
+#[derive(Clone)]
+struct Checker {
+    module: Arc<Module>,
+    /// What each type variable has been solved to so far.
+    solved: Vec<Option<Type>>,
+    functions: HashMap<String, Scheme>,
+    records: HashMap<String, RecordInfo>,
+    datas: HashMap<String, DataInfo>,
+    /// The data type and index of each variant, their constructors are in
+    /// `functions`.
+    variants: HashMap<String, (String, usize)>,
+    /// The type parameters of the data type being declared.
+    type_params: HashMap<String, Type>,
+    /// The variables of the function being checked, one scope for each block
+    /// it is in with the innermost last. Functions don't see the variables of
+    /// the code around them.
+    scopes: Vec<Scope>,
+    /// What `return` must return in the function being checked.
+    returns: Option<Type>,
+    /// Set while checking a statement whose value is thrown away, the
+    /// branches of an `if` or `match` may then have different types.
+    discarded: bool,
+    /// Types `+` was used on before they were known, they must end up as an
+    /// Int, a Float or a String.
+    additions: Vec<(Type, Position)>,
+    /// Types other arithmetic or natives were used on before they were
+    /// known, with what they must end up as and the operator or native.
+    constrained: Vec<(Type, Constraint, String, Position)>,
+    /// Types `?` was used on before they were known, with the type of the
+    /// value it gets out and what the function it is in returns.
+    tries: Vec<(Type, Type, Option<Type>, Position)>,
+    errors: Vec<OmgError>,
+    /// The types of variables where they are declared or used, only kept
+    /// when asked for.
+    types: Option<Vec<(Position, Type)>>,
+}
+
+impl Checker {
+    fn new(module: &Arc<Module>) -> Self {
+        let records = module
+            .type_names()
+            .filter_map(|name| module.get_type(name))
+            .map(|record_type| {
+                let info = RecordInfo {
+                    kind: record_type.kind,
+                    fields: record_type
+                        .fields
+                        .iter()
+                        .map(|field| (field.clone(), Type::Any))
+                        .collect(),
+                };
+                (record_type.name.clone(), info)
+            })
+            .collect();
+        let mut checker = Checker {
+            module: Arc::clone(module),
+            solved: Vec::new(),
+            functions: HashMap::new(),
+            records,
+            datas: HashMap::new(),
+            variants: HashMap::new(),
+            type_params: HashMap::new(),
+            scopes: vec![Scope::new()],
+            returns: None,
+            discarded: false,
+            additions: Vec::new(),
+            constrained: Vec::new(),
+            tries: Vec::new(),
+            errors: Vec::new(),
+            types: None,
+        };
+        let prelude = core_lib::prelude();
+        checker.declare_datas(&prelude.iter().collect::<Vec<_>>());
+        checker
+    }
+
+    /// Checks a piece of a session, the variables of its top level block are
+    /// kept for the pieces after it.
+    fn check_piece(&mut self, exp: &Exp) -> Result<Type, Vec<OmgError>> {
+        let t = match exp {
+            Exp::Block(block) => self.check_block(block),
+            exp => self.check(exp),
+        };
+        self.check_deferred();
+        if self.errors.is_empty() {
+            Ok(self.resolve(&t))
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn error<S: Into<String>>(&mut self, msg: S, pos: &Position) {
+        self.errors.push(OmgError::new(msg, pos.clone()));
+    }
+
+    fn note_type(&mut self, pos: &Position, t: &Type) {
+        if let Some(types) = &mut self.types {
+            types.push((pos.clone(), t.clone()));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn bind(&mut self, name: &str, t: Type, mutable: bool) {
+        let innermost = self.scopes.last_mut().expect("there are no scopes");
+        innermost.insert(name.to_string(), Binding { t, mutable });
+    }
+
+    /// Checks with a scope of its own, which is gone afterwards along with
+    /// the functions declared in it.
+    fn scoped<T, F>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        self.scopes.push(Scope::new());
+        let functions = self.functions.clone();
+        let result = f(self);
+        self.functions = functions;
+        self.scopes.pop();
+        result
+    }
+
+    fn missing_variable(&mut self, name: &str, to: &str, pos: &Position) {
+        let known = self.scopes.iter().flat_map(|scope| scope.keys());
+        let msg = format!(
+            "Cant find variable named {}{}{}",
+            name,
+            to,
+            did_you_mean(name, known.map(|name| name.as_str()))
+        );
+        self.error(msg, pos);
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.solved.push(None);
+        Type::Var(self.solved.len() - 1)
+    }
+
+    /// Follows solved type variables until the outermost type is known or
+    /// is an unsolved variable.
+    fn shallow(&self, t: &Type) -> Type {
+        match t {
+            Type::Var(n) => match &self.solved[*n] {
+                Some(t) => self.shallow(t),
+                None => t.clone(),
+            },
+            t => t.clone(),
+        }
+    }
+
+    /// Replaces every solved type variable in the type.
+    fn resolve(&self, t: &Type) -> Type {
+        match self.shallow(t) {
+            Type::List(item) => Type::List(Box::new(self.resolve(&item))),
+            Type::Data(name, args) => {
+                Type::Data(name, args.iter().map(|arg| self.resolve(arg)).collect())
+            }
+            t => t,
+        }
+    }
+
+    fn occurs(&self, var: usize, t: &Type) -> bool {
+        match self.shallow(t) {
+            Type::Var(n) => n == var,
+            Type::List(item) => self.occurs(var, &item),
+            Type::Data(_, args) => args.iter().any(|arg| self.occurs(var, arg)),
+            _ => false,
+        }
+    }
+
+    /// Makes the two types the same by solving type variables, returns
+    /// `false` if they can't be.
+    fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        match (self.shallow(a), self.shallow(b)) {
+            (Type::Any, _) | (_, Type::Any) | (Type::Never, _) | (_, Type::Never) => true,
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(var), t) | (t, Type::Var(var)) => {
+                if self.occurs(var, &t) {
+                    return false;
+                }
+                self.solved[var] = Some(t);
+                true
+            }
+            (Type::List(a), Type::List(b)) => self.unify(&a, &b),
+            (Type::Data(a, a_args), Type::Data(b, b_args)) => {
+                a == b
+                    && a_args.len() == b_args.len()
+                    && a_args.iter().zip(&b_args).all(|(a, b)| self.unify(a, b))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, found: &Type, pos: &Position) {
+        if !self.unify(expected, found) {
+            let msg = format!(
+                "Expected {} found {}",
+                self.resolve(expected),
+                self.resolve(found)
+            );
+            self.error(msg, pos);
+        }
+    }
+
+    /// The type of two branches of an `if` or `match`. Branches that differ
+    /// are an error at the second one, unless the value is thrown away.
+    fn join(&mut self, a: &Type, b: &Type, discarded: bool, pos: &Position) -> Type {
+        if self.shallow(a) == Type::Never {
+            return b.clone();
+        }
+        let solved = self.solved.clone();
+        if self.unify(a, b) {
+            return a.clone();
+        }
+        self.solved = solved;
+        if discarded {
+            return Type::Nothing;
+        }
+        let msg = format!(
+            "The branches have different types, {} and {}",
+            self.resolve(a),
+            self.resolve(b)
+        );
+        self.error(msg, pos);
+        // Any type will do, so the error is not reported again where the
+        // value is used.
+        self.fresh()
+    }
+
+    fn free_vars(&self, t: &Type, vars: &mut Vec<usize>) {
+        match self.shallow(t) {
+            Type::Var(n) if !vars.contains(&n) => vars.push(n),
+            Type::List(item) => self.free_vars(&item, vars),
+            Type::Data(_, args) => {
+                for arg in &args {
+                    self.free_vars(arg, vars);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> (Vec<Type>, Type) {
+        let mut fresh = HashMap::new();
+        for var in &scheme.vars {
+            fresh.insert(*var, self.fresh());
+        }
+        let params = scheme
+            .params
+            .iter()
+            .map(|t| self.substitute(t, &fresh))
+            .collect();
+        (params, self.substitute(&scheme.returns, &fresh))
+    }
+
+    fn substitute(&self, t: &Type, fresh: &HashMap<usize, Type>) -> Type {
+        match self.shallow(t) {
+            Type::Var(n) => fresh.get(&n).cloned().unwrap_or(Type::Var(n)),
+            Type::List(item) => Type::List(Box::new(self.substitute(&item, fresh))),
+            Type::Data(name, args) => Type::Data(
+                name,
+                args.iter().map(|arg| self.substitute(arg, fresh)).collect(),
+            ),
+            t => t,
+        }
+    }
+
+    fn type_error(&mut self, op: &str, lhs: &Type, rhs: Option<&Type>, pos: &Position) {
+        let lhs = self.resolve(lhs);
+        let msg = match rhs {
+            Some(rhs) => format!("Can't use {} on {} and {}", op, lhs, self.resolve(rhs)),
+            None => format!("Can't use {} on {}", op, lhs),
+        };
+        self.error(msg, pos);
+    }
+
+    /// The type written in the source, or Any if it can't be found.
+    fn type_of_name(&mut self, type_name: &TypeName) -> Type {
+        if let Some(t) = self.type_params.get(&type_name.name) {
+            return t.clone();
+        }
+        let t = match type_name.name.as_str() {
+            "Any" => Type::Any,
+            "Nothing" => Type::Nothing,
+            "Int" => Type::Int,
+            "Float" => Type::Float,
+            "Boolean" => Type::Boolean,
+            "String" => Type::String,
+            "List" => {
+                if type_name.args.len() != 1 {
+                    let msg = format!(
+                        "List takes 1 type argument but {} were given",
+                        type_name.args.len()
+                    );
+                    self.error(msg, &type_name.pos);
+                    return Type::Any;
+                }
+                let item = self.type_of_name(&type_name.args[0]);
+                return Type::List(Box::new(item));
+            }
+            name if self.records.contains_key(name) => Type::Record(name.to_string()),
+            name if self.datas.contains_key(name) => {
+                let params = self.datas[name].params;
+                if type_name.args.len() != params {
+                    let msg = format!(
+                        "{} takes {} type arguments but {} were given",
+                        name,
+                        params,
+                        type_name.args.len()
+                    );
+                    self.error(msg, &type_name.pos);
+                    return Type::Any;
+                }
+                let args = type_name
+                    .args
+                    .iter()
+                    .map(|arg| self.type_of_name(arg))
+                    .collect();
+                return Type::Data(name.to_string(), args);
+            }
+            name => {
+                let known = [
+                    "Any", "Nothing", "Int", "Float", "Boolean", "String", "List",
+                ];
+                let known = known
+                    .iter()
+                    .cloned()
+                    .chain(self.records.keys().map(|k| k.as_str()))
+                    .chain(self.datas.keys().map(|k| k.as_str()));
+                let msg = format!("Cant find type named {}{}", name, did_you_mean(name, known));
+                self.error(msg, &type_name.pos);
+                return Type::Any;
+            }
+        };
+        if !type_name.args.is_empty() {
+            let msg = format!("{} takes no type arguments", type_name.name);
+            self.error(msg, &type_name.pos);
+        }
+        t
+    }
+
+    fn check_all(&mut self, expressions: &[Exp]) -> Vec<Type> {
+        expressions.iter().map(|exp| self.check(exp)).collect()
+    }
+
+    fn check(&mut self, exp: &Exp) -> Type {
+        let discarded = std::mem::replace(&mut self.discarded, false);
+        match exp {
+            Exp::Block(block) => self.scoped(|checker| checker.check_block(block)),
+            Exp::Call(call) => self.check_call(call),
+            Exp::Literal(literal) => match literal.value {
+                Value::Nothing => Type::Nothing,
+                Value::Int(_) => Type::Int,
+                Value::Float(_) => Type::Float,
+                Value::True | Value::False => Type::Boolean,
+                Value::String(_) => Type::String,
+                _ => Type::Any,
+            },
+            Exp::Assignment(assignment) => {
+                let value = self.check(&assignment.value);
+                let name = &assignment.name;
+                let current = match assignment.declaration {
+                    Some(declaration) => {
+                        let declared = assignment
+                            .type_name
+                            .as_ref()
+                            .map(|type_name| self.type_of_name(type_name));
+                        let t = declared.unwrap_or_else(|| value.clone());
+                        self.note_type(&assignment.pos, &t);
+                        self.bind(name, t.clone(), declaration == Declaration::Var);
+                        t
+                    }
+                    None => match self.lookup(name) {
+                        Some(binding) if binding.mutable => binding.t.clone(),
+                        Some(_) => {
+                            let msg =
+                                format!("Can't assign to {} which wasn't declared with var", name);
+                            self.error(msg, &assignment.pos);
+                            return Type::Nothing;
+                        }
+                        None => {
+                            self.missing_variable(name, " to assign to", &assignment.pos);
+                            return Type::Nothing;
+                        }
+                    },
+                };
+                if !self.unify(&current, &value) {
+                    let msg = format!(
+                        "Can't assign {} to {} which is {}",
+                        self.resolve(&value),
+                        name,
+                        self.resolve(&current)
+                    );
+                    self.error(msg, &assignment.pos);
+                }
+                Type::Nothing
+            }
+            Exp::Variable(variable) => match self.lookup(&variable.name) {
+                Some(binding) => {
+                    let t = binding.t.clone();
+                    self.note_type(&variable.pos, &t);
+                    t
+                }
+                None if self.is_unit_variant(&variable.name) => {
+                    let scheme = self.functions[&variable.name].clone();
+                    self.instantiate(&scheme).1
+                }
+                None => {
+                    self.missing_variable(&variable.name, "", &variable.pos);
+                    Type::Any
+                }
+            },
+            Exp::Operator(op) => {
+                let lhs = self.check(&op.lhs);
+                let rhs = self.check(&op.rhs);
+                self.check_operator(op, lhs, rhs)
+            }
+            Exp::Unary(unary) => {
+                let t = self.check(&unary.exp);
+                let symbol = unary.unary_type.symbol();
+                match unary.unary_type {
+                    UnaryType::Negate | UnaryType::Plus => {
+                        self.number(symbol, &t, None, &unary.pos);
+                        t
+                    }
+                    UnaryType::Not => {
+                        if !self.unify(&Type::Boolean, &t) {
+                            self.type_error(symbol, &t, None, &unary.pos);
+                        }
+                        Type::Boolean
+                    }
+                }
+            }
+            Exp::If(if_exp) => {
+                self.check_condition("if", &if_exp.condition);
+                let then = self.check(&if_exp.then);
+                match &if_exp.otherwise {
+                    Some(otherwise_exp) => {
+                        let otherwise = self.check(otherwise_exp);
+                        self.join(&then, &otherwise, discarded, &otherwise_exp.position())
+                    }
+                    None => Type::Nothing,
+                }
+            }
+            Exp::While(while_exp) => {
+                self.check_condition("while", &while_exp.condition);
+                self.check(&while_exp.body);
+                Type::Nothing
+            }
+            Exp::For(for_exp) => {
+                let collection = self.check(&for_exp.collection);
+                let item = match self.shallow(&collection) {
+                    Type::String => Type::String,
+                    Type::List(item) => *item,
+                    Type::Any | Type::Never => Type::Any,
+                    Type::Var(_) => {
+                        let item = self.fresh();
+                        self.unify(&collection, &Type::List(Box::new(item.clone())));
+                        item
+                    }
+                    t => {
+                        let msg = format!("Can't iterate over {}", self.resolve(&t));
+                        self.error(msg, &for_exp.collection.position());
+                        Type::Any
+                    }
+                };
+                self.scoped(|checker| {
+                    checker.bind(&for_exp.name, item, false);
+                    checker.check(&for_exp.body);
+                });
+                Type::Nothing
+            }
+            Exp::Break(_) | Exp::Continue(_) => Type::Never,
+            Exp::FunctionDef(_) | Exp::RecordDef(_) | Exp::DataDef(_) => Type::Nothing,
+            Exp::Match(match_exp) => self.check_match(match_exp, discarded),
+            Exp::Try(try_exp) => self.check_try(try_exp),
+            Exp::Return(return_exp) => {
+                let value = match &return_exp.value {
+                    Some(value) => self.check(value),
+                    None => Type::Nothing,
+                };
+                if let Some(returns) = self.returns.clone() {
+                    self.expect(&returns, &value, &return_exp.pos);
+                }
+                Type::Never
+            }
+            Exp::List(list) => {
+                let item = self.fresh();
+                for exp in &list.items {
+                    let t = self.check(exp);
+                    self.expect(&item, &t, &exp.position());
+                }
+                Type::List(Box::new(item))
+            }
+            Exp::Index(index) => {
+                let list = self.check(&index.exp);
+                let i = self.check(&index.index);
+                self.expect(&Type::Int, &i, &index.index.position());
+                let item = self.fresh();
+                if !self.unify(&Type::List(Box::new(item.clone())), &list) {
+                    let msg = format!("Can't index into {}", self.resolve(&list));
+                    self.error(msg, &index.pos);
+                }
+                item
+            }
+            Exp::New(new) => self.check_new(new),
+            Exp::Field(field) => {
+                let t = self.check(&field.exp);
+                match self.shallow(&t) {
+                    Type::Record(name) => {
+                        let found = self.records[&name]
+                            .fields
+                            .iter()
+                            .find(|(field_name, _)| *field_name == field.name)
+                            .map(|(_, t)| t.clone());
+                        found.unwrap_or_else(|| {
+                            let msg = format!("{} has no field named {}", name, field.name);
+                            self.error(msg, &field.pos);
+                            Type::Any
+                        })
+                    }
+                    Type::Any => Type::Any,
+                    Type::Never => Type::Never,
+                    Type::Var(_) => {
+                        let msg = format!(
+                            "Can't tell the type to read field {} from, add a type annotation",
+                            field.name
+                        );
+                        self.error(msg, &field.pos);
+                        Type::Any
+                    }
+                    t => {
+                        let msg = format!("Can't read field {} from {}", field.name, t);
+                        self.error(msg, &field.pos);
+                        Type::Any
+                    }
+                }
+            }
+            Exp::MethodCall(method) => self.check_method(method),
+            Exp::Run(def) => {
+                match self.records.get(&def.event).map(|info| info.kind) {
+                    Some(RecordKind::Event) => (),
+                    Some(RecordKind::Record) => {
+                        let msg = format!("{} is a record and not an event", def.event);
+                        self.error(msg, &def.pos);
+                    }
+                    None => {
+                        let msg = format!("Cant find event named {} to run on", def.event);
+                        self.error(msg, &def.pos);
+                    }
+                }
+                let mut scope = Scope::new();
+                if let Some(name) = &def.name {
+                    let binding = Binding {
+                        t: Type::Record(def.event.clone()),
+                        mutable: false,
+                    };
+                    scope.insert(name.clone(), binding);
+                }
+                let returns = Some(self.fresh());
+                self.check_body(scope, returns, &def.body);
+                Type::Nothing
+            }
+            Exp::Async(async_exp) => {
+                self.scoped(|checker| {
+                    checker.declare(&async_exp.statements);
+                    // Every statement runs as a task of its own, so what one
+                    // declares is not seen by the others or after the block.
+                    for statement in &async_exp.statements {
+                        checker.scoped(|checker| {
+                            checker.discarded = true;
+                            checker.check(statement)
+                        });
+                    }
+                });
+                Type::Nothing
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &Block) -> Type {
+        self.declare(block.statements.iter().chain(block.value.as_deref()));
+        let mut diverges = false;
+        for statement in &block.statements {
+            self.discarded = true;
+            if self.check(statement) == Type::Never {
+                diverges = true;
+            }
+        }
+        let value = match &block.value {
+            Some(value) => self.check(value),
+            None => Type::Nothing,
+        };
+        if diverges {
+            Type::Never
+        } else {
+            value
+        }
+    }
+
+    /// Checks the body of a function or handler with its own variables.
+    fn check_body(&mut self, scope: Scope, returns: Option<Type>, body: &Exp) -> Type {
+        let scopes = std::mem::replace(&mut self.scopes, vec![scope]);
+        let returns = std::mem::replace(&mut self.returns, returns);
+        let t = self.check(body);
+        self.scopes = scopes;
+        self.returns = returns;
+        t
+    }
+
+    /// Declares the types and functions of a block up front, as they can be
+    /// used before they are declared. The functions may call each other, so
+    /// they are all checked before any of them is made generic.
+    fn declare<'b, I>(&mut self, statements: I)
+    where
+        I: IntoIterator<Item = &'b Exp>,
+    {
+        let mut records = Vec::new();
+        let mut datas = Vec::new();
+        let mut functions = Vec::new();
+        for statement in statements {
+            match statement {
+                Exp::RecordDef(def) => records.push(def),
+                Exp::DataDef(def) => datas.push(def),
+                Exp::FunctionDef(def) => functions.push(def),
+                _ => (),
+            }
+        }
+
+        for def in &records {
+            let info = RecordInfo {
+                kind: def.kind,
+                fields: Vec::new(),
+            };
+            self.records.insert(def.name.clone(), info);
+        }
+        self.declare_datas(&datas);
+        for def in &records {
+            let fields = def
+                .fields
+                .iter()
+                .map(|field| {
+                    let t = match &field.type_name {
+                        Some(type_name) => self.type_of_name(type_name),
+                        None => self.fresh(),
+                    };
+                    (field.name.clone(), t)
+                })
+                .collect();
+            self.records.get_mut(&def.name).unwrap().fields = fields;
+        }
+
+        for def in &functions {
+            let params = def
+                .params
+                .iter()
+                .map(|param| match &param.type_name {
+                    Some(type_name) => self.type_of_name(type_name),
+                    None => self.fresh(),
+                })
+                .collect();
+            let scheme = Scheme {
+                vars: Vec::new(),
+                params,
+                returns: self.fresh(),
+            };
+            self.functions.insert(def.name.clone(), scheme);
+        }
+        for def in &functions {
+            let scheme = self.functions[&def.name].clone();
+            let scope = def
+                .params
+                .iter()
+                .zip(scheme.params)
+                .map(|(param, t)| (param.name.clone(), Binding { t, mutable: false }))
+                .collect();
+            let t = self.check_body(scope, Some(scheme.returns.clone()), &def.body);
+            self.expect(&scheme.returns, &t, &def.pos);
+        }
+
+        let names: HashSet<_> = functions.iter().map(|def| def.name.as_str()).collect();
+        let mut fixed = Vec::new();
+        for (name, scheme) in &self.functions {
+            if !names.contains(name.as_str()) {
+                let mut vars = Vec::new();
+                for t in scheme.params.iter().chain(Some(&scheme.returns)) {
+                    self.free_vars(t, &mut vars);
+                }
+                fixed.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+            }
+        }
+        for info in self.records.values() {
+            for (_, t) in &info.fields {
+                self.free_vars(t, &mut fixed);
+            }
+        }
+        for binding in self.scopes.iter().flat_map(|scope| scope.values()) {
+            self.free_vars(&binding.t, &mut fixed);
+        }
+        // What `?` is used on is found out from the calls, which must then
+        // share its type.
+        for (t, value, returns, _) in &self.tries {
+            for t in [t, value].iter().copied().chain(returns) {
+                self.free_vars(t, &mut fixed);
+            }
+        }
+        for name in names {
+            let scheme = &self.functions[name];
+            let mut vars = Vec::new();
+            for t in scheme.params.iter().chain(Some(&scheme.returns)) {
+                self.free_vars(t, &mut vars);
+            }
+            vars.retain(|var| !fixed.contains(var));
+            self.functions.get_mut(name).unwrap().vars = vars;
+        }
+    }
+
+    /// Declares data types and their variants, which may refer to each other.
+    fn declare_datas(&mut self, datas: &[&Arc<DataDef>]) {
+        for def in datas {
+            let variants = def
+                .variants
+                .iter()
+                .map(|variant| (variant.name.clone(), variant.fields.len()))
+                .collect();
+            let info = DataInfo {
+                params: def.params.len(),
+                variants: Arc::new(variants),
+            };
+            self.datas.insert(def.name.clone(), info);
+            for (index, variant) in def.variants.iter().enumerate() {
+                self.variants
+                    .insert(variant.name.clone(), (def.name.clone(), index));
+            }
+        }
+        for def in datas {
+            self.declare_constructors(def);
+        }
+    }
+
+    /// Each variant is a function that creates it, generic over the type
+    /// parameters of the data type.
+    fn declare_constructors(&mut self, def: &DataDef) {
+        let params: Vec<_> = def.params.iter().map(|_| self.fresh()).collect();
+        self.type_params = def.params.iter().cloned().zip(params.clone()).collect();
+        let vars = params
+            .iter()
+            .filter_map(|t| match t {
+                Type::Var(n) => Some(*n),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for variant in &def.variants {
+            let fields = variant
+                .fields
+                .iter()
+                .map(|field| match &field.type_name {
+                    Some(type_name) => self.type_of_name(type_name),
+                    None => self.fresh(),
+                })
+                .collect();
+            let scheme = Scheme {
+                vars: vars.clone(),
+                params: fields,
+                returns: Type::Data(def.name.clone(), params.clone()),
+            };
+            self.functions.insert(variant.name.clone(), scheme);
+        }
+        self.type_params.clear();
+    }
+
+    /// If the name is a variant without fields, which is used without `()`.
+    fn is_unit_variant(&self, name: &str) -> bool {
+        self.variants.contains_key(name) && self.functions[name].params.is_empty()
+    }
+
+    fn check_match(&mut self, match_exp: &Match, discarded: bool) -> Type {
+        let t = self.check(&match_exp.exp);
+        let mut value = Type::Never;
+        let mut rows = Vec::new();
+        for arm in &match_exp.arms {
+            let arm_value = self.scoped(|checker| {
+                checker.check_pattern(&arm.pattern, &t);
+                match &arm.guard {
+                    Some(guard) => checker.check_condition("guard", guard),
+                    None => rows.push(checker.pat(&arm.pattern)),
+                }
+                checker.check(&arm.value)
+            });
+            value = self.join(&value, &arm_value, discarded, &arm.value.position());
+        }
+        if let Some(missing) = exhaustive::missing(&rows) {
+            let msg = format!("match doesn't handle {}", missing);
+            self.error(msg, &match_exp.pos);
+        }
+        value
+    }
+
+    /// Checks that the pattern can match values of type `t`, and declares
+    /// the variables it binds.
+    fn check_pattern(&mut self, pattern: &Pattern, t: &Type) {
+        match pattern {
+            Pattern::Wildcard(_) => (),
+            Pattern::Name(name, pos) if self.is_unit_variant(name) => {
+                let scheme = self.functions[name].clone();
+                let (_, returns) = self.instantiate(&scheme);
+                self.expect(t, &returns, pos);
+            }
+            Pattern::Name(name, pos) => {
+                self.note_type(pos, t);
+                self.bind(name, t.clone(), false)
+            }
+            Pattern::Literal(value, pos) => {
+                let literal = match value {
+                    Value::Int(_) => Type::Int,
+                    Value::Float(_) => Type::Float,
+                    Value::True | Value::False => Type::Boolean,
+                    Value::String(_) => Type::String,
+                    _ => Type::Any,
+                };
+                self.expect(t, &literal, pos);
+            }
+            Pattern::Variant { name, args, pos } => {
+                if !self.variants.contains_key(name) {
+                    let known = self.variants.keys().map(|name| name.as_str());
+                    let msg = format!(
+                        "Cant find variant named {}{}",
+                        name,
+                        did_you_mean(name, known)
+                    );
+                    self.error(msg, pos);
+                    return;
+                }
+                let scheme = self.functions[name].clone();
+                let (params, returns) = self.instantiate(&scheme);
+                self.expect(t, &returns, pos);
+                if params.len() != args.len() {
+                    let msg = format!(
+                        "{} has {} fields but {} patterns were given",
+                        name,
+                        params.len(),
+                        args.len()
+                    );
+                    self.error(msg, pos);
+                    return;
+                }
+                for (arg, param) in args.iter().zip(&params) {
+                    self.check_pattern(arg, param);
+                }
+            }
+            Pattern::Record { name, fields, pos } => {
+                let record_fields = match self.records.get(name) {
+                    Some(info) => info.fields.clone(),
+                    None => {
+                        let msg = format!("Cant find type named {}", name);
+                        self.error(msg, pos);
+                        return;
+                    }
+                };
+                self.expect(t, &Type::Record(name.clone()), pos);
+                for (field, pattern) in fields {
+                    match record_fields.iter().find(|(f, _)| f == field) {
+                        Some((_, field_type)) => self.check_pattern(pattern, field_type),
+                        None => {
+                            let msg = format!("{} has no field named {}", name, field);
+                            self.error(msg, &pattern.position());
+                        }
+                    }
+                }
+            }
+            Pattern::List { items, rest, pos } => {
+                let item = self.fresh();
+                let list = Type::List(Box::new(item.clone()));
+                self.expect(t, &list, pos);
+                for pattern in items {
+                    self.check_pattern(pattern, &item);
+                }
+                if let Some(rest) = rest {
+                    self.check_pattern(rest, &list);
+                }
+            }
+        }
+    }
+
+    /// The pattern as the exhaustiveness check sees it.
+    fn pat(&self, pattern: &Pattern) -> Pat {
+        match pattern {
+            Pattern::Wildcard(_) => Pat::Wild,
+            Pattern::Name(name, _) if self.is_unit_variant(name) => self.variant_pat(name, &[]),
+            Pattern::Name(_, _) => Pat::Wild,
+            Pattern::Literal(Value::True, _) => Pat::Ctor(Ctor::Boolean(true), Vec::new()),
+            Pattern::Literal(Value::False, _) => Pat::Ctor(Ctor::Boolean(false), Vec::new()),
+            Pattern::Literal(Value::String(s), _) => {
+                Pat::Ctor(Ctor::Literal(format!("{:?}", s)), Vec::new())
+            }
+            Pattern::Literal(value, _) => Pat::Ctor(Ctor::Literal(value.to_string()), Vec::new()),
+            Pattern::Variant { name, args, .. } => self.variant_pat(name, args),
+            Pattern::Record { name, fields, .. } => match self.records.get(name) {
+                Some(info) => {
+                    let names: Vec<String> =
+                        info.fields.iter().map(|(field, _)| field.clone()).collect();
+                    let args = names
+                        .iter()
+                        .map(|field| {
+                            fields
+                                .iter()
+                                .find(|(f, _)| f == field)
+                                .map_or(Pat::Wild, |(_, pattern)| self.pat(pattern))
+                        })
+                        .collect();
+                    let ctor = Ctor::Record {
+                        name: name.clone(),
+                        fields: names,
+                    };
+                    Pat::Ctor(ctor, args)
+                }
+                None => Pat::Wild,
+            },
+            Pattern::List { items, rest, .. } => {
+                let tail = match rest {
+                    Some(rest) => self.pat(rest),
+                    None => Pat::Ctor(Ctor::Nil, Vec::new()),
+                };
+                items.iter().rev().fold(tail, |tail, item| {
+                    Pat::Ctor(Ctor::Cons, vec![self.pat(item), tail])
+                })
+            }
+        }
+    }
+
+    fn variant_pat(&self, name: &str, args: &[Pattern]) -> Pat {
+        let (data, index) = match self.variants.get(name) {
+            Some((data, index)) => (data, *index),
+            None => return Pat::Wild,
+        };
+        let variants = Arc::clone(&self.datas[data].variants);
+        if variants[index].1 != args.len() {
+            return Pat::Wild;
+        }
+        let args = args.iter().map(|arg| self.pat(arg)).collect();
+        Pat::Ctor(Ctor::Variant { index, variants }, args)
+    }
+
+    /// `?` gives the value in an `Ok` or `Some`, the function must return the
+    /// same kind of data type for the rest.
+    fn check_try(&mut self, try_exp: &Try) -> Type {
+        let t = self.check(&try_exp.exp);
+        let returns = self.returns.clone();
+        if let Type::Var(_) = self.shallow(&t) {
+            // The code after it may still tell, it is checked at the end.
+            let value = self.fresh();
+            let pos = try_exp.pos.clone();
+            self.tries.push((t, value.clone(), returns, pos));
+            return value;
+        }
+        self.unwrap_try(&t, returns, &try_exp.pos)
+    }
+
+    /// The type of what `?` gets out of a Result or an Option, the function
+    /// it is in must return the same kind.
+    fn unwrap_try(&mut self, t: &Type, returns: Option<Type>, pos: &Position) -> Type {
+        let (early, value) = match self.shallow(t) {
+            Type::Data(name, args) if name == RESULT => {
+                let early = Type::Data(name, vec![self.fresh(), args[1].clone()]);
+                (early, args[0].clone())
+            }
+            Type::Data(name, args) if name == OPTION => {
+                (Type::Data(name, vec![self.fresh()]), args[0].clone())
+            }
+            Type::Any => return Type::Any,
+            Type::Var(_) => {
+                let msg = "Can't tell if ? is used on a Result or an Option, add a type annotation";
+                self.error(msg, pos);
+                return Type::Any;
+            }
+            t => {
+                self.error(format!("Can't use ? on {}", self.resolve(&t)), pos);
+                return Type::Any;
+            }
+        };
+        if let Some(returns) = returns {
+            self.expect(&returns, &early, pos);
+        }
+        value
+    }
+
+    fn check_condition(&mut self, name: &str, condition: &Exp) {
+        let t = self.check(condition);
+        if !self.unify(&Type::Boolean, &t) {
+            let msg = format!(
+                "Expected {} condition to be a boolean found {}",
+                name,
+                self.resolve(&t)
+            );
+            self.error(msg, &condition.position());
+        }
+    }
+
+    fn check_operator(&mut self, op: &Operator, lhs: Type, rhs: Type) -> Type {
+        let symbol = op.op_type.symbol();
+        match op.op_type {
+            OpType::Add => {
+                if !self.unify(&lhs, &rhs) {
+                    self.type_error(symbol, &lhs, Some(&rhs), &op.pos);
+                    return Type::Any;
+                }
+                match self.shallow(&lhs) {
+                    Type::Var(_) => {
+                        self.additions.push((lhs.clone(), op.pos.clone()));
+                        lhs
+                    }
+                    Type::Int | Type::Float | Type::String | Type::Any | Type::Never => lhs,
+                    _ => {
+                        self.type_error(symbol, &lhs, Some(&rhs), &op.pos);
+                        Type::Any
+                    }
+                }
+            }
+            OpType::Subtract
+            | OpType::Multiply
+            | OpType::Divide
+            | OpType::Remainder
+            | OpType::Power => self.number_operands(symbol, &lhs, &rhs, &op.pos),
+            OpType::GreaterThan | OpType::GreaterEqual | OpType::LessThan | OpType::LessEqual => {
+                self.number_operands(symbol, &lhs, &rhs, &op.pos);
+                Type::Boolean
+            }
+            OpType::And | OpType::Or => {
+                let boolean = Type::Boolean;
+                if !(self.unify(&boolean, &lhs) && self.unify(&boolean, &rhs)) {
+                    self.type_error(symbol, &lhs, Some(&rhs), &op.pos);
+                }
+                Type::Boolean
+            }
+            OpType::Equal | OpType::NotEqual => {
+                if !self.unify(&lhs, &rhs) {
+                    self.type_error(symbol, &lhs, Some(&rhs), &op.pos);
+                }
+                Type::Boolean
+            }
+        }
+    }
+
+    /// Both operands must be Ints or both Floats, gives the type they have.
+    fn number_operands(
+        &mut self,
+        symbol: &'static str,
+        lhs: &Type,
+        rhs: &Type,
+        pos: &Position,
+    ) -> Type {
+        if !self.unify(lhs, rhs) {
+            self.type_error(symbol, lhs, Some(rhs), pos);
+            return Type::Any;
+        }
+        self.number(symbol, lhs, Some(rhs), pos);
+        lhs.clone()
+    }
+
+    fn number(&mut self, symbol: &str, t: &Type, rhs: Option<&Type>, pos: &Position) {
+        self.constrain(Constraint::Number, symbol, t, rhs, pos);
+    }
+
+    fn constrain(
+        &mut self,
+        constraint: Constraint,
+        op: &str,
+        t: &Type,
+        rhs: Option<&Type>,
+        pos: &Position,
+    ) {
+        match self.shallow(t) {
+            Type::Var(_) => {
+                let entry = (t.clone(), constraint, op.to_string(), pos.clone());
+                self.constrained.push(entry);
+            }
+            t if constraint.allows(&t) => (),
+            _ => self.type_error(op, t, rhs, pos),
+        }
+    }
+
+    /// Arithmetic, natives and `?` used on types that were not known at the
+    /// time must have been used on the right types by the end.
+    fn check_deferred(&mut self) {
+        for (t, value, returns, pos) in std::mem::take(&mut self.tries) {
+            let unwrapped = self.unwrap_try(&t, returns, &pos);
+            self.expect(&unwrapped, &value, &pos);
+        }
+        for (t, pos) in std::mem::take(&mut self.additions) {
+            match self.resolve(&t) {
+                Type::Int | Type::Float | Type::String | Type::Any | Type::Never | Type::Var(_) => {
+                }
+                t => self.type_error("+", &t, Some(&t), &pos),
+            }
+        }
+        for (t, constraint, op, pos) in std::mem::take(&mut self.constrained) {
+            match self.resolve(&t) {
+                Type::Var(_) => (),
+                t if constraint.allows(&t) => (),
+                t => self.type_error(&op, &t, None, &pos),
+            }
+        }
+    }
+
+    fn check_call(&mut self, call: &Call) -> Type {
+        let args = self.check_all(&call.args);
+        let signature = match self.functions.get(&call.name).cloned() {
+            Some(scheme) => {
+                let (params, returns) = self.instantiate(&scheme);
+                Signature {
+                    params,
+                    rest: None,
+                    returns,
+                }
+            }
+            None => match self.module.get_function(&call.name) {
+                Some(Function::NativeFunction(native)) => native.signature(),
+                Some(Function::UserFunction(function)) => Signature {
+                    params: vec![Type::Any; function.def.params.len()],
+                    rest: None,
+                    returns: Type::Any,
+                },
+                Some(Function::Constructor(data_type, index)) => Signature {
+                    params: vec![Type::Any; data_type.variants[index].fields.len()],
+                    rest: None,
+                    returns: Type::Any,
+                },
+                None => {
+                    let known = self
+                        .functions
+                        .keys()
+                        .map(|name| name.as_str())
+                        .chain(self.module.function_names());
+                    let msg = format!(
+                        "Cant find function named {} to call{}",
+                        call.name,
+                        did_you_mean(&call.name, known)
+                    );
+                    self.error(msg, &call.pos);
+                    return Type::Any;
+                }
+            },
+        };
+        let arity_ok = match signature.rest {
+            Some(_) => args.len() >= signature.params.len(),
+            None => args.len() == signature.params.len(),
+        };
+        if !arity_ok {
+            let msg = format!(
+                "Function {} takes {} arguments but {} were given",
+                call.name,
+                signature.params.len(),
+                args.len()
+            );
+            self.error(msg, &call.pos);
+            return signature.returns;
+        }
+        let params = signature.params.iter().chain(signature.rest.iter().cycle());
+        for ((param, arg), exp) in params.zip(&args).zip(&call.args) {
+            self.expect(param, arg, &exp.position());
+        }
+        let native = match self.module.get_function(&call.name) {
+            Some(Function::NativeFunction(native)) if !self.functions.contains_key(&call.name) => {
+                native.constraint()
+            }
+            _ => None,
+        };
+        if let (Some(constraint), Some(arg), Some(exp)) = (native, args.first(), call.args.first())
+        {
+            self.constrain(constraint, &call.name, arg, None, &exp.position());
+        }
+        signature.returns
+    }
+
+    fn check_new(&mut self, new: &New) -> Type {
+        let args = self.check_all(&new.args);
+        let fields = match self.records.get(&new.name) {
+            Some(info) => info.fields.clone(),
+            None => {
+                let known = self.records.keys().map(|name| name.as_str());
+                let msg = format!(
+                    "Cant find type named {} to create{}",
+                    new.name,
+                    did_you_mean(&new.name, known)
+                );
+                self.error(msg, &new.pos);
+                return Type::Any;
+            }
+        };
+        if fields.len() != args.len() {
+            let msg = format!(
+                "{} has {} fields but {} values were given",
+                new.name,
+                fields.len(),
+                args.len()
+            );
+            self.error(msg, &new.pos);
+        } else {
+            for (((_, field), arg), exp) in fields.iter().zip(&args).zip(&new.args) {
+                self.expect(field, arg, &exp.position());
+            }
+        }
+        Type::Record(new.name.clone())
+    }
+
+    fn check_method(&mut self, method: &MethodCall) -> Type {
+        let args = self.check_all(&method.args);
+        let kind = match self.records.get(&method.type_name) {
+            Some(info) => info.kind,
+            None => {
+                let msg = format!(
+                    "Cant find type named {} to call {} on",
+                    method.type_name, method.name
+                );
+                self.error(msg, &method.pos);
+                return Type::Any;
+            }
+        };
+        let event = Type::Record(method.type_name.clone());
+        let (params, returns) = match (kind, method.name.as_str()) {
+            (RecordKind::Event, "emit") => (vec![event], Type::Nothing),
+            (RecordKind::Event, "next") => (Vec::new(), event),
+            _ => {
+                let msg = format!("{} has no method named {}", method.type_name, method.name);
+                self.error(msg, &method.pos);
+                return Type::Any;
+            }
+        };
+        if params.len() != args.len() {
+            let msg = format!(
+                "Method {}.{} takes {} arguments but {} were given",
+                method.type_name,
+                method.name,
+                params.len(),
+                args.len()
+            );
+            self.error(msg, &method.pos);
+        } else {
+            for ((param, arg), exp) in params.iter().zip(&args).zip(&method.args) {
+                self.expect(param, arg, &exp.position());
+            }
+        }
+        returns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core_lib::add_std_lib;
+    use crate::pipeline::{lexer, parse_block, Source};
+
+    fn check_source(source: &str) -> Result<(), Vec<OmgError>> {
+        let mut tokens = lexer(Source {
+            path: "test".to_string(),
+            source: source.to_string(),
+        })
+        .unwrap();
+        let exp = parse_block(&mut tokens).unwrap();
+        check(&exp, &Arc::new(add_std_lib(&Module::new())))
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        check_source(source)
+            .err()
+            .unwrap()
+            .into_iter()
+            .map(|e| format!("{}: {}", e.pos, e.msg))
+            .collect()
+    }
+
+    #[test]
+    fn well_typed() {
+        let source = "
+            record Point { Int x; y; }
+            event Hello { String hello; }
+            fn id(a) { a }
+            fn sum(List<Int> xs) {
+                var total = 0;
+                for x in xs { total = total + x; }
+                total
+            }
+            fn fact(n) { if n < 2 { return 1; }; n * fact(n - 1) }
+            let p = new Point(1, id(2));
+            print(p.x + p.y, sum([1, 2]), fact(3), id(\"a\"), len(\"abc\"));
+            let String s = \"\" + id(\"b\");
+            run (Main) { Hello.emit(new Hello(\"world\")); }
+            run (Hello e) { print(e.hello); }
+        ";
+        check_source(source).unwrap();
+    }
+
+    #[test]
+    fn all_errors_are_reported() {
+        let source = "var a = 1;\na = \"b\";\nprint(true + 1);\nif 1 { }";
+        assert_eq!(
+            messages(source),
+            vec![
+                "test:1:0: Can't assign String to a which is Int",
+                "test:2:11: Can't use + on Boolean and Int",
+                "test:3:3: Expected if condition to be a boolean found Int",
+            ]
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(
+            messages("let Int n = \"one\";\nfn f(String s) { s }\nf(1);"),
+            vec![
+                "test:0:8: Can't assign String to n which is Int",
+                "test:2:2: Expected String found Int",
+            ]
+        );
+        assert_eq!(
+            messages("let Flot n = 1.5;"),
+            vec!["test:0:4: Cant find type named Flot, did you mean Float?"]
+        );
+    }
+
+    #[test]
+    fn inferred_function_types() {
+        assert_eq!(
+            messages("fn double(n) { n * 2 }\nlet x = double(2) + \"a\";"),
+            vec!["test:1:18: Can't use + on Int and String"]
+        );
+        check_source("fn pair(a, b) { [a, b] }\npair(1, 2);\npair(\"a\", \"b\");").unwrap();
+        messages("fn pair(a, b) { [a, b] }\npair(1, \"b\");");
+    }
+
+    #[test]
+    fn records_and_events() {
+        assert_eq!(
+            messages("record R { Int a; }\nlet r = new R(\"a\");\nr.b;\nR.emit(r);\nrun (R) { }"),
+            vec![
+                "test:1:14: Expected Int found String",
+                "test:2:2: R has no field named b",
+                "test:3:2: R has no method named emit",
+                "test:4:0: R is a record and not an event",
+            ]
+        );
+        assert_eq!(
+            messages("fn f(r) { r.a }"),
+            vec!["test:0:12: Can't tell the type to read field a from, add a type annotation"]
+        );
+    }
+
+    #[test]
+    fn exhaustive_matches() {
+        let shapes = "type Shape = Circle(r) | Rect(w, h) | Dot;\n";
+        check_source(&format!(
+            "{}fn f(s) {{ match s {{ Circle(r) => r, Rect(w, h) => w * h, Dot => 0 }} }}",
+            shapes
+        ))
+        .unwrap();
+        assert_eq!(
+            messages(&format!(
+                "{}match Dot {{ Circle(r) => 1, Rect(w, h) if w > h => 2, Dot => 0 }};",
+                shapes
+            )),
+            vec!["test:1:0: match doesn't handle Rect(_, _)"]
+        );
+        assert_eq!(
+            messages("match [1] { [] => 0, [a] => a };\nmatch true { false => 0 };"),
+            vec![
+                "test:0:0: match doesn't handle [_, _, ..]",
+                "test:1:0: match doesn't handle true",
+            ]
+        );
+        check_source("let x = match 1 { 1 => \"one\", n => \"many\" } + \"!\";").unwrap();
+    }
+
+    #[test]
+    fn branches_must_agree() {
+        assert_eq!(
+            messages("let c = true;\nlet x = if c { 1 } else { \"a\" };\nprint(x);"),
+            vec!["test:1:24: The branches have different types, Int and String"]
+        );
+        assert_eq!(
+            messages("let y = match 1 { 1 => 1.5, _ => 2 };"),
+            vec!["test:0:33: The branches have different types, Float and Int"]
+        );
+        // The value of a statement is thrown away, so its branches may differ.
+        check_source("var n = 0;\nif true { n = 1; } else { 2 };\nmatch n { 0 => 1, _ => \"a\" };")
+            .unwrap();
+    }
+
+    #[test]
+    fn pattern_types() {
+        let source = "type Maybe<T> = Just(T value) | Empty;
+            let Maybe<Int> m = Just(\"a\");
+            match Just(1) { Just(\"a\") => 0, Just(a, b) => 1, Nope(x) => 2, _ => 3 };
+            match Just(1) { Just(n) => n + 1, Empty => \"a\" } + 1;";
+        assert_eq!(
+            messages(source),
+            vec![
+                "test:1:27: Can't assign Maybe<String> to m which is Maybe<Int>",
+                "test:2:33: Expected Int found String",
+                "test:2:44: Just has 1 fields but 2 patterns were given",
+                "test:2:61: Cant find variant named Nope, did you mean None?",
+                "test:3:55: The branches have different types, Int and String",
+            ]
+        );
+    }
+
+    #[test]
+    fn natives() {
+        assert_eq!(
+            messages("len(1, 2);\nprnt(1);"),
+            vec![
+                "test:0:0: Function len takes 1 arguments but 2 were given",
+                "test:1:0: Cant find function named prnt to call, did you mean print?",
+            ]
+        );
+        check_source("let a = len([1]) + len(\"ab\");\nlet b = int(1.5) + int(2);\nfloat(a);")
+            .unwrap();
+        assert_eq!(
+            messages("len(1) + 1;\nint(\"1\");\nlet x = float([]);"),
+            vec![
+                "test:0:4: Can't use len on Int",
+                "test:1:4: Can't use int on String",
+                "test:2:14: Can't use float on List<?>",
+            ]
+        );
+    }
+
+    #[test]
+    fn ints_and_floats() {
+        check_source("let a = 7 / 2 % 3 ** 2;\nlet b = -1.5 * 2.0;\nlet c = float(a) + b;")
+            .unwrap();
+        assert_eq!(
+            messages("let a = 1 + 1.0;\nfn half(n) { n / 2 }\nhalf(1.0);\nlet b = [1][0.0];"),
+            vec![
+                "test:0:10: Can't use + on Int and Float",
+                "test:2:5: Expected Int found Float",
+                "test:3:12: Expected Int found Float",
+            ]
+        );
+        assert_eq!(
+            messages("record R { x; y; }\nfn f(R r) { r.x - r.y }\nlet r = new R(\"a\", \"b\");"),
+            vec!["test:1:16: Can't use - on String"]
+        );
+    }
+
+    #[test]
+    fn boolean_logic() {
+        check_source("let a = !(1 >= 2) && 1 != 2 || 1 <= 2;").unwrap();
+        assert_eq!(
+            messages("let a = 1 && true;\nlet b = !1;"),
+            vec![
+                "test:0:10: Can't use && on Int and Boolean",
+                "test:1:8: Can't use ! on Int",
+            ]
+        );
+    }
+
+    #[test]
+    fn results_and_options() {
+        let source = "
+            fn size(path) { Ok(len(read_file(path)?)) }
+            fn first(List<Int> xs) { match xs { [] => None, [x, ..] => Some(x) } }
+            fn double(xs) { Some(first(xs)? * 2) }
+            let n = match size(\"a\") { Ok(n) => n, Err(e) => 0 };
+            fn inc(x) { Some(x? + 1) }
+            let m = inc(Some(1));
+            fn same(x) { let v = x?; match x { Some(_) => Some(v), None => None } }
+        ";
+        check_source(source).unwrap();
+        assert_eq!(
+            messages("fn f() { 1? }\nfn g(x) { x? }\nfn h() { parse_number(\"1\")? }"),
+            vec![
+                "test:0:10: Can't use ? on Int",
+                "test:2:0: Expected Option<?> found Float",
+                "test:1:11: Can't tell if ? is used on a Result or an Option, add a type annotation",
+            ]
+        );
+        assert_eq!(
+            messages("fn inc(x) { Some(x? + 1) }\nlet a = inc(Ok(1));"),
+            vec!["test:0:18: Expected Option<Int> found Result<?, ?>"]
+        );
+    }
+
+    #[test]
+    fn scopes_and_immutability() {
+        let source = "
+            var total = 0;
+            let limit = 3;
+            for i in [1, 2] { let limit = i; total = total + limit; }
+            if true { let String limit = \"shadowed\"; }
+            match 1 { n => total = n };
+            limit + total;
+        ";
+        check_source(source).unwrap();
+        assert_eq!(
+            messages("let a = 1;\na = 2;\nb = 3;\nfor x in [1] { x = 2; }\nfn f(n) { n = 1 }"),
+            vec![
+                "test:4:10: Can't assign to n which wasn't declared with var",
+                "test:1:0: Can't assign to a which wasn't declared with var",
+                "test:2:0: Cant find variable named b to assign to, did you mean a?",
+                "test:3:15: Can't assign to x which wasn't declared with var",
+            ]
+        );
+        assert_eq!(
+            messages("var a = 1;\nif true { let b = 2; }\nb;\nfn f() { a = 2 }"),
+            vec![
+                "test:3:9: Cant find variable named a to assign to",
+                "test:2:0: Cant find variable named b, did you mean a?",
+            ]
+        );
+        assert_eq!(
+            messages("var a = 0;\nasync { let x = 1; a = 2; }\nprint(x);"),
+            vec!["test:2:6: Cant find variable named x, did you mean a?"]
+        );
+        assert_eq!(
+            messages("if true { fn inner() { 1 } inner(); }\nprint(inner());"),
+            vec!["test:1:6: Cant find function named inner to call"]
+        );
+    }
+}
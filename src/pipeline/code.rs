@@ -0,0 +1,115 @@
+use crate::error::Position;
+use crate::pipeline::ast::{FunctionDef, OpType, Pattern, RunDef, UnaryType};
+use crate::value::{DataType, RecordType, Value};
+use std::sync::Arc;
+
+/// One step for the runtime. Every expression compiles to instructions that
+/// leave exactly one value on the stack.
+#[derive(Debug)]
+pub enum Instruction {
+    Push(Value),
+    Pop,
+    Load(String, Position),
+    /// Pops the value and declares a variable with it in the innermost scope.
+    Declare(String),
+    /// Pops the value and assigns it to the innermost variable with the name.
+    Store(String, Position),
+    /// Starts the scope of a block, its variables are gone when it ends.
+    PushScope,
+    PopScope,
+    Operator(OpType, Position),
+    /// Checks that the left hand side of `&&` or `||` on top of the stack is
+    /// a boolean, and jumps to `target` leaving it as the result when it
+    /// decides the result on its own.
+    ShortCircuit {
+        op_type: OpType,
+        target: usize,
+        pos: Position,
+    },
+    Unary(UnaryType, Position),
+    /// Pops the arguments and pushes the result of the function.
+    Call {
+        name: String,
+        args: usize,
+        pos: Position,
+    },
+    /// Pops the arguments and pushes the result of the method on the type.
+    Method {
+        type_name: String,
+        name: String,
+        args: usize,
+        pos: Position,
+    },
+    List(usize),
+    /// Pops the index and then the list. `pos` is where the index starts.
+    Index {
+        pos: Position,
+        index_pos: Position,
+    },
+    New {
+        name: String,
+        args: usize,
+        pos: Position,
+    },
+    Field(String, Position),
+    Jump(usize),
+    /// Pops the condition and jumps to `target` when it is `false`.
+    JumpUnless {
+        target: usize,
+        name: &'static str,
+        pos: Position,
+    },
+    /// Starts a loop, `break` jumps to `exit` and `continue` to `next`.
+    Loop {
+        exit: usize,
+        next: usize,
+    },
+    EndLoop,
+    Break,
+    Continue,
+    /// Pops the collection the innermost loop iterates over.
+    Iterate(Position),
+    /// Stores the next item of the innermost loop, or jumps to `exit` when
+    /// there are no more items.
+    NextItem {
+        name: String,
+        exit: usize,
+    },
+    /// Pops the value and returns it from the current function.
+    Return,
+    /// Runs each code at the same time, and continues when all are done.
+    Async(Vec<Arc<Code>>, Position),
+    /// Binds the variables of the pattern if the value on top of the stack
+    /// matches it, or jumps to `fail` if it doesn't. The value is left on the
+    /// stack.
+    Match {
+        pattern: Arc<Pattern>,
+        fail: usize,
+    },
+    /// Pops a Result or Option, pushes the value of an `Ok` or `Some` and
+    /// returns anything else from the current function.
+    Try(Position),
+    /// Pops the value that no arm of a `match` matched.
+    NoMatch(Position),
+    DeclareFunction(Arc<UserFunction>),
+    DeclareType(Arc<RecordType>),
+    DeclareData(Arc<DataType>),
+}
+
+#[derive(Debug, Default)]
+pub struct Code {
+    pub instructions: Vec<Instruction>,
+}
+
+#[derive(Debug)]
+pub struct UserFunction {
+    pub def: Arc<FunctionDef>,
+    pub code: Arc<Code>,
+}
+
+/// A `run (Event) { }` handler with its compiled body.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    pub def: Arc<RunDef>,
+    pub code: Arc<Code>,
+}
@@ -0,0 +1,505 @@
+use crate::error::{OmgError, Position, Result};
+use crate::pipeline::ast::{Block, DataDef, Exp, FunctionDef, Match, RecordDef, RunDef};
+use crate::pipeline::code::{Code, Handler, Instruction, UserFunction};
+use crate::value::{DataType, RecordType, Value, VariantType};
+use std::sync::Arc;
+
+/// The compiled top level of a file and the `run` handlers declared in it.
+pub struct Program {
+    pub code: Arc<Code>,
+    pub handlers: Vec<Handler>,
+}
+
+pub fn compile(exp: &Exp) -> Result<Program> {
+    let mut compiler = Compiler::new(false);
+    compiler.compile_unscoped(exp)?;
+    compiler.emit(Instruction::Return);
+    Ok(Program {
+        code: Arc::new(Code {
+            instructions: compiler.instructions,
+        }),
+        handlers: compiler.handlers,
+    })
+}
+
+struct Compiler {
+    instructions: Vec<Instruction>,
+    handlers: Vec<Handler>,
+    /// How many loops the code being compiled is inside of.
+    loops: usize,
+    /// If `return` is allowed, as it is in functions and handlers.
+    returns: bool,
+    /// If this is a statement of an `async` block, which can't be jumped out
+    /// of.
+    in_async: bool,
+}
+
+impl Compiler {
+    fn new(returns: bool) -> Compiler {
+        Compiler {
+            instructions: Vec::new(),
+            handlers: Vec::new(),
+            loops: 0,
+            returns,
+            in_async: false,
+        }
+    }
+
+    /// Compiles the body of a function or handler into its own code.
+    fn compile_body(&mut self, body: &Exp) -> Result<Arc<Code>> {
+        let mut compiler = Compiler::new(true);
+        compiler.compile_unscoped(body)?;
+        compiler.emit(Instruction::Return);
+        self.handlers.append(&mut compiler.handlers);
+        Ok(Arc::new(Code {
+            instructions: compiler.instructions,
+        }))
+    }
+
+    /// Compiles a statement of an `async` block into its own code.
+    fn compile_async_statement(&mut self, statement: &Exp) -> Result<Arc<Code>> {
+        let mut compiler = Compiler::new(false);
+        compiler.in_async = true;
+        // What the statement declares stays in its own task.
+        compiler.emit(Instruction::PushScope);
+        compiler.compile(statement)?;
+        compiler.emit(Instruction::PopScope);
+        compiler.emit(Instruction::Return);
+        self.handlers.append(&mut compiler.handlers);
+        Ok(Arc::new(Code {
+            instructions: compiler.instructions,
+        }))
+    }
+
+    fn jump_error(&self, keyword: &str, outside: &str, pos: &Position) -> OmgError {
+        let msg = if self.in_async {
+            format!("Can't {} out of an async block", keyword)
+        } else {
+            format!("Found {} outside of {}", keyword, outside)
+        };
+        OmgError::new(msg, pos.clone())
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    /// The address of the next instruction.
+    fn here(&self) -> usize {
+        self.instructions.len()
+    }
+
+    fn patch(&mut self, address: usize, instruction: Instruction) {
+        self.instructions[address] = instruction;
+    }
+
+    /// The block of a file or function body runs in the scope it is given,
+    /// other blocks get a scope of their own.
+    fn compile_unscoped(&mut self, exp: &Exp) -> Result<()> {
+        match exp {
+            Exp::Block(block) => self.compile_block(block),
+            exp => self.compile(exp),
+        }
+    }
+
+    fn compile(&mut self, exp: &Exp) -> Result<()> {
+        match exp {
+            Exp::Block(block) => {
+                self.emit(Instruction::PushScope);
+                self.compile_block(block)?;
+                self.emit(Instruction::PopScope);
+            }
+            Exp::Call(call) => {
+                self.compile_all(&call.args)?;
+                self.emit(Instruction::Call {
+                    name: call.name.clone(),
+                    args: call.args.len(),
+                    pos: call.pos.clone(),
+                });
+            }
+            Exp::Literal(literal) => {
+                self.emit(Instruction::Push(literal.value.clone()));
+            }
+            Exp::Assignment(assignment) => {
+                self.compile(&assignment.value)?;
+                let name = assignment.name.clone();
+                match assignment.declaration {
+                    Some(_) => self.emit(Instruction::Declare(name)),
+                    None => self.emit(Instruction::Store(name, assignment.pos.clone())),
+                };
+                self.emit(Instruction::Push(Value::Nothing));
+            }
+            Exp::Variable(variable) => {
+                self.emit(Instruction::Load(
+                    variable.name.clone(),
+                    variable.pos.clone(),
+                ));
+            }
+            Exp::Operator(op) if op.op_type.short_circuits() => {
+                self.compile(&op.lhs)?;
+                let short_circuit = self.emit(Instruction::Jump(0));
+                self.compile(&op.rhs)?;
+                self.emit(Instruction::Operator(op.op_type, op.pos.clone()));
+                self.patch(
+                    short_circuit,
+                    Instruction::ShortCircuit {
+                        op_type: op.op_type,
+                        target: self.here(),
+                        pos: op.pos.clone(),
+                    },
+                );
+            }
+            Exp::Operator(op) => {
+                self.compile(&op.lhs)?;
+                self.compile(&op.rhs)?;
+                self.emit(Instruction::Operator(op.op_type, op.pos.clone()));
+            }
+            Exp::Unary(unary) => {
+                self.compile(&unary.exp)?;
+                self.emit(Instruction::Unary(unary.unary_type, unary.pos.clone()));
+            }
+            Exp::If(if_exp) => {
+                self.compile(&if_exp.condition)?;
+                let jump_else = self.emit(Instruction::Jump(0));
+                self.compile(&if_exp.then)?;
+                let jump_end = self.emit(Instruction::Jump(0));
+                self.patch(
+                    jump_else,
+                    Instruction::JumpUnless {
+                        target: self.here(),
+                        name: "if",
+                        pos: if_exp.condition.position(),
+                    },
+                );
+                match &if_exp.otherwise {
+                    Some(otherwise) => self.compile(otherwise)?,
+                    None => {
+                        self.emit(Instruction::Push(Value::Nothing));
+                    }
+                }
+                self.patch(jump_end, Instruction::Jump(self.here()));
+            }
+            Exp::While(while_exp) => {
+                let start = self.emit(Instruction::Jump(0));
+                let next = self.here();
+                self.compile(&while_exp.condition)?;
+                let jump_exit = self.emit(Instruction::Jump(0));
+                self.compile_loop_body(&while_exp.body)?;
+                self.emit(Instruction::Jump(next));
+                let exit = self.end_loop();
+                self.patch(start, Instruction::Loop { exit, next });
+                self.patch(
+                    jump_exit,
+                    Instruction::JumpUnless {
+                        target: exit,
+                        name: "while",
+                        pos: while_exp.condition.position(),
+                    },
+                );
+            }
+            Exp::For(for_exp) => {
+                // The scope of the loop variable.
+                self.emit(Instruction::PushScope);
+                let start = self.emit(Instruction::Jump(0));
+                self.compile(&for_exp.collection)?;
+                self.emit(Instruction::Iterate(for_exp.collection.position()));
+                let next = self.emit(Instruction::Jump(0));
+                self.compile_loop_body(&for_exp.body)?;
+                self.emit(Instruction::Jump(next));
+                let exit = self.end_loop();
+                self.patch(start, Instruction::Loop { exit, next });
+                self.patch(
+                    next,
+                    Instruction::NextItem {
+                        name: for_exp.name.clone(),
+                        exit,
+                    },
+                );
+                self.emit(Instruction::PopScope);
+            }
+            Exp::Break(pos) => {
+                if self.loops == 0 {
+                    return Err(self.jump_error("break", "a loop", pos));
+                }
+                self.emit(Instruction::Break);
+            }
+            Exp::Continue(pos) => {
+                if self.loops == 0 {
+                    return Err(self.jump_error("continue", "a loop", pos));
+                }
+                self.emit(Instruction::Continue);
+            }
+            // Declared up front by the block it is in, see `compile_block`.
+            Exp::FunctionDef(_) | Exp::RecordDef(_) | Exp::DataDef(_) => {
+                self.emit(Instruction::Push(Value::Nothing));
+            }
+            Exp::Run(def) => {
+                self.compile_handler(def)?;
+                self.emit(Instruction::Push(Value::Nothing));
+            }
+            Exp::Return(return_exp) => {
+                if !self.returns {
+                    return Err(self.jump_error("return", "a function", &return_exp.pos));
+                }
+                match &return_exp.value {
+                    Some(value) => self.compile(value)?,
+                    None => {
+                        self.emit(Instruction::Push(Value::Nothing));
+                    }
+                }
+                self.emit(Instruction::Return);
+            }
+            Exp::List(list) => {
+                self.compile_all(&list.items)?;
+                self.emit(Instruction::List(list.items.len()));
+            }
+            Exp::Index(index) => {
+                self.compile(&index.exp)?;
+                self.compile(&index.index)?;
+                self.emit(Instruction::Index {
+                    pos: index.pos.clone(),
+                    index_pos: index.index.position(),
+                });
+            }
+            Exp::New(new) => {
+                self.compile_all(&new.args)?;
+                self.emit(Instruction::New {
+                    name: new.name.clone(),
+                    args: new.args.len(),
+                    pos: new.pos.clone(),
+                });
+            }
+            Exp::Field(field) => {
+                self.compile(&field.exp)?;
+                self.emit(Instruction::Field(field.name.clone(), field.pos.clone()));
+            }
+            Exp::Async(async_exp) => {
+                self.emit(Instruction::PushScope);
+                self.compile_declarations(&async_exp.statements)?;
+                let statements = async_exp
+                    .statements
+                    .iter()
+                    .map(|statement| self.compile_async_statement(statement))
+                    .collect::<Result<_>>()?;
+                self.emit(Instruction::Async(statements, async_exp.pos.clone()));
+                self.emit(Instruction::PopScope);
+            }
+            Exp::Match(match_exp) => self.compile_match(match_exp)?,
+            Exp::Try(try_exp) => {
+                if !self.returns {
+                    let msg = if self.in_async {
+                        "Can't use ? in an async block"
+                    } else {
+                        "Found ? outside of a function"
+                    };
+                    return Err(OmgError::new(msg, try_exp.pos.clone()));
+                }
+                self.compile(&try_exp.exp)?;
+                self.emit(Instruction::Try(try_exp.pos.clone()));
+            }
+            Exp::MethodCall(method) => {
+                self.compile_all(&method.args)?;
+                self.emit(Instruction::Method {
+                    type_name: method.type_name.clone(),
+                    name: method.name.clone(),
+                    args: method.args.len(),
+                    pos: method.pos.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_all(&mut self, expressions: &[Exp]) -> Result<()> {
+        for exp in expressions {
+            self.compile(exp)?;
+        }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, block: &Block) -> Result<()> {
+        self.compile_declarations(block.statements.iter().chain(block.value.as_deref()))?;
+        for statement in &block.statements {
+            self.compile(statement)?;
+            self.emit(Instruction::Pop);
+        }
+        match &block.value {
+            Some(value) => self.compile(value)?,
+            None => {
+                self.emit(Instruction::Push(Value::Nothing));
+            }
+        }
+        Ok(())
+    }
+
+    /// Functions and types can be used from anywhere in the block they are
+    /// declared in, including before the declaration.
+    fn compile_declarations<'a, I>(&mut self, statements: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Exp>,
+    {
+        for statement in statements {
+            match statement {
+                Exp::FunctionDef(def) => {
+                    let function = self.compile_function(def)?;
+                    self.emit(Instruction::DeclareFunction(function));
+                }
+                Exp::RecordDef(def) => {
+                    self.emit(Instruction::DeclareType(record_type(def)));
+                }
+                Exp::DataDef(def) => {
+                    self.emit(Instruction::DeclareData(data_type(def)));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Tries the arms in order, the matched value stays on the stack until
+    /// an arm is picked.
+    fn compile_match(&mut self, match_exp: &Match) -> Result<()> {
+        self.compile(&match_exp.exp)?;
+        let mut ends = Vec::new();
+        for arm in &match_exp.arms {
+            self.emit(Instruction::PushScope);
+            let test = self.emit(Instruction::Jump(0));
+            let guard = match &arm.guard {
+                Some(guard) => {
+                    self.compile(guard)?;
+                    Some((self.emit(Instruction::Jump(0)), guard.position()))
+                }
+                None => None,
+            };
+            self.emit(Instruction::Pop);
+            self.compile(&arm.value)?;
+            self.emit(Instruction::PopScope);
+            ends.push(self.emit(Instruction::Jump(0)));
+            let fail = self.emit(Instruction::PopScope);
+            let pattern = Arc::clone(&arm.pattern);
+            self.patch(test, Instruction::Match { pattern, fail });
+            if let Some((address, pos)) = guard {
+                let target = fail;
+                let name = "guard";
+                self.patch(address, Instruction::JumpUnless { target, name, pos });
+            }
+        }
+        self.emit(Instruction::NoMatch(match_exp.pos.clone()));
+        for end in ends {
+            self.patch(end, Instruction::Jump(self.here()));
+        }
+        Ok(())
+    }
+
+    fn compile_loop_body(&mut self, body: &Exp) -> Result<()> {
+        self.loops += 1;
+        let result = self.compile(body);
+        self.loops -= 1;
+        result?;
+        self.emit(Instruction::Pop);
+        Ok(())
+    }
+
+    /// Emits the exit of a loop, returning its address.
+    fn end_loop(&mut self) -> usize {
+        let exit = self.emit(Instruction::EndLoop);
+        self.emit(Instruction::Push(Value::Nothing));
+        exit
+    }
+
+    fn compile_function(&mut self, def: &Arc<FunctionDef>) -> Result<Arc<UserFunction>> {
+        Ok(Arc::new(UserFunction {
+            def: Arc::clone(def),
+            code: self.compile_body(&def.body)?,
+        }))
+    }
+
+    fn compile_handler(&mut self, def: &Arc<RunDef>) -> Result<()> {
+        let code = self.compile_body(&def.body)?;
+        self.handlers.push(Handler {
+            def: Arc::clone(def),
+            code,
+        });
+        Ok(())
+    }
+}
+
+fn record_type(def: &RecordDef) -> Arc<RecordType> {
+    Arc::new(RecordType {
+        name: def.name.clone(),
+        kind: def.kind,
+        fields: def.fields.iter().map(|f| f.name.clone()).collect(),
+    })
+}
+
+pub fn data_type(def: &DataDef) -> Arc<DataType> {
+    Arc::new(DataType {
+        name: def.name.clone(),
+        variants: def
+            .variants
+            .iter()
+            .map(|variant| VariantType {
+                name: variant.name.clone(),
+                fields: variant.fields.iter().map(|f| f.name.clone()).collect(),
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{lexer, parse_block, Source};
+
+    fn compile_source(source: &str) -> Result<Program> {
+        let mut tokens = lexer(Source {
+            path: "test".to_string(),
+            source: source.to_string(),
+        })?;
+        compile(&parse_block(&mut tokens)?)
+    }
+
+    #[test]
+    fn break_outside_loop() {
+        let err = compile_source("a = 1;\nbreak;").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:0");
+        compile_source("while true { fn f() { continue } }")
+            .err()
+            .unwrap();
+    }
+
+    #[test]
+    fn return_outside_function() {
+        compile_source("return 1;").err().unwrap();
+        compile_source("fn f() { return 1 }").unwrap();
+    }
+
+    #[test]
+    fn jump_out_of_async() {
+        let err = compile_source("while true { async { break; } }")
+            .err()
+            .unwrap();
+        assert_eq!(err.msg, "Can't break out of an async block");
+        compile_source("fn f() { async { return 1; } }")
+            .err()
+            .unwrap();
+        compile_source("async { while true { break } }").unwrap();
+    }
+
+    #[test]
+    fn try_outside_function() {
+        let err = compile_source("x?;").err().unwrap();
+        assert_eq!(err.msg, "Found ? outside of a function");
+        let err = compile_source("fn f(x) { async { x?; } }").err().unwrap();
+        assert_eq!(err.msg, "Can't use ? in an async block");
+        compile_source("fn f(x) { x? }").unwrap();
+    }
+
+    #[test]
+    fn handlers() {
+        let program = compile_source("run (Main) { return; }\nrun (Main m) { }").unwrap();
+        assert_eq!(program.handlers.len(), 2);
+        assert_eq!(program.handlers[1].def.name, Some("m".to_string()));
+    }
+}
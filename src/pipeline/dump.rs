@@ -0,0 +1,447 @@
+use super::ast::*;
+use super::lexer::lexer;
+use super::parser::parse_block;
+use super::source::Source;
+use super::tokens::Token;
+use crate::error::{Position, Result};
+use crate::json::{Json, ToJson};
+use crate::value::{RecordKind, Value};
+
+/// Every token of the source with its slice and where it starts, ending with
+/// `EndOfFile`.
+pub fn dump_tokens(source: Source) -> Result<Json> {
+    let mut tokens = lexer(source)?;
+    let mut dump = Vec::new();
+    loop {
+        let pos = tokens.position();
+        dump.push(Json::object(vec![
+            ("kind", format!("{:?}", tokens.current()).into()),
+            ("slice", tokens.slice().into()),
+            ("line", pos.line.into()),
+            ("column", pos.column.into()),
+        ]));
+        if tokens.current() == Token::EndOfFile {
+            return Ok(dump.into());
+        }
+        tokens.next();
+    }
+}
+
+/// The syntax tree of the source, each node an object with its `kind`, its
+/// children and its `pos`.
+pub fn dump_ast(source: Source) -> Result<Json> {
+    let exp = parse_block(&mut lexer(source)?)?;
+    Ok(exp.to_json())
+}
+
+/// A node of the tree, the fields go between its kind and its position.
+fn node(kind: &str, fields: Vec<(&str, Json)>, pos: &Position) -> Json {
+    let mut entries = vec![("kind", kind.into())];
+    entries.extend(fields);
+    entries.push(("pos", pos.to_json()));
+    Json::object(entries)
+}
+
+/// The path is left out, so the same source dumps the same wherever it is.
+impl ToJson for Position {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("line", self.line.into()),
+            ("column", self.column.into()),
+            ("end_line", self.end_line.into()),
+            ("end_column", self.end_column.into()),
+        ])
+    }
+}
+
+/// Only the values a literal can have.
+impl ToJson for Value {
+    fn to_json(&self) -> Json {
+        match self {
+            Value::Nothing => Json::Null,
+            Value::Int(i) => (*i).into(),
+            Value::Float(f) => Json::Number(*f),
+            Value::True => true.into(),
+            Value::False => false.into(),
+            Value::String(s) => s.as_ref().into(),
+            value => value.to_string().into(),
+        }
+    }
+}
+
+impl ToJson for TypeName {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("args", self.args.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Param {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("type_name", self.type_name.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for FieldDef {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("type_name", self.type_name.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for VariantDef {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", self.name.to_json()),
+            ("fields", self.fields.to_json()),
+            ("pos", self.pos.to_json()),
+        ])
+    }
+}
+
+impl ToJson for MatchArm {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("pattern", self.pattern.to_json()),
+            ("guard", self.guard.to_json()),
+            ("value", self.value.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Pattern {
+    fn to_json(&self) -> Json {
+        match self {
+            Pattern::Wildcard(pos) => node("Wildcard", vec![], pos),
+            Pattern::Name(name, pos) => node("Name", vec![("name", name.to_json())], pos),
+            Pattern::Literal(value, pos) => node(
+                "Literal",
+                vec![
+                    ("type", value.type_name().into()),
+                    ("value", value.to_json()),
+                ],
+                pos,
+            ),
+            Pattern::Variant { name, args, pos } => node(
+                "Variant",
+                vec![("name", name.to_json()), ("args", args.to_json())],
+                pos,
+            ),
+            Pattern::Record { name, fields, pos } => {
+                let fields: Vec<Json> = fields
+                    .iter()
+                    .map(|(name, pattern)| {
+                        Json::object(vec![
+                            ("name", name.to_json()),
+                            ("pattern", pattern.to_json()),
+                        ])
+                    })
+                    .collect();
+                node(
+                    "Record",
+                    vec![("name", name.to_json()), ("fields", fields.into())],
+                    pos,
+                )
+            }
+            Pattern::List { items, rest, pos } => node(
+                "List",
+                vec![("items", items.to_json()), ("rest", rest.to_json())],
+                pos,
+            ),
+        }
+    }
+}
+
+impl ToJson for Exp {
+    fn to_json(&self) -> Json {
+        match self {
+            Exp::Block(block) => node(
+                "Block",
+                vec![
+                    ("statements", block.statements.to_json()),
+                    ("value", block.value.to_json()),
+                ],
+                &block.pos,
+            ),
+            Exp::Call(call) => node(
+                "Call",
+                vec![("name", call.name.to_json()), ("args", call.args.to_json())],
+                &call.pos,
+            ),
+            Exp::Literal(literal) => node(
+                "Literal",
+                vec![
+                    ("type", literal.value.type_name().into()),
+                    ("value", literal.value.to_json()),
+                ],
+                &literal.pos,
+            ),
+            Exp::Assignment(assignment) => {
+                let declaration = assignment.declaration.map(|declaration| match declaration {
+                    Declaration::Let => "let",
+                    Declaration::Var => "var",
+                });
+                node(
+                    "Assignment",
+                    vec![
+                        ("declaration", declaration.into()),
+                        ("type_name", assignment.type_name.to_json()),
+                        ("name", assignment.name.to_json()),
+                        ("value", assignment.value.to_json()),
+                    ],
+                    &assignment.pos,
+                )
+            }
+            Exp::Variable(variable) => node(
+                "Variable",
+                vec![("name", variable.name.to_json())],
+                &variable.pos,
+            ),
+            Exp::Operator(op) => node(
+                "Operator",
+                vec![
+                    ("op", op.op_type.symbol().into()),
+                    ("lhs", op.lhs.to_json()),
+                    ("rhs", op.rhs.to_json()),
+                ],
+                &op.pos,
+            ),
+            Exp::Unary(unary) => node(
+                "Unary",
+                vec![
+                    ("op", unary.unary_type.symbol().into()),
+                    ("exp", unary.exp.to_json()),
+                ],
+                &unary.pos,
+            ),
+            Exp::If(i) => node(
+                "If",
+                vec![
+                    ("condition", i.condition.to_json()),
+                    ("then", i.then.to_json()),
+                    ("otherwise", i.otherwise.to_json()),
+                ],
+                &i.pos,
+            ),
+            Exp::While(w) => node(
+                "While",
+                vec![
+                    ("condition", w.condition.to_json()),
+                    ("body", w.body.to_json()),
+                ],
+                &w.pos,
+            ),
+            Exp::For(f) => node(
+                "For",
+                vec![
+                    ("name", f.name.to_json()),
+                    ("collection", f.collection.to_json()),
+                    ("body", f.body.to_json()),
+                ],
+                &f.pos,
+            ),
+            Exp::Break(pos) => node("Break", vec![], pos),
+            Exp::Continue(pos) => node("Continue", vec![], pos),
+            Exp::FunctionDef(def) => node(
+                "FunctionDef",
+                vec![
+                    ("name", def.name.to_json()),
+                    ("params", def.params.to_json()),
+                    ("body", def.body.to_json()),
+                    ("doc", def.doc.to_json()),
+                ],
+                &def.pos,
+            ),
+            Exp::Return(r) => node("Return", vec![("value", r.value.to_json())], &r.pos),
+            Exp::List(list) => node("List", vec![("items", list.items.to_json())], &list.pos),
+            Exp::Index(index) => node(
+                "Index",
+                vec![
+                    ("exp", index.exp.to_json()),
+                    ("index", index.index.to_json()),
+                ],
+                &index.pos,
+            ),
+            Exp::RecordDef(def) => {
+                let record_kind = match def.kind {
+                    RecordKind::Record => "record",
+                    RecordKind::Event => "event",
+                };
+                node(
+                    "RecordDef",
+                    vec![
+                        ("record_kind", record_kind.into()),
+                        ("name", def.name.to_json()),
+                        ("fields", def.fields.to_json()),
+                        ("doc", def.doc.to_json()),
+                    ],
+                    &def.pos,
+                )
+            }
+            Exp::New(new) => node(
+                "New",
+                vec![("name", new.name.to_json()), ("args", new.args.to_json())],
+                &new.pos,
+            ),
+            Exp::Field(field) => node(
+                "Field",
+                vec![("exp", field.exp.to_json()), ("name", field.name.to_json())],
+                &field.pos,
+            ),
+            Exp::MethodCall(method) => node(
+                "MethodCall",
+                vec![
+                    ("type_name", method.type_name.to_json()),
+                    ("name", method.name.to_json()),
+                    ("args", method.args.to_json()),
+                ],
+                &method.pos,
+            ),
+            Exp::Run(run) => node(
+                "Run",
+                vec![
+                    ("event", run.event.to_json()),
+                    ("name", run.name.to_json()),
+                    ("body", run.body.to_json()),
+                ],
+                &run.pos,
+            ),
+            Exp::Async(a) => node(
+                "Async",
+                vec![("statements", a.statements.to_json())],
+                &a.pos,
+            ),
+            Exp::DataDef(def) => node(
+                "DataDef",
+                vec![
+                    ("name", def.name.to_json()),
+                    ("params", def.params.to_json()),
+                    ("variants", def.variants.to_json()),
+                    ("doc", def.doc.to_json()),
+                ],
+                &def.pos,
+            ),
+            Exp::Match(m) => node(
+                "Match",
+                vec![("exp", m.exp.to_json()), ("arms", m.arms.to_json())],
+                &m.pos,
+            ),
+            Exp::Try(t) => node("Try", vec![("exp", t.exp.to_json())], &t.pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(source: &str) -> Source {
+        Source {
+            path: "test.omg".to_string(),
+            source: source.to_string(),
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let dump = dump_tokens(source("let a =\n \"b\";")).unwrap();
+        assert_eq!(
+            dump.to_string(),
+            concat!(
+                r#"[{"kind":"Let","slice":"let","line":0,"column":0},"#,
+                r#"{"kind":"Identifier","slice":"a","line":0,"column":4},"#,
+                r#"{"kind":"Assignment","slice":"=","line":0,"column":6},"#,
+                r#"{"kind":"String","slice":"\"b\"","line":1,"column":1},"#,
+                r#"{"kind":"Semicolon","slice":";","line":1,"column":4},"#,
+                r#"{"kind":"EndOfFile","slice":"","line":1,"column":5}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn ast() {
+        let dump = dump_ast(source("-x?")).unwrap();
+        let pos = |column: u64, end_column: u64| {
+            format!(
+                r#"{{"line":0,"column":{},"end_line":0,"end_column":{}}}"#,
+                column, end_column
+            )
+        };
+        let expected = format!(
+            concat!(
+                r#"{{"kind":"Block","statements":[],"value":"#,
+                r#"{{"kind":"Unary","op":"-","exp":"#,
+                r#"{{"kind":"Try","exp":{{"kind":"Variable","name":"x","pos":{}}},"pos":{}}},"#,
+                r#""pos":{}}},"pos":{}}}"#
+            ),
+            pos(1, 2),
+            pos(2, 3),
+            pos(0, 1),
+            pos(0, 1)
+        );
+        assert_eq!(dump.to_string(), expected);
+    }
+
+    #[test]
+    fn every_node() {
+        let dump = dump_ast(source(
+            "/// A.\nevent A { Int x; }\ntype T<U> = V(U u) | W;\n\
+             fn f(Int a) { for i in [1.5] { break; continue; } return a; }\n\
+             run (A a) { async { A.emit(new A(1)); } }\n\
+             var y = match Some(1) { Some(1) if true => a.x, A { x: _ } => 1, [z, ..r] => 2 };\n\
+             while false { y = y[0]; }\n\
+             if y == 1 { } else { }",
+        ))
+        .unwrap();
+        let text = dump.to_string();
+        for kind in &[
+            "RecordDef",
+            "DataDef",
+            "FunctionDef",
+            "For",
+            "Break",
+            "Continue",
+            "Return",
+            "Run",
+            "Async",
+            "MethodCall",
+            "New",
+            "Match",
+            "Wildcard",
+            "Field",
+            "While",
+            "Index",
+            "If",
+            "Operator",
+        ] {
+            assert!(
+                text.contains(&format!(r#""kind":"{}""#, kind)),
+                "{} is missing",
+                kind
+            );
+        }
+        assert!(text.contains(r#""doc":"A.""#));
+        assert!(text.contains(r#""type":"Float","value":1.5"#));
+    }
+
+    #[test]
+    fn exact_numbers() {
+        let text = dump_ast(source(
+            "let x = 9007199254740993;\nlet y = 10000000000000000.0;",
+        ))
+        .unwrap()
+        .to_string();
+        assert!(text.contains(r#""value":9007199254740993"#));
+        assert!(text.contains(r#""value":10000000000000000.0"#));
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// A pattern reduced to what matters when checking if a `match` handles
+/// every value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+/// How a value was built, the fields are the arguments of the `Pat`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ctor {
+    Variant {
+        index: usize,
+        /// The names of all variants of the data type and their number of
+        /// fields.
+        variants: Arc<Vec<(String, usize)>>,
+    },
+    Boolean(bool),
+    /// The empty list.
+    Nil,
+    /// A list as its first item and the rest of the list.
+    Cons,
+    Record {
+        name: String,
+        fields: Vec<String>,
+    },
+    /// Numbers and strings, there are too many of them to list.
+    Literal(String),
+}
+
+impl Ctor {
+    fn arity(&self) -> usize {
+        match self {
+            Ctor::Variant { index, variants } => variants[*index].1,
+            Ctor::Boolean(_) | Ctor::Nil | Ctor::Literal(_) => 0,
+            Ctor::Cons => 2,
+            Ctor::Record { fields, .. } => fields.len(),
+        }
+    }
+
+    /// Every constructor of the type, when there are few enough to list.
+    fn all(&self) -> Option<Vec<Ctor>> {
+        match self {
+            Ctor::Variant { variants, .. } => Some(
+                (0..variants.len())
+                    .map(|index| Ctor::Variant {
+                        index,
+                        variants: Arc::clone(variants),
+                    })
+                    .collect(),
+            ),
+            Ctor::Boolean(_) => Some(vec![Ctor::Boolean(true), Ctor::Boolean(false)]),
+            Ctor::Nil | Ctor::Cons => Some(vec![Ctor::Nil, Ctor::Cons]),
+            Ctor::Record { .. } => Some(vec![self.clone()]),
+            Ctor::Literal(_) => None,
+        }
+    }
+}
+
+/// A value that none of the rows match, or `None` when they match them all.
+/// Each row is the pattern of an arm without a guard.
+pub fn missing(rows: &[Pat]) -> Option<Pat> {
+    let rows = rows.iter().map(|pat| vec![pat.clone()]).collect();
+    witness(rows, 1).map(|mut pats| pats.remove(0))
+}
+
+/// Finds values for `width` columns that none of the rows match.
+fn witness(rows: Vec<Vec<Pat>>, width: usize) -> Option<Vec<Pat>> {
+    if width == 0 {
+        return if rows.is_empty() {
+            Some(Vec::new())
+        } else {
+            None
+        };
+    }
+    let mut heads: Vec<Ctor> = Vec::new();
+    for row in &rows {
+        if let Pat::Ctor(ctor, _) = &row[0] {
+            if !heads.contains(ctor) {
+                heads.push(ctor.clone());
+            }
+        }
+    }
+    let all = heads.first().and_then(|ctor| ctor.all());
+    match all {
+        Some(all) if all.iter().all(|ctor| heads.contains(ctor)) => {
+            for ctor in all {
+                let arity = ctor.arity();
+                if let Some(mut pats) = witness(specialize(&rows, &ctor), arity + width - 1) {
+                    let rest = pats.split_off(arity);
+                    let mut result = vec![Pat::Ctor(ctor, pats)];
+                    result.extend(rest);
+                    return Some(result);
+                }
+            }
+            None
+        }
+        all => {
+            let mut pats = witness(default(&rows), width - 1)?;
+            let unused = all.and_then(|all| all.into_iter().find(|ctor| !heads.contains(ctor)));
+            let head = match unused {
+                Some(ctor) => {
+                    let arity = ctor.arity();
+                    Pat::Ctor(ctor, vec![Pat::Wild; arity])
+                }
+                None => Pat::Wild,
+            };
+            pats.insert(0, head);
+            Some(pats)
+        }
+    }
+}
+
+/// The rows that match `ctor` in the first column, with its fields in place
+/// of the first column.
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut fields = match &row[0] {
+                Pat::Ctor(head, args) if head == ctor => args.clone(),
+                Pat::Ctor(_, _) => return None,
+                Pat::Wild => vec![Pat::Wild; ctor.arity()],
+            };
+            fields.extend(row[1..].iter().cloned());
+            Some(fields)
+        })
+        .collect()
+}
+
+/// The rows that match anything in the first column, without it.
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| row[0] == Pat::Wild)
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ctor, args) = match self {
+            Pat::Wild => return write!(f, "_"),
+            Pat::Ctor(ctor, args) => (ctor, args),
+        };
+        match ctor {
+            Ctor::Variant { index, variants } if args.is_empty() => {
+                write!(f, "{}", variants[*index].0)
+            }
+            Ctor::Variant { index, variants } => {
+                write!(f, "{}(", variants[*index].0)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            }
+            Ctor::Boolean(b) => write!(f, "{}", b),
+            Ctor::Nil | Ctor::Cons => {
+                let mut items = Vec::new();
+                let mut tail = self;
+                while let Pat::Ctor(Ctor::Cons, args) = tail {
+                    items.push(args[0].clone());
+                    tail = &args[1];
+                }
+                write!(f, "[")?;
+                write_list(f, &items)?;
+                match tail {
+                    Pat::Ctor(Ctor::Nil, _) => write!(f, "]"),
+                    _ if items.is_empty() => write!(f, "..]"),
+                    _ => write!(f, ", ..]"),
+                }
+            }
+            Ctor::Record { name, fields } => {
+                write!(f, "{} {{", name)?;
+                for (i, (field, arg)) in fields.iter().zip(args).enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    write!(f, "{} {}: {}", separator, field, arg)?;
+                }
+                write!(f, " }}")
+            }
+            Ctor::Literal(literal) => write!(f, "{}", literal),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter, pats: &[Pat]) -> fmt::Result {
+    for (i, pat) in pats.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", pat)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(index: usize, args: Vec<Pat>) -> Pat {
+        let variants = vec![("Circle".to_string(), 1), ("Rect".to_string(), 2)];
+        let ctor = Ctor::Variant {
+            index,
+            variants: Arc::new(variants),
+        };
+        Pat::Ctor(ctor, args)
+    }
+
+    fn boolean(b: bool) -> Pat {
+        Pat::Ctor(Ctor::Boolean(b), Vec::new())
+    }
+
+    fn list(items: Vec<Pat>, rest: Option<Pat>) -> Pat {
+        let tail = rest.unwrap_or_else(|| Pat::Ctor(Ctor::Nil, Vec::new()));
+        items
+            .into_iter()
+            .rev()
+            .fold(tail, |tail, item| Pat::Ctor(Ctor::Cons, vec![item, tail]))
+    }
+
+    fn missing_string(rows: &[Pat]) -> Option<String> {
+        missing(rows).map(|pat| pat.to_string())
+    }
+
+    #[test]
+    fn variants() {
+        let circle = shape(0, vec![Pat::Wild]);
+        let rect = shape(1, vec![Pat::Wild, Pat::Wild]);
+        assert_eq!(
+            missing_string(std::slice::from_ref(&circle)),
+            Some("Rect(_, _)".to_string())
+        );
+        assert_eq!(missing_string(&[circle.clone(), rect]), None);
+        assert_eq!(missing_string(&[circle, Pat::Wild]), None);
+    }
+
+    #[test]
+    fn nested() {
+        let rows = [
+            shape(0, vec![Pat::Wild]),
+            shape(1, vec![boolean(true), Pat::Wild]),
+            shape(1, vec![Pat::Wild, boolean(true)]),
+        ];
+        assert_eq!(
+            missing_string(&rows),
+            Some("Rect(false, false)".to_string())
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            missing_string(&[list(vec![], None)]),
+            Some("[_, ..]".to_string())
+        );
+        let rows = [list(vec![], None), list(vec![Pat::Wild], Some(Pat::Wild))];
+        assert_eq!(missing_string(&rows), None);
+        let rows = [list(vec![], None), list(vec![Pat::Wild], None)];
+        assert_eq!(missing_string(&rows), Some("[_, _, ..]".to_string()));
+    }
+
+    #[test]
+    fn literals() {
+        let one = Pat::Ctor(Ctor::Literal("1".to_string()), Vec::new());
+        assert_eq!(
+            missing_string(std::slice::from_ref(&one)),
+            Some("_".to_string())
+        );
+        assert_eq!(missing_string(&[one, Pat::Wild]), None);
+        assert_eq!(missing_string(&[]), Some("_".to_string()));
+    }
+}
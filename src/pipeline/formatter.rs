@@ -0,0 +1,852 @@
+use super::ast::*;
+use super::lexer::lexer;
+use super::parser::parse_block;
+use super::source::Source;
+use super::tokens::{Token, Trivia, TriviaKind};
+use crate::error::{OmgError, Position, Result};
+use crate::value::RecordKind;
+use std::collections::HashMap;
+
+const INDENT: &str = "    ";
+
+/// Precedence of expressions that end in a token of their own, like calls,
+/// literals and postfix operators. Above `**`, which binds tighter than
+/// prefix operators.
+const POSTFIX_PRECEDENCE: u8 = UNARY_PRECEDENCE + 2;
+
+/// Formats the source the one way omg code is laid out, keeping its comments
+/// and blank lines. Formatting is checked to never change what the program
+/// means, by parsing the output again.
+pub fn format(source: Source) -> Result<String> {
+    let path = source.path.clone();
+    let tokens = lexer(source)?;
+    let layout = Layout::new(&tokens);
+    let exp = parse_block(&mut tokens.clone())?;
+    let formatted = Printer::new(&layout, true).root(&exp);
+
+    let changed = || {
+        OmgError::new(
+            "Formatting would change the meaning of the file",
+            Position::new(path.clone()).with_pos(0, 0),
+        )
+    };
+    let output = lexer(Source {
+        path: path.clone(),
+        source: formatted.clone(),
+    })
+    .map_err(|_| changed())?;
+    let output_layout = Layout::new(&output);
+    let output_exp = parse_block(&mut output.clone()).map_err(|_| changed())?;
+    let meaning = Printer::new(&layout, false).root(&exp);
+    if meaning != Printer::new(&output_layout, false).root(&output_exp) {
+        return Err(changed());
+    }
+    Ok(formatted)
+}
+
+/// The tokens and trivia of a file, for what the AST leaves out like
+/// comments and how a literal was written.
+struct Layout {
+    /// Each token with its slice and its line and column.
+    tokens: Vec<(Token, String, (u64, u64))>,
+    /// The index of the token at a line and column.
+    index: HashMap<(u64, u64), usize>,
+    /// The index of the closing bracket for the index of an opening one.
+    closing: HashMap<usize, usize>,
+    trivia: Vec<Trivia>,
+    /// If there is code in front of the trivia on its line.
+    trailing: Vec<bool>,
+    end: (u64, u64),
+}
+
+impl Layout {
+    fn new(tokens: &super::Tokens) -> Self {
+        let mut tokens = tokens.clone();
+        let mut layout = Layout {
+            tokens: Vec::new(),
+            index: HashMap::new(),
+            closing: HashMap::new(),
+            trivia: Vec::new(),
+            trailing: Vec::new(),
+            end: (0, 0),
+        };
+        let mut open = Vec::new();
+        let mut first_column = HashMap::new();
+        loop {
+            layout.trivia.extend(tokens.trivia().iter().cloned());
+            let pos = tokens.position();
+            let at = (pos.line, pos.column);
+            let index = layout.tokens.len();
+            first_column.entry(pos.line).or_insert(pos.column);
+            layout.index.insert(at, index);
+            layout
+                .tokens
+                .push((tokens.current(), tokens.slice().to_string(), at));
+            match tokens.current() {
+                Token::ParenthesesOpen | Token::BraceOpen | Token::BracketOpen => open.push(index),
+                Token::ParenthesesClose | Token::BraceClose | Token::BracketClose => {
+                    if let Some(opening) = open.pop() {
+                        layout.closing.insert(opening, index);
+                    }
+                }
+                Token::EndOfFile => {
+                    layout.end = at;
+                    break;
+                }
+                _ => (),
+            }
+            tokens.next();
+        }
+        layout.trailing = layout
+            .trivia
+            .iter()
+            .map(|trivia| match first_column.get(&trivia.line) {
+                Some(column) => *column < trivia.column,
+                None => false,
+            })
+            .collect();
+        layout
+    }
+}
+
+fn at(trivia: &Trivia) -> (u64, u64) {
+    (trivia.line, trivia.column)
+}
+
+fn precedence(exp: &Exp) -> u8 {
+    match exp {
+        Exp::Assignment(_) | Exp::Return(_) => 0,
+        Exp::Operator(op) => op.op_type.precedence(),
+        Exp::Unary(_) => UNARY_PRECEDENCE,
+        _ => POSTFIX_PRECEDENCE,
+    }
+}
+
+fn lhs_needs_parens(op: &Operator) -> bool {
+    let lhs = precedence(&op.lhs);
+    let precedence = op.op_type.precedence();
+    lhs < precedence || (lhs == precedence && op.op_type.right_associative())
+}
+
+fn rhs_needs_parens(op: &Operator) -> bool {
+    // A prefix operator can start any right hand side, as in `2 ** -1`.
+    if let Exp::Unary(_) = *op.rhs {
+        return false;
+    }
+    let rhs = precedence(&op.rhs);
+    let precedence = op.op_type.precedence();
+    rhs < precedence || (rhs == precedence && !op.op_type.right_associative())
+}
+
+/// If the expression is printed starting with a token that would continue
+/// an expression that ends with `}` in front of it, as in `if a { b } -c`.
+fn continues_previous(exp: &Exp) -> bool {
+    match exp {
+        Exp::Unary(unary) => unary.unary_type != UnaryType::Not,
+        Exp::List(_) => true,
+        Exp::Operator(op) => !lhs_needs_parens(op) && continues_previous(&op.lhs),
+        Exp::Index(Index { exp, .. })
+        | Exp::Field(Field { exp, .. })
+        | Exp::Try(Try { exp, .. }) => {
+            precedence(exp) >= POSTFIX_PRECEDENCE && continues_previous(exp)
+        }
+        _ => false,
+    }
+}
+
+/// Prints an AST back to source. With `pretty` off it prints the canonical
+/// form used to compare meanings, without comments and with every operator in
+/// parentheses.
+struct Printer<'a> {
+    layout: &'a Layout,
+    pretty: bool,
+    out: String,
+    depth: usize,
+    /// The next trivia to print.
+    trivia: usize,
+    /// If a blank line goes in front of the next line.
+    blank: bool,
+    /// If nothing has been printed since a block was opened.
+    at_start: bool,
+    /// Where the last statement ended, blank lines inside it are dropped.
+    last_end: (u64, u64),
+}
+
+impl<'a> Printer<'a> {
+    fn new(layout: &'a Layout, pretty: bool) -> Self {
+        Printer {
+            layout,
+            pretty,
+            out: String::new(),
+            depth: 0,
+            trivia: 0,
+            blank: false,
+            at_start: true,
+            last_end: (0, 0),
+        }
+    }
+
+    fn root(mut self, exp: &Exp) -> String {
+        match exp {
+            Exp::Block(block) => self.statements(&block_items(block), block.value.is_some(), false),
+            exp => self.statements(&[exp], true, false),
+        }
+        self.leading(self.layout.end);
+        self.out
+    }
+
+    fn token(&self, pos: &Position) -> usize {
+        self.layout.index[&(pos.line, pos.column)]
+    }
+
+    fn at_token(&self, index: usize) -> (u64, u64) {
+        self.layout.tokens[index].2
+    }
+
+    fn slice(&self, pos: &Position) -> &'a str {
+        &self.layout.tokens[self.token(pos)].1
+    }
+
+    fn closing(&self, opening: usize) -> usize {
+        self.layout.closing[&opening]
+    }
+
+    /// The index of the first token of the expression.
+    fn first_token(&self, exp: &Exp) -> usize {
+        match exp {
+            Exp::Operator(op) => self.first_token(&op.lhs),
+            Exp::Index(Index { exp, .. })
+            | Exp::Field(Field { exp, .. })
+            | Exp::Try(Try { exp, .. }) => self.first_token(exp),
+            Exp::MethodCall(call) => self.token(&call.pos) - 2,
+            Exp::Assignment(assignment) if assignment.declaration.is_some() => {
+                let mut index = self.token(&assignment.pos);
+                while !matches!(self.layout.tokens[index].0, Token::Let | Token::Var) {
+                    index -= 1;
+                }
+                index
+            }
+            exp => self.token(&exp.position()),
+        }
+    }
+
+    /// The index of the last token of the expression, leaving out closing
+    /// parentheses around it.
+    fn last_token(&self, exp: &Exp) -> usize {
+        match exp {
+            Exp::Block(block) => self.closing(self.token(&block.pos)),
+            Exp::Call(Call { pos, .. }) | Exp::MethodCall(MethodCall { pos, .. }) => {
+                self.closing(self.token(pos) + 1)
+            }
+            Exp::New(New { pos, .. }) => self.closing(self.token(pos) + 2),
+            Exp::RecordDef(record) => self.closing(self.token(&record.pos) + 2),
+            Exp::List(List { pos, .. }) | Exp::Index(Index { pos, .. }) => {
+                self.closing(self.token(pos))
+            }
+            Exp::Async(Async { pos, .. }) => self.closing(self.token(pos) + 1),
+            Exp::Assignment(Assignment { value: exp, .. })
+            | Exp::Operator(Operator { rhs: exp, .. })
+            | Exp::Unary(Unary { exp, .. })
+            | Exp::While(While { body: exp, .. })
+            | Exp::For(For { body: exp, .. }) => self.last_token(exp),
+            Exp::If(i) => self.last_token(i.otherwise.as_ref().unwrap_or(&i.then)),
+            Exp::FunctionDef(f) => self.last_token(&f.body),
+            Exp::Run(run) => self.last_token(&run.body),
+            Exp::Return(Return {
+                value: Some(value), ..
+            }) => self.last_token(value),
+            Exp::DataDef(data) => {
+                let index = self.token(&data.variants[data.variants.len() - 1].pos);
+                match self.layout.tokens[index + 1].0 {
+                    Token::ParenthesesOpen => self.closing(index + 1),
+                    _ => index,
+                }
+            }
+            Exp::Match(m) => self.closing(self.match_open(m)),
+            exp => self.token(&exp.position()),
+        }
+    }
+
+    /// The index of the `{` in front of the arms.
+    fn match_open(&self, m: &Match) -> usize {
+        let mut index = self.last_token(&m.exp) + 1;
+        while self.layout.tokens[index].0 != Token::BraceOpen {
+            index += 1;
+        }
+        index
+    }
+
+    /// Prints the comments in front of `before`, on lines of their own unless
+    /// there was code in front of them.
+    fn leading(&mut self, before: (u64, u64)) {
+        if !self.pretty {
+            return;
+        }
+        while let Some(trivia) = self.layout.trivia.get(self.trivia) {
+            if at(trivia) >= before {
+                break;
+            }
+            let trailing = self.layout.trailing[self.trivia];
+            self.trivia += 1;
+            match trivia.kind {
+                TriviaKind::BlankLine => self.blank = self.blank || at(trivia) > self.last_end,
+                _ if trailing && self.ends_with_code() => {
+                    self.out.pop();
+                    self.out.push(' ');
+                    self.out.push_str(trivia.slice.trim_end());
+                    self.out.push('\n');
+                }
+                _ => {
+                    self.line();
+                    self.out.push_str(trivia.slice.trim_end());
+                    self.out.push('\n');
+                }
+            }
+        }
+    }
+
+    /// If the last line printed has code on it.
+    fn ends_with_code(&self) -> bool {
+        let out = match self.out.strip_suffix('\n') {
+            Some(out) => out,
+            None => return false,
+        };
+        let line = &out[out.rfind('\n').map_or(0, |i| i + 1)..];
+        !line.trim_start().is_empty()
+    }
+
+    /// If there are comments in front of `before`.
+    fn has_comments(&self, before: (u64, u64)) -> bool {
+        self.pretty
+            && self.layout.trivia[self.trivia..]
+                .iter()
+                .take_while(|trivia| at(trivia) < before)
+                .any(|trivia| trivia.kind != TriviaKind::BlankLine)
+    }
+
+    /// Starts a new line at the current depth.
+    fn line(&mut self) {
+        if self.blank && !self.at_start {
+            self.out.push('\n');
+        }
+        self.blank = false;
+        self.at_start = false;
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn statements(&mut self, items: &[&Exp], has_value: bool, is_async: bool) {
+        for (i, exp) in items.iter().enumerate() {
+            let first = self.first_token(exp);
+            self.leading(self.at_token(first));
+            self.line();
+            self.exp(exp);
+            let last = i == items.len() - 1;
+            let next_continues = !last && continues_previous(items[i + 1]);
+            let semicolon = if has_value && last {
+                false
+            } else if !self.pretty || !self.out.ends_with('}') {
+                true
+            } else {
+                // A statement that ends with `}` needs no `;`, unless it
+                // would become the value of the block or run into the next.
+                (last && !is_async) || next_continues
+            };
+            if semicolon {
+                self.out.push(';');
+            }
+            self.out.push('\n');
+            self.last_end = self.at_token(self.last_token(exp));
+        }
+    }
+
+    /// Prints the items between `{` and the `}` at `close`.
+    fn braces<F>(&mut self, close: usize, empty: bool, print: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        let close = self.at_token(close);
+        if empty && !self.has_comments(close) {
+            self.leading(close);
+            self.blank = false;
+            self.out.push_str("{ }");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.at_start = true;
+        print(self);
+        self.leading(close);
+        self.depth -= 1;
+        self.blank = false;
+        self.line();
+        self.out.push('}');
+    }
+
+    fn block(&mut self, block: &Block) {
+        let close = self.closing(self.token(&block.pos));
+        let items = block_items(block);
+        let has_value = block.value.is_some();
+        self.braces(close, items.is_empty(), |printer| {
+            printer.statements(&items, has_value, false)
+        });
+    }
+
+    fn exp(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Block(block) => self.block(block),
+            Exp::Call(call) => {
+                self.out.push_str(&call.name);
+                self.args(&call.args);
+            }
+            Exp::Literal(literal) => self.out.push_str(self.slice(&literal.pos)),
+            Exp::Assignment(assignment) => {
+                match assignment.declaration {
+                    Some(Declaration::Let) => self.out.push_str("let "),
+                    Some(Declaration::Var) => self.out.push_str("var "),
+                    None => (),
+                }
+                if let Some(type_name) = &assignment.type_name {
+                    self.type_name(type_name);
+                    self.out.push(' ');
+                }
+                self.out.push_str(&assignment.name);
+                self.out.push_str(" = ");
+                self.exp(&assignment.value);
+            }
+            Exp::Variable(variable) => self.out.push_str(&variable.name),
+            Exp::Operator(op) => {
+                self.operand(&op.lhs, lhs_needs_parens(op));
+                self.out.push(' ');
+                self.out.push_str(op.op_type.symbol());
+                self.out.push(' ');
+                self.operand(&op.rhs, rhs_needs_parens(op));
+            }
+            Exp::Unary(unary) => {
+                self.out.push_str(unary.unary_type.symbol());
+                self.operand(&unary.exp, precedence(&unary.exp) < UNARY_PRECEDENCE);
+            }
+            Exp::If(i) => {
+                self.out.push_str("if ");
+                self.exp(&i.condition);
+                self.out.push(' ');
+                self.exp(&i.then);
+                if let Some(otherwise) = &i.otherwise {
+                    self.out.push_str(" else ");
+                    self.exp(otherwise);
+                }
+            }
+            Exp::While(w) => {
+                self.out.push_str("while ");
+                self.exp(&w.condition);
+                self.out.push(' ');
+                self.exp(&w.body);
+            }
+            Exp::For(f) => {
+                self.out.push_str("for ");
+                self.out.push_str(&f.name);
+                self.out.push_str(" in ");
+                self.exp(&f.collection);
+                self.out.push(' ');
+                self.exp(&f.body);
+            }
+            Exp::Break(_) => self.out.push_str("break"),
+            Exp::Continue(_) => self.out.push_str("continue"),
+            Exp::FunctionDef(f) => {
+                self.out.push_str("fn ");
+                self.out.push_str(&f.name);
+                self.out.push('(');
+                for (i, param) in f.params.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    if let Some(type_name) = &param.type_name {
+                        self.type_name(type_name);
+                        self.out.push(' ');
+                    }
+                    self.out.push_str(&param.name);
+                }
+                self.out.push_str(") ");
+                self.exp(&f.body);
+            }
+            Exp::Return(r) => {
+                self.out.push_str("return");
+                if let Some(value) = &r.value {
+                    self.out.push(' ');
+                    self.exp(value);
+                }
+            }
+            Exp::List(list) => {
+                self.out.push('[');
+                self.list(&list.items);
+                self.out.push(']');
+            }
+            Exp::Index(index) => {
+                self.postfix(&index.exp);
+                self.out.push('[');
+                self.exp(&index.index);
+                self.out.push(']');
+            }
+            Exp::RecordDef(record) => self.record_def(record),
+            Exp::New(new) => {
+                self.out.push_str("new ");
+                self.out.push_str(&new.name);
+                self.args(&new.args);
+            }
+            Exp::Field(field) => {
+                self.postfix(&field.exp);
+                self.out.push('.');
+                self.out.push_str(&field.name);
+            }
+            Exp::MethodCall(call) => {
+                self.out.push_str(&call.type_name);
+                self.out.push('.');
+                self.out.push_str(&call.name);
+                self.args(&call.args);
+            }
+            Exp::Run(run) => {
+                self.out.push_str("run (");
+                self.out.push_str(&run.event);
+                if let Some(name) = &run.name {
+                    self.out.push(' ');
+                    self.out.push_str(name);
+                }
+                self.out.push_str(") ");
+                self.exp(&run.body);
+            }
+            Exp::Async(a) => {
+                self.out.push_str("async ");
+                let close = self.closing(self.token(&a.pos) + 1);
+                let items: Vec<&Exp> = a.statements.iter().collect();
+                self.braces(close, items.is_empty(), |printer| {
+                    printer.statements(&items, false, true)
+                });
+            }
+            Exp::DataDef(data) => self.data_def(data),
+            Exp::Match(m) => self.match_exp(m),
+            Exp::Try(t) => {
+                self.postfix(&t.exp);
+                self.out.push('?');
+            }
+        }
+    }
+
+    fn operand(&mut self, exp: &Exp, parens: bool) {
+        let parens = parens || (!self.pretty && precedence(exp) < POSTFIX_PRECEDENCE);
+        if parens {
+            self.out.push('(');
+        }
+        self.exp(exp);
+        if parens {
+            self.out.push(')');
+        }
+    }
+
+    fn postfix(&mut self, exp: &Exp) {
+        self.operand(exp, precedence(exp) < POSTFIX_PRECEDENCE);
+    }
+
+    fn list(&mut self, items: &[Exp]) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.exp(item);
+        }
+    }
+
+    fn args(&mut self, args: &[Exp]) {
+        self.out.push('(');
+        self.list(args);
+        self.out.push(')');
+    }
+
+    fn type_name(&mut self, type_name: &TypeName) {
+        self.out.push_str(&type_name.name);
+        if !type_name.args.is_empty() {
+            self.out.push('<');
+            for (i, arg) in type_name.args.iter().enumerate() {
+                if i > 0 {
+                    self.out.push_str(", ");
+                }
+                self.type_name(arg);
+            }
+            self.out.push('>');
+        }
+    }
+
+    fn field_def(&mut self, field: &FieldDef) {
+        if let Some(type_name) = &field.type_name {
+            self.type_name(type_name);
+            self.out.push(' ');
+        }
+        self.out.push_str(&field.name);
+    }
+
+    fn record_def(&mut self, record: &RecordDef) {
+        self.out.push_str(match record.kind {
+            RecordKind::Record => "record ",
+            RecordKind::Event => "event ",
+        });
+        self.out.push_str(&record.name);
+        self.out.push(' ');
+        let close = self.closing(self.token(&record.pos) + 2);
+        self.braces(close, record.fields.is_empty(), |printer| {
+            for field in &record.fields {
+                let first = match &field.type_name {
+                    Some(type_name) => &type_name.pos,
+                    None => &field.pos,
+                };
+                printer.leading((first.line, first.column));
+                printer.line();
+                printer.field_def(field);
+                printer.out.push_str(";\n");
+                printer.last_end = (field.pos.line, field.pos.column);
+            }
+        });
+    }
+
+    fn data_def(&mut self, data: &DataDef) {
+        self.out.push_str("type ");
+        self.out.push_str(&data.name);
+        if !data.params.is_empty() {
+            self.out.push('<');
+            self.out.push_str(&data.params.join(", "));
+            self.out.push('>');
+        }
+        self.out.push_str(" = ");
+        for (i, variant) in data.variants.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(" | ");
+            }
+            self.out.push_str(&variant.name);
+            let index = self.token(&variant.pos);
+            if self.layout.tokens[index + 1].0 == Token::ParenthesesOpen {
+                self.out.push('(');
+                for (i, field) in variant.fields.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.field_def(field);
+                }
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn match_exp(&mut self, m: &Match) {
+        self.out.push_str("match ");
+        self.exp(&m.exp);
+        self.out.push(' ');
+        let close = self.closing(self.match_open(m));
+        self.braces(close, m.arms.is_empty(), |printer| {
+            for arm in &m.arms {
+                let first = printer.token(&arm.pattern.position());
+                printer.leading(printer.at_token(first));
+                printer.line();
+                printer.pattern(&arm.pattern);
+                if let Some(guard) = &arm.guard {
+                    printer.out.push_str(" if ");
+                    printer.exp(guard);
+                }
+                printer.out.push_str(" => ");
+                printer.exp(&arm.value);
+                printer.out.push_str(",\n");
+                printer.last_end = printer.at_token(printer.last_token(&arm.value));
+            }
+        });
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard(_) => self.out.push('_'),
+            Pattern::Name(name, _) => self.out.push_str(name),
+            Pattern::Literal(_, pos) => {
+                let index = self.token(pos);
+                if self.layout.tokens[index].0 == Token::OpSubtract {
+                    self.out.push('-');
+                    self.out.push_str(&self.layout.tokens[index + 1].1);
+                } else {
+                    self.out.push_str(&self.layout.tokens[index].1);
+                }
+            }
+            Pattern::Variant { name, args, .. } => {
+                self.out.push_str(name);
+                self.out.push('(');
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.pattern(arg);
+                }
+                self.out.push(')');
+            }
+            Pattern::Record { name, fields, pos } => {
+                self.out.push_str(name);
+                self.out.push_str(" {");
+                for (i, (field, pattern)) in fields.iter().enumerate() {
+                    self.out.push_str(if i > 0 { ", " } else { " " });
+                    self.out.push_str(field);
+                    match pattern {
+                        Pattern::Name(name, _) if name == field => (),
+                        pattern => {
+                            self.out.push_str(": ");
+                            self.pattern(pattern);
+                        }
+                    }
+                }
+                // `..` only shows that fields are left out, so it is kept as
+                // it was written.
+                let close = self.closing(self.token(pos) + 1);
+                if self.pretty && self.layout.tokens[close - 1].0 == Token::DotDot {
+                    self.out
+                        .push_str(if fields.is_empty() { " .." } else { ", .." });
+                }
+                self.out.push_str(" }");
+            }
+            Pattern::List { items, rest, .. } => {
+                self.out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.pattern(item);
+                }
+                if let Some(rest) = rest {
+                    if !items.is_empty() {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str("..");
+                    if let Pattern::Name(..) = **rest {
+                        self.pattern(rest);
+                    }
+                }
+                self.out.push(']');
+            }
+        }
+    }
+}
+
+fn block_items(block: &Block) -> Vec<&Exp> {
+    block
+        .statements
+        .iter()
+        .chain(block.value.as_deref())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        format(Source {
+            path: "test.omg".to_string(),
+            source: source.to_string(),
+        })
+        .unwrap()
+    }
+
+    /// Formats the source and checks that formatting the output again
+    /// leaves it as it is.
+    fn check(source: &str, expected: &str) {
+        let formatted = fmt(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(fmt(&formatted), expected);
+    }
+
+    #[test]
+    fn layout() {
+        check(
+            "let a=1;fn f(Int x,y){if x>y{return x}else{print( y );};x}\nf(a,2)",
+            "let a = 1;\nfn f(Int x, y) {\n    if x > y {\n        return x\n    } else {\n        \
+             print(y);\n    }\n    x\n}\nf(a, 2)\n",
+        );
+        check(
+            "record Point{Int x;y;}\nevent Empty{}\ntype Shape<T> =Circle(T r)|Square;\n\
+             run(Main e){async{f();g()}}",
+            "record Point {\n    Int x;\n    y;\n}\nevent Empty { }\ntype Shape<T> = Circle(T r) \
+             | Square;\nrun (Main e) {\n    async {\n        f();\n        g();\n    }\n}\n",
+        );
+        check(
+            "match s{Circle(r)if r>1=>{r}Point{x,y:0,..}=>x,[a,..rest]=>-1,[..]=>2,-3=>Ok(p)?.x}",
+            "match s {\n    Circle(r) if r > 1 => {\n        r\n    },\n    \
+             Point { x, y: 0, .. } => x,\n    [a, ..rest] => -1,\n    [..] => 2,\n    \
+             -3 => Ok(p)?.x,\n}\n",
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        check(
+            "// Header.\n\n\n/// Docs.\nfn f() { // Opens.\n\n    a; /* Trailing. */\n\n\n\
+             // Own line.\n    b\n    // Before close.\n}\nlet xs = [1, // One.\n\n    2];\n",
+            "// Header.\n\n/// Docs.\nfn f() { // Opens.\n    a; /* Trailing. */\n\n    \
+             // Own line.\n    b\n    // Before close.\n}\nlet xs = [1, 2]; // One.\n",
+        );
+        check(
+            "fn f() {\n    // Nothing yet.\n}\n",
+            "fn f() {\n    // Nothing yet.\n}\n",
+        );
+    }
+
+    #[test]
+    fn parentheses() {
+        check(
+            "let a = ((1 + 2)) * (3 - (4 - 5)) - (6 - 7);",
+            "let a = (1 + 2) * (3 - (4 - 5)) - (6 - 7);\n",
+        );
+        check(
+            "(2 ** 3) ** (2 ** 1); -(a ** 2); (-a) ** 2; !(a && b); (x = 1) + (return 2);",
+            "(2 ** 3) ** 2 ** 1;\n-a ** 2;\n(-a) ** 2;\n!(a && b);\n(x = 1) + (return 2);\n",
+        );
+        check("2 ** (-1); a * (-b);", "2 ** -1;\na * -b;\n");
+        check("(a + b).c; (-xs)[0]; f()?", "(a + b).c;\n(-xs)[0];\nf()?\n");
+    }
+
+    #[test]
+    fn semicolons() {
+        check(
+            "if a { 1 } else { 2 };\n-b;\nwhile a { };\n[1];\nfor x in xs { }\nf()",
+            "if a {\n    1\n} else {\n    2\n};\n-b;\nwhile a { };\n[1];\nfor x in xs { }\nf()\n",
+        );
+        check("fn f() { if a { }; }", "fn f() {\n    if a { };\n}\n");
+        check("fn f() { if a { } }", "fn f() {\n    if a { }\n}\n");
+    }
+
+    #[test]
+    fn literals_are_kept() {
+        check(
+            "let a = 0xff + 1_000 + 1e3 + \"\\u{1F600}\\n\"; match a { -0b1 => 1, _ => 2, }",
+            "let a = 0xff + 1_000 + 1e3 + \"\\u{1F600}\\n\";\n\
+             match a {\n    -0b1 => 1,\n    _ => 2,\n}\n",
+        );
+    }
+
+    #[test]
+    fn meaning_tells_groupings_apart() {
+        let meaning = |source: &str| {
+            let tokens = lexer(Source {
+                path: "test.omg".to_string(),
+                source: source.to_string(),
+            })
+            .unwrap();
+            let exp = parse_block(&mut tokens.clone()).unwrap();
+            Printer::new(&Layout::new(&tokens), false).root(&exp)
+        };
+        assert_eq!(meaning("a - b - c"), meaning("((a - b)) - c // c"));
+        assert_ne!(meaning("a - b - c"), meaning("a - (b - c)"));
+        assert_ne!(meaning("if a { }"), meaning("if a { };"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = format(Source {
+            path: "test.omg".to_string(),
+            source: "let a = ;".to_string(),
+        })
+        .unwrap_err();
+        assert_eq!(error.msg, "Expected identifier or number found ;");
+    }
+}
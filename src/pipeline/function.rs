@@ -1,6 +1,13 @@
 use crate::core_lib::Native;
+use crate::pipeline::UserFunction;
+use crate::value::DataType;
+use std::sync::Arc;
 
 #[derive(Clone)]
-pub enum Function { 
+#[allow(clippy::enum_variant_names)]
+pub enum Function {
     NativeFunction(Native),
-}
\ No newline at end of file
+    UserFunction(Arc<UserFunction>),
+    /// Creates the variant at the index of the data type.
+    Constructor(Arc<DataType>, usize),
+}
@@ -1,30 +1,65 @@
 use super::source::Source;
-use super::tokens::Tokens;
+use super::tokens::{Tokens, TriviaKind};
 use crate::error::{OmgError, Position, Result};
 use crate::pipeline::tokens::Token;
-use logos::{Extras, Logos};
+use logos::internal::LexerInternal;
+use logos::{Extras, Lexer, Logos};
 
 pub fn lexer(source: Source) -> Result<Tokens> {
+    lexer_at(source, 0)
+}
+
+/// Lexes source that starts at the given line of something longer.
+pub fn lexer_at(source: Source, line: u64) -> Result<Tokens> {
     let mut tokens = Tokens::new(source.path.clone());
     let mut lexer = TokenType::lexer(&source.source[..]);
-    let mut pos = Pos { line: 0, column: 0 };
+    let mut pos = Pos { line, column: 0 };
+    // The line the previous token or comment ended on.
+    let mut end_line = None;
     loop {
-        if lexer.token == TokenType::Error {
-            return Err(OmgError::new(
-                format!("Found unknown character in \"{}\" in file.", lexer.slice()),
-                Position::new(source.path).with_pos(pos.line, pos.column),
-            ));
+        if let Some(end) = end_line {
+            if pos.line > end + 1 && lexer.token != TokenType::End {
+                tokens.push_trivia(TriviaKind::BlankLine, String::new(), end + 1, 0);
+            }
+        }
+        match lexer.token {
+            TokenType::Error if lexer.slice().starts_with("/*") => {
+                return Err(OmgError::new(
+                    "Found block comment that is never closed.",
+                    Position::new(source.path).with_pos(pos.line, pos.column),
+                ));
+            }
+            TokenType::Error => {
+                return Err(OmgError::new(
+                    format!("Found unknown character in \"{}\" in file.", lexer.slice()),
+                    Position::new(source.path).with_pos(pos.line, pos.column),
+                ));
+            }
+            TokenType::LineComment | TokenType::BlockComment => tokens.push_trivia(
+                TriviaKind::Comment,
+                lexer.slice().to_string(),
+                pos.line,
+                pos.column,
+            ),
+            TokenType::DocComment => tokens.push_trivia(
+                TriviaKind::DocComment,
+                lexer.slice().to_string(),
+                pos.line,
+                pos.column,
+            ),
+            token_type => tokens.push(
+                to_token(token_type),
+                lexer.slice().to_string(),
+                pos.line,
+                pos.column,
+            ),
         }
-        tokens.push(
-            to_token(lexer.token),
-            lexer.slice().to_string(),
-            pos.line,
-            pos.column,
-        );
         if lexer.token == TokenType::End {
             break;
         }
-        pos.column += lexer.slice().len() as u64;
+        let newlines = lexer.slice().matches('\n').count() as u64;
+        end_line = Some(pos.line + newlines);
+        lexer.extras.on_slice(lexer.slice());
         lexer.advance();
         pos = lexer.extras.update_pos(pos);
     }
@@ -36,22 +71,62 @@ fn to_token(token_type: TokenType) -> Token {
     match token_type {
         TokenType::End => Token::EndOfFile,
         TokenType::Error => Token::EndOfFile,
+        TokenType::LineComment => Token::EndOfFile,
+        TokenType::DocComment => Token::EndOfFile,
+        TokenType::BlockComment => Token::EndOfFile,
         TokenType::Identifier => Token::Identifier,
         TokenType::Number => Token::Number,
+        TokenType::String => Token::String,
         TokenType::True => Token::True,
         TokenType::False => Token::False,
+        TokenType::If => Token::If,
+        TokenType::Else => Token::Else,
+        TokenType::While => Token::While,
+        TokenType::For => Token::For,
+        TokenType::In => Token::In,
+        TokenType::Break => Token::Break,
+        TokenType::Continue => Token::Continue,
+        TokenType::Fn => Token::Fn,
+        TokenType::Return => Token::Return,
+        TokenType::Record => Token::Record,
+        TokenType::Event => Token::Event,
+        TokenType::New => Token::New,
+        TokenType::Run => Token::Run,
+        TokenType::Async => Token::Async,
+        TokenType::Type => Token::Type,
+        TokenType::Match => Token::Match,
+        TokenType::Let => Token::Let,
+        TokenType::Var => Token::Var,
         TokenType::ParenthesesOpen => Token::ParenthesesOpen,
         TokenType::ParenthesesClose => Token::ParenthesesClose,
+        TokenType::BraceOpen => Token::BraceOpen,
+        TokenType::BraceClose => Token::BraceClose,
+        TokenType::BracketOpen => Token::BracketOpen,
+        TokenType::BracketClose => Token::BracketClose,
         TokenType::Comma => Token::Comma,
+        TokenType::Dot => Token::Dot,
+        TokenType::DotDot => Token::DotDot,
         TokenType::Semicolon => Token::Semicolon,
+        TokenType::Colon => Token::Colon,
+        TokenType::Question => Token::Question,
         TokenType::Assignment => Token::Assignment,
+        TokenType::Arrow => Token::Arrow,
+        TokenType::Bar => Token::Bar,
         TokenType::OpAdd => Token::OpAdd,
         TokenType::OpSubtract => Token::OpSubtract,
         TokenType::OpMultiply => Token::OpMultiply,
         TokenType::OpDivide => Token::OpDivide,
+        TokenType::OpRemainder => Token::OpRemainder,
+        TokenType::OpPower => Token::OpPower,
         TokenType::OpEqual => Token::OpEqual,
+        TokenType::OpNotEqual => Token::OpNotEqual,
         TokenType::OpGreaterThan => Token::OpGreaterThan,
+        TokenType::OpGreaterEqual => Token::OpGreaterEqual,
         TokenType::OpLessThan => Token::OpLessThan,
+        TokenType::OpLessEqual => Token::OpLessEqual,
+        TokenType::OpAnd => Token::OpAnd,
+        TokenType::OpOr => Token::OpOr,
+        TokenType::OpNot => Token::OpNot,
     }
 }
 
@@ -81,6 +156,21 @@ impl TokenExtra {
     }
 }
 
+impl TokenExtra {
+    /// Counts the lines and columns covered by a token, as tokens like block
+    /// comments and strings may span multiple lines.
+    fn on_slice(&mut self, slice: &str) {
+        for c in slice.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+}
+
 impl Extras for TokenExtra {
     fn on_advance(&mut self) {}
 
@@ -106,30 +196,118 @@ enum TokenType {
     #[regex = "[a-zA-Z_][a-zA-Z0-9_]*"]
     Identifier,
 
-    #[regex = "\\d+"]
+    #[token("0", callback = "zero_number")]
+    #[regex("[1-9]", callback = "number")]
     Number,
 
+    #[regex = "\"([^\"\\\\]|\\\\.)*\""]
+    String,
+
     #[token = "true"]
     True,
 
     #[token = "false"]
     False,
 
+    #[token = "if"]
+    If,
+
+    #[token = "else"]
+    Else,
+
+    #[token = "while"]
+    While,
+
+    #[token = "for"]
+    For,
+
+    #[token = "in"]
+    In,
+
+    #[token = "break"]
+    Break,
+
+    #[token = "continue"]
+    Continue,
+
+    #[token = "fn"]
+    Fn,
+
+    #[token = "return"]
+    Return,
+
+    #[token = "record"]
+    Record,
+
+    #[token = "event"]
+    Event,
+
+    #[token = "new"]
+    New,
+
+    #[token = "run"]
+    Run,
+
+    #[token = "async"]
+    Async,
+
+    #[token = "type"]
+    Type,
+
+    #[token = "match"]
+    Match,
+
+    #[token = "let"]
+    Let,
+
+    #[token = "var"]
+    Var,
+
     #[token = "("]
     ParenthesesOpen,
 
     #[token = ")"]
     ParenthesesClose,
 
+    #[token = "{"]
+    BraceOpen,
+
+    #[token = "}"]
+    BraceClose,
+
+    #[token = "["]
+    BracketOpen,
+
+    #[token = "]"]
+    BracketClose,
+
     #[token = ","]
     Comma,
 
+    #[token = "."]
+    Dot,
+
+    #[token = ".."]
+    DotDot,
+
     #[token = ";"]
     Semicolon,
 
+    #[token = ":"]
+    Colon,
+
+    #[token = "?"]
+    Question,
+
     #[token = "="]
     Assignment,
 
+    #[token = "=>"]
+    Arrow,
+
+    #[token = "|"]
+    Bar,
+
     #[token = "+"]
     OpAdd,
 
@@ -142,12 +320,305 @@ enum TokenType {
     #[token = "/"]
     OpDivide,
 
+    #[token = "%"]
+    OpRemainder,
+
+    #[token = "**"]
+    OpPower,
+
+    #[token = "//"]
+    #[callback = "line_comment"]
+    LineComment,
+
+    #[token = "///"]
+    #[callback = "doc_comment"]
+    DocComment,
+
+    #[token = "/*"]
+    #[callback = "block_comment"]
+    BlockComment,
+
     #[token = "=="]
     OpEqual,
 
+    #[token = "!="]
+    OpNotEqual,
+
     #[token = ">"]
     OpGreaterThan,
 
+    #[token = ">="]
+    OpGreaterEqual,
+
     #[token = "<"]
     OpLessThan,
+
+    #[token = "<="]
+    OpLessEqual,
+
+    #[token = "&&"]
+    OpAnd,
+
+    #[token = "||"]
+    OpOr,
+
+    #[token = "!"]
+    OpNot,
+}
+
+/// Numbers are scanned by hand as logos can't look ahead to tell the `.` in
+/// `1.5` apart from a `.` that follows a number. The parser validates the
+/// digits, so trailing letters end up in the token and get a proper error.
+fn number<'source, S>(lex: &mut Lexer<TokenType, S>)
+where
+    S: logos::Source<'source>,
+{
+    scan_number(lex, false)
+}
+
+/// A leading `0` may start a hex or binary literal.
+fn zero_number<'source, S>(lex: &mut Lexer<TokenType, S>)
+where
+    S: logos::Source<'source>,
+{
+    let radix = matches!(lex.read(), b'x' | b'X' | b'b' | b'B');
+    scan_number(lex, radix)
+}
+
+fn scan_number<'source, S>(lex: &mut Lexer<TokenType, S>, radix: bool)
+where
+    S: logos::Source<'source>,
+{
+    let mut fraction = radix;
+    let mut exponent = false;
+    loop {
+        let byte = lex.read();
+        match byte {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' => lex.bump(1),
+            b'.' if !fraction && next_is_digit(lex) => {
+                fraction = true;
+                lex.bump(1)
+            }
+            b'+' | b'-' if exponent && next_is_digit(lex) => lex.bump(1),
+            _ => return,
+        }
+        exponent = !radix && (byte == b'e' || byte == b'E');
+        if exponent {
+            // No fraction after the exponent has started.
+            fraction = true;
+        }
+    }
+}
+
+fn next_is_digit<'source, S>(lex: &mut Lexer<TokenType, S>) -> bool
+where
+    S: logos::Source<'source>,
+{
+    match lex.read_bytes::<[u8; 2]>() {
+        Some([_, next]) => next.is_ascii_digit(),
+        None => false,
+    }
+}
+
+fn line_comment<'source, S>(lex: &mut Lexer<TokenType, S>)
+where
+    S: logos::Source<'source>,
+{
+    loop {
+        match lex.read() {
+            0 | b'\n' => break,
+            _ => lex.bump(1),
+        }
+    }
+}
+
+/// `///` starts a doc comment, but `////` and longer are plain comments.
+fn doc_comment<'source, S>(lex: &mut Lexer<TokenType, S>)
+where
+    S: logos::Source<'source>,
+{
+    if lex.read() == b'/' {
+        lex.token = TokenType::LineComment;
+    }
+    line_comment(lex);
+}
+
+/// Block comments nest, so every `/*` needs its own matching `*/`.
+fn block_comment<'source, S>(lex: &mut Lexer<TokenType, S>)
+where
+    S: logos::Source<'source>,
+{
+    let mut depth = 1;
+    while depth > 0 {
+        match lex.read() {
+            0 => {
+                lex.token = TokenType::Error;
+                return;
+            }
+            b'/' => {
+                if lex.next() == b'*' {
+                    lex.bump(1);
+                    depth += 1;
+                }
+            }
+            b'*' => {
+                if lex.next() == b'/' {
+                    lex.bump(1);
+                    depth -= 1;
+                }
+            }
+            _ => lex.bump(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> Result<Tokens> {
+        lexer(Source {
+            path: "test.omg".to_string(),
+            source: source.to_string(),
+        })
+    }
+
+    fn collect(source: &str) -> Vec<(Token, String)> {
+        let mut tokens = lex(source).unwrap();
+        let mut result = Vec::new();
+        loop {
+            result.push((tokens.current(), tokens.position().to_string()));
+            if tokens.current() == Token::EndOfFile {
+                return result;
+            }
+            tokens.next();
+        }
+    }
+
+    #[test]
+    fn line_comment() {
+        assert_eq!(
+            collect("a // comment\nb"),
+            vec![
+                (Token::Identifier, "test.omg:0:0".to_string()),
+                (Token::Identifier, "test.omg:1:0".to_string()),
+                (Token::EndOfFile, "test.omg:1:1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_comment() {
+        assert_eq!(
+            collect("a /* one\n two */ b / c"),
+            vec![
+                (Token::Identifier, "test.omg:0:0".to_string()),
+                (Token::Identifier, "test.omg:1:8".to_string()),
+                (Token::OpDivide, "test.omg:1:10".to_string()),
+                (Token::Identifier, "test.omg:1:12".to_string()),
+                (Token::EndOfFile, "test.omg:1:13".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_block_comment() {
+        assert_eq!(
+            collect("/* a /* b */ c */ d"),
+            vec![
+                (Token::Identifier, "test.omg:0:18".to_string()),
+                (Token::EndOfFile, "test.omg:0:19".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_block_comment() {
+        let err = lex("a /* b /* c */").unwrap_err();
+        assert_eq!(err.pos.to_string(), "test.omg:0:2");
+    }
+
+    #[test]
+    fn logic_tokens() {
+        let tokens: Vec<_> = collect("!a && b || c != d >= e <= f")
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::OpNot,
+                Token::Identifier,
+                Token::OpAnd,
+                Token::Identifier,
+                Token::OpOr,
+                Token::Identifier,
+                Token::OpNotEqual,
+                Token::Identifier,
+                Token::OpGreaterEqual,
+                Token::Identifier,
+                Token::OpLessEqual,
+                Token::Identifier,
+                Token::EndOfFile,
+            ]
+        );
+    }
+
+    #[test]
+    fn pattern_tokens() {
+        let tokens: Vec<_> = collect("a => [b, ..c] | d.e")
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier,
+                Token::Arrow,
+                Token::BracketOpen,
+                Token::Identifier,
+                Token::Comma,
+                Token::DotDot,
+                Token::Identifier,
+                Token::BracketClose,
+                Token::Bar,
+                Token::Identifier,
+                Token::Dot,
+                Token::Identifier,
+                Token::EndOfFile,
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_are_trivia() {
+        let mut tokens = lex("/// The answer.\n//// Not docs.\na /* b */\n\n\n// c\nd").unwrap();
+        assert_eq!(tokens.current(), Token::Identifier);
+        let trivia: Vec<_> = tokens
+            .trivia()
+            .iter()
+            .map(|trivia| (trivia.kind, trivia.slice.as_str()))
+            .collect();
+        assert_eq!(
+            trivia,
+            vec![
+                (TriviaKind::DocComment, "/// The answer."),
+                (TriviaKind::Comment, "//// Not docs."),
+            ]
+        );
+        tokens.next();
+        let trivia: Vec<_> = tokens
+            .trivia()
+            .iter()
+            .map(|trivia| (trivia.kind, trivia.line))
+            .collect();
+        assert_eq!(
+            trivia,
+            vec![
+                (TriviaKind::Comment, 2),
+                (TriviaKind::BlankLine, 3),
+                (TriviaKind::Comment, 5),
+            ]
+        );
+    }
 }
@@ -1,14 +1,18 @@
 use crate::pipeline::Function;
+use crate::value::{DataType, RecordType};
 use im::HashMap;
+use std::sync::Arc;
 
 pub struct Module {
     functions: HashMap<String, Function>,
+    types: HashMap<String, Arc<RecordType>>,
 }
 
 impl Module {
     pub fn new() -> Self {
         Module {
             functions: HashMap::new(),
+            types: HashMap::new(),
         }
     }
 
@@ -18,10 +22,43 @@ impl Module {
     {
         Module {
             functions: self.functions.update(name.into(), function),
+            types: self.types.clone(),
+        }
+    }
+
+    /// Adds the variants of the data type as the functions that create them.
+    pub fn add_data(&self, data_type: &Arc<DataType>) -> Self {
+        let mut functions = self.functions.clone();
+        for (index, variant) in data_type.variants.iter().enumerate() {
+            let constructor = Function::Constructor(Arc::clone(data_type), index);
+            functions.insert(variant.name.clone(), constructor);
+        }
+        Module {
+            functions,
+            types: self.types.clone(),
         }
     }
 
     pub fn get_function(&self, name: &str) -> Option<Function> {
         self.functions.get(name).cloned()
     }
+
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(|name| name.as_str())
+    }
+
+    pub fn add_type(&self, record_type: Arc<RecordType>) -> Self {
+        Module {
+            functions: self.functions.clone(),
+            types: self.types.update(record_type.name.clone(), record_type),
+        }
+    }
+
+    pub fn type_names(&self) -> impl Iterator<Item = &str> {
+        self.types.keys().map(|name| name.as_str())
+    }
+
+    pub fn get_type(&self, name: &str) -> Option<Arc<RecordType>> {
+        self.types.get(name).cloned()
+    }
 }
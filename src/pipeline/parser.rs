@@ -1,80 +1,832 @@
 use crate::pipeline::ast::*;
 use crate::{
-    error::{OmgError, Result},
-    pipeline::{Token, Tokens},
-    value::Value,
+    error::{OmgError, Position, Result},
+    pipeline::{Token, Tokens, TriviaKind},
+    value::{RecordKind, Value},
 };
+use std::convert::TryFrom;
+use std::sync::Arc;
 
 pub fn parse_block(tokens: &mut Tokens) -> Result<Exp> {
-    let mut statements = Vec::new();
     let pos = tokens.position();
+    parse_statements(tokens, Token::EndOfFile, pos)
+}
+
+/// Parses statements until the `end` token, leaving it as the current token.
+/// Statements are separated by `;`, except after a statement that ends with a
+/// `}`. A last statement without a `;` becomes the value of the block.
+fn parse_statements(tokens: &mut Tokens, end: Token, pos: Position) -> Result<Exp> {
+    let mut statements = Vec::new();
+    let mut value = None;
     loop {
-        let exp = parse(tokens)?;
+        match tokens.current() {
+            token if token == end => break,
+            Token::Semicolon => {
+                tokens.next();
+                continue;
+            }
+            Token::EndOfFile => {
+                return Err(OmgError::new(
+                    "Expected } found end of file",
+                    tokens.position(),
+                ))
+            }
+            _ => (),
+        }
+
+        let exp = match tokens.current() {
+            Token::Run if end == Token::EndOfFile => parse_run(tokens)?,
+            _ => parse(tokens)?,
+        };
+        let ends_with_brace = tokens.current() == Token::BraceClose;
         tokens.next();
         let token = tokens.current();
-        let slice = tokens.slice();
         if token == Token::Semicolon {
             statements.push(exp);
             tokens.next();
-        } else if token != Token::EndOfFile {
+        } else if token == end {
+            value = Some(exp);
+        } else if ends_with_brace {
+            statements.push(exp);
+        } else {
             return Err(OmgError::new(
-                format!("Expected ; found {}", slice),
+                format!("Expected ; found {}", tokens.slice()),
                 tokens.position(),
             ));
         }
-        if tokens.current() == Token::EndOfFile {
-            break;
-        }
     }
-    Ok(Exp::new_block(statements, pos))
+    Ok(match value {
+        Some(value) => Exp::new_block_value(statements, Box::new(value), pos),
+        None => Exp::new_block(statements, pos),
+    })
 }
 
 pub fn parse(tokens: &mut Tokens) -> Result<Exp> {
-    // parse left hand side;
-    let lhs = match tokens.current() {
+    parse_expression(tokens, 0)
+}
+
+fn parse_expression(tokens: &mut Tokens, min_precedence: u8) -> Result<Exp> {
+    let primary = parse_primary(tokens)?;
+    let mut lhs = parse_postfix(tokens, primary)?;
+
+    loop {
+        let op_type = match to_op_type(tokens.peek()) {
+            Some(op_type) => op_type,
+            None => return Ok(lhs),
+        };
+        let precedence = op_type.precedence();
+        if precedence < min_precedence {
+            return Ok(lhs);
+        }
+
+        // For left associative operators the right hand side may only bind
+        // operators that are strictly tighter than this one.
+        tokens.next(); // at Operator
+        let pos = tokens.position();
+        tokens.next(); // at next expression
+        let min_precedence = if op_type.right_associative() {
+            precedence
+        } else {
+            precedence + 1
+        };
+        let rhs = parse_expression(tokens, min_precedence)?;
+        lhs = Exp::new_operator(op_type, Box::new(lhs), Box::new(rhs), pos);
+    }
+}
+
+fn parse_primary(tokens: &mut Tokens) -> Result<Exp> {
+    match tokens.current() {
         Token::Identifier => parse_identifier(tokens),
-        Token::Number => match tokens.slice().parse() {
-            Ok(i) => Ok(Exp::new_literal(Value::Number(i), tokens.position())),
-            Err(err) => Err(OmgError::new(
-                format!(
-                    "Unable to covert {} into an integer: {}",
-                    tokens.slice(),
-                    err
-                ),
-                tokens.position(),
-            )),
-        },
+        Token::Number => Ok(Exp::new_literal(
+            parse_number(tokens.slice(), tokens.position())?,
+            tokens.position(),
+        )),
+        Token::String => Ok(Exp::new_literal(
+            Value::from_string(unescape(tokens.slice(), tokens.position())?),
+            tokens.position(),
+        )),
         Token::True => Ok(Exp::new_literal(Value::True, tokens.position())),
         Token::False => Ok(Exp::new_literal(Value::False, tokens.position())),
+        Token::ParenthesesOpen => parse_group(tokens),
+        Token::BraceOpen => parse_brace_block(tokens),
+        Token::BracketOpen => {
+            let pos = tokens.position();
+            let items = parse_list_items(tokens, Token::BracketClose)?;
+            Ok(Exp::new_list(items, pos))
+        }
+        Token::If => parse_if(tokens),
+        Token::While => parse_while(tokens),
+        Token::For => parse_for(tokens),
+        Token::Break => Ok(Exp::Break(tokens.position())),
+        Token::Continue => Ok(Exp::Continue(tokens.position())),
+        Token::Fn => parse_function(tokens),
+        Token::Record => parse_record(tokens, RecordKind::Record),
+        Token::Event => parse_record(tokens, RecordKind::Event),
+        Token::New => parse_new(tokens),
+        Token::Run => Err(OmgError::new(
+            "run handlers can only be declared at the top level of a file",
+            tokens.position(),
+        )),
+        Token::Return => parse_return(tokens),
+        Token::Async => parse_async(tokens),
+        Token::Type => parse_data(tokens),
+        Token::Match => parse_match(tokens),
+        Token::Let => parse_declaration(tokens, Declaration::Let),
+        Token::Var => parse_declaration(tokens, Declaration::Var),
+        Token::OpSubtract => parse_unary(tokens, UnaryType::Negate),
+        Token::OpAdd => parse_unary(tokens, UnaryType::Plus),
+        Token::OpNot => parse_unary(tokens, UnaryType::Not),
         _ => Err(OmgError::new(
             format!("Expected identifier or number found {}", tokens.slice()),
             tokens.position(),
         )),
-    }?;
-
-    let op_type = match tokens.peek() {
-        Token::OpAdd => OpType::Add,
-        Token::OpSubtract => OpType::Subtract,
-        Token::OpMultiply => OpType::Multiply,
-        Token::OpDivide => OpType::Divide,
-        Token::OpEqual => OpType::Equal,
-        Token::OpGreaterThan => OpType::GreaterThan,
-        Token::OpLessThan => OpType::LessThan,
-        _ => return Ok(lhs),
+    }
+}
+
+fn parse_unary(tokens: &mut Tokens, unary_type: UnaryType) -> Result<Exp> {
+    let pos = tokens.position();
+    tokens.next(); // at operand
+    let exp = parse_expression(tokens, UNARY_PRECEDENCE)?;
+    Ok(Exp::new_unary(unary_type, Box::new(exp), pos))
+}
+
+/// Parses `{ statements }`, leaving the `}` as the current token.
+fn parse_brace_block(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    tokens.next(); // at first statement
+    parse_statements(tokens, Token::BraceClose, pos)
+}
+
+fn expect_brace_block(tokens: &mut Tokens) -> Result<Exp> {
+    tokens.next();
+    if tokens.current() != Token::BraceOpen {
+        return Err(OmgError::new(
+            format!("Expected {{ found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    parse_brace_block(tokens)
+}
+
+fn parse_if(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    tokens.next(); // at condition
+    let condition = parse(tokens)?;
+    let then = expect_brace_block(tokens)?;
+    let otherwise = if tokens.expect(Token::Else) {
+        if tokens.expect(Token::If) {
+            Some(Box::new(parse_if(tokens)?))
+        } else {
+            Some(Box::new(expect_brace_block(tokens)?))
+        }
+    } else {
+        None
     };
+    Ok(Exp::new_if(
+        Box::new(condition),
+        Box::new(then),
+        otherwise,
+        pos,
+    ))
+}
+
+fn parse_while(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    tokens.next(); // at condition
+    let condition = parse(tokens)?;
+    let body = expect_brace_block(tokens)?;
+    Ok(Exp::new_while(Box::new(condition), Box::new(body), pos))
+}
+
+fn parse_for(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    let name = expect_identifier(tokens, "loop variable")?;
+    if !tokens.expect(Token::In) {
+        tokens.next();
+        return Err(OmgError::new(
+            format!("Expected in found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    tokens.next(); // at collection
+    let collection = parse(tokens)?;
+    let body = expect_brace_block(tokens)?;
+    Ok(Exp::new_for(
+        name,
+        Box::new(collection),
+        Box::new(body),
+        pos,
+    ))
+}
 
-    tokens.next(); // at Operator
-    tokens.next(); // at next expression
-    let rhs = parse(tokens)?;
-    let pos = lhs.position();
-    Ok(Exp::new_operator(
-        op_type,
-        Box::new(lhs),
-        Box::new(rhs),
+fn parse_function(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    let doc = doc_comment(tokens);
+    let name = expect_identifier(tokens, "function name")?;
+    tokens.next();
+    if tokens.current() != Token::ParenthesesOpen {
+        return Err(OmgError::new(
+            format!("Expected ( found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let mut params = Vec::new();
+    if !tokens.expect(Token::ParenthesesClose) {
+        loop {
+            let (type_name, name) = parse_typed_name(tokens, "parameter name")?;
+            params.push(Param {
+                name,
+                type_name,
+                pos: tokens.position(),
+            });
+            tokens.next();
+            match tokens.current() {
+                Token::ParenthesesClose => break,
+                Token::Comma => (),
+                _ => {
+                    return Err(OmgError::new(
+                        format!("Expected ) or , found {}", tokens.slice()),
+                        tokens.position(),
+                    ))
+                }
+            };
+        }
+    }
+    let body = expect_brace_block(tokens)?;
+    Ok(Exp::new_function_def(
+        name,
+        params,
+        Box::new(body),
+        doc,
         pos,
     ))
 }
 
+/// Parses `record Name { Type field; other; }`, the field types are optional.
+fn parse_record(tokens: &mut Tokens, kind: RecordKind) -> Result<Exp> {
+    let pos = tokens.position();
+    let doc = doc_comment(tokens);
+    let name = expect_identifier(tokens, "type name")?;
+    tokens.next();
+    if tokens.current() != Token::BraceOpen {
+        return Err(OmgError::new(
+            format!("Expected {{ found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let mut fields = Vec::new();
+    while !tokens.expect(Token::BraceClose) {
+        let (type_name, name) = parse_typed_name(tokens, "field")?;
+        let field = FieldDef {
+            name,
+            type_name,
+            pos: tokens.position(),
+        };
+        if fields.iter().any(|f: &FieldDef| f.name == field.name) {
+            return Err(OmgError::new(
+                format!("Field {} is declared more than once", field.name),
+                field.pos,
+            ));
+        }
+        fields.push(field);
+        tokens.next();
+        if tokens.current() != Token::Semicolon {
+            return Err(OmgError::new(
+                format!("Expected ; found {}", tokens.slice()),
+                tokens.position(),
+            ));
+        }
+    }
+    Ok(Exp::new_record_def(name, kind, fields, doc, pos))
+}
+
+fn parse_run(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    tokens.next();
+    if tokens.current() != Token::ParenthesesOpen {
+        return Err(OmgError::new(
+            format!("Expected ( found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let event = expect_identifier(tokens, "event type")?;
+    let name = if tokens.peek() == Token::Identifier {
+        tokens.next();
+        Some(tokens.slice().to_string())
+    } else {
+        None
+    };
+    tokens.next();
+    if tokens.current() != Token::ParenthesesClose {
+        return Err(OmgError::new(
+            format!("Expected ) found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let body = expect_brace_block(tokens)?;
+    Ok(Exp::new_run(event, name, Box::new(body), pos))
+}
+
+fn parse_async(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    let statements = match expect_brace_block(tokens)? {
+        Exp::Block(block) => block
+            .statements
+            .into_iter()
+            .chain(block.value.map(|value| *value))
+            .collect(),
+        exp => vec![exp],
+    };
+    Ok(Exp::new_async(statements, pos))
+}
+
+/// Parses `type Name<T> = Variant(Type field) | Other`.
+fn parse_data(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    let doc = doc_comment(tokens);
+    let name = expect_identifier(tokens, "type name")?;
+    let mut params = Vec::new();
+    if tokens.expect(Token::OpLessThan) {
+        loop {
+            params.push(expect_identifier(tokens, "type parameter")?);
+            tokens.next();
+            match tokens.current() {
+                Token::OpGreaterThan => break,
+                Token::Comma => (),
+                _ => {
+                    return Err(OmgError::new(
+                        format!("Expected > or , found {}", tokens.slice()),
+                        tokens.position(),
+                    ))
+                }
+            }
+        }
+    }
+    tokens.next();
+    if tokens.current() != Token::Assignment {
+        return Err(OmgError::new(
+            format!("Expected = found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let mut variants: Vec<VariantDef> = Vec::new();
+    loop {
+        let variant_name = expect_identifier(tokens, "variant name")?;
+        let variant_pos = tokens.position();
+        if variants.iter().any(|v| v.name == variant_name) {
+            return Err(OmgError::new(
+                format!("Variant {} is declared more than once", variant_name),
+                variant_pos,
+            ));
+        }
+        let mut fields = Vec::new();
+        if tokens.expect(Token::ParenthesesOpen) && !tokens.expect(Token::ParenthesesClose) {
+            loop {
+                let (type_name, name) = parse_typed_name(tokens, "field")?;
+                fields.push(FieldDef {
+                    name,
+                    type_name,
+                    pos: tokens.position(),
+                });
+                tokens.next();
+                match tokens.current() {
+                    Token::ParenthesesClose => break,
+                    Token::Comma => (),
+                    _ => {
+                        return Err(OmgError::new(
+                            format!("Expected ) or , found {}", tokens.slice()),
+                            tokens.position(),
+                        ))
+                    }
+                }
+            }
+        }
+        variants.push(VariantDef {
+            name: variant_name,
+            fields,
+            pos: variant_pos,
+        });
+        if !tokens.expect(Token::Bar) {
+            break;
+        }
+    }
+    Ok(Exp::new_data_def(name, params, variants, doc, pos))
+}
+
+/// Parses `match exp { pattern if guard => value, }`, the `,` may be left
+/// out after a value that ends with `}`.
+fn parse_match(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    tokens.next(); // at the matched expression
+    let exp = parse(tokens)?;
+    tokens.next();
+    if tokens.current() != Token::BraceOpen {
+        return Err(OmgError::new(
+            format!("Expected {{ found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let mut arms = Vec::new();
+    while !tokens.expect(Token::BraceClose) {
+        tokens.next(); // at pattern
+        let pattern = parse_pattern(tokens)?;
+        let guard = if tokens.expect(Token::If) {
+            tokens.next(); // at guard
+            Some(Box::new(parse(tokens)?))
+        } else {
+            None
+        };
+        tokens.next();
+        if tokens.current() != Token::Arrow {
+            return Err(OmgError::new(
+                format!("Expected => found {}", tokens.slice()),
+                tokens.position(),
+            ));
+        }
+        tokens.next(); // at value
+        let value = parse(tokens)?;
+        let ends_with_brace = tokens.current() == Token::BraceClose;
+        arms.push(MatchArm {
+            pattern: Arc::new(pattern),
+            guard,
+            value: Box::new(value),
+        });
+        if !tokens.expect(Token::Comma) && !ends_with_brace && tokens.peek() != Token::BraceClose {
+            tokens.next();
+            return Err(OmgError::new(
+                format!("Expected , or }} found {}", tokens.slice()),
+                tokens.position(),
+            ));
+        }
+    }
+    Ok(Exp::new_match(Box::new(exp), arms, pos))
+}
+
+/// Parses the pattern starting at the current token, leaving its last token
+/// as the current token.
+fn parse_pattern(tokens: &mut Tokens) -> Result<Pattern> {
+    let pos = tokens.position();
+    match tokens.current() {
+        Token::Identifier if tokens.slice() == "_" => Ok(Pattern::Wildcard(pos)),
+        Token::Identifier => {
+            let name = tokens.slice().to_string();
+            if tokens.expect(Token::ParenthesesOpen) {
+                let args = parse_patterns(tokens, Token::ParenthesesClose)?;
+                Ok(Pattern::Variant { name, args, pos })
+            } else if tokens.expect(Token::BraceOpen) {
+                let mut fields = Vec::new();
+                while !tokens.expect(Token::BraceClose) {
+                    // `..` only shows that the other fields are left out.
+                    if tokens.expect(Token::DotDot) {
+                        tokens.next();
+                        if tokens.current() != Token::BraceClose {
+                            return Err(OmgError::new(
+                                format!("Expected }} found {}", tokens.slice()),
+                                tokens.position(),
+                            ));
+                        }
+                        break;
+                    }
+                    let field = expect_identifier(tokens, "field")?;
+                    let field_pos = tokens.position();
+                    let pattern = if tokens.expect(Token::Colon) {
+                        tokens.next(); // at pattern
+                        parse_pattern(tokens)?
+                    } else {
+                        Pattern::Name(field.clone(), field_pos)
+                    };
+                    fields.push((field, pattern));
+                    if !tokens.expect(Token::Comma) && tokens.peek() != Token::BraceClose {
+                        tokens.next();
+                        return Err(OmgError::new(
+                            format!("Expected , or }} found {}", tokens.slice()),
+                            tokens.position(),
+                        ));
+                    }
+                }
+                Ok(Pattern::Record { name, fields, pos })
+            } else {
+                Ok(Pattern::Name(name, pos))
+            }
+        }
+        Token::Number => Ok(Pattern::Literal(parse_number(tokens.slice(), pos.clone())?, pos)),
+        Token::OpSubtract if tokens.peek() == Token::Number => {
+            tokens.next();
+            let number = parse_number(tokens.slice(), tokens.position())?;
+            let negated = number.negate().map_err(|_| {
+                OmgError::new(format!("-{} is too large for an Int", tokens.slice()), pos.clone())
+            })?;
+            Ok(Pattern::Literal(negated, pos))
+        }
+        Token::String => Ok(Pattern::Literal(
+            Value::from_string(unescape(tokens.slice(), pos.clone())?),
+            pos,
+        )),
+        Token::True => Ok(Pattern::Literal(Value::True, pos)),
+        Token::False => Ok(Pattern::Literal(Value::False, pos)),
+        Token::BracketOpen => {
+            let mut items = Vec::new();
+            let mut rest = None;
+            while !tokens.expect(Token::BracketClose) {
+                tokens.next();
+                if tokens.current() == Token::DotDot {
+                    let rest_pos = tokens.position();
+                    rest = Some(Box::new(match tokens.peek() {
+                        Token::Identifier => {
+                            tokens.next();
+                            parse_pattern(tokens)?
+                        }
+                        _ => Pattern::Wildcard(rest_pos),
+                    }));
+                    tokens.next();
+                    if tokens.current() != Token::BracketClose {
+                        return Err(OmgError::new(
+                            format!("Expected ] found {}", tokens.slice()),
+                            tokens.position(),
+                        ));
+                    }
+                    break;
+                }
+                items.push(parse_pattern(tokens)?);
+                if !tokens.expect(Token::Comma) && tokens.peek() != Token::BracketClose {
+                    tokens.next();
+                    return Err(OmgError::new(
+                        format!("Expected , or ] found {}", tokens.slice()),
+                        tokens.position(),
+                    ));
+                }
+            }
+            Ok(Pattern::List { items, rest, pos })
+        }
+        _ => Err(OmgError::new(
+            format!("Expected pattern found {}", tokens.slice()),
+            pos,
+        )),
+    }
+}
+
+/// Parses comma separated patterns from the opening token up to and
+/// including the `close` token.
+fn parse_patterns(tokens: &mut Tokens, close: Token) -> Result<Vec<Pattern>> {
+    let mut patterns = Vec::new();
+    if tokens.expect(close) {
+        return Ok(patterns);
+    }
+    loop {
+        tokens.next();
+        patterns.push(parse_pattern(tokens)?);
+        tokens.next();
+        match tokens.current() {
+            token if token == close => return Ok(patterns),
+            Token::Comma => (),
+            _ => {
+                return Err(OmgError::new(
+                    format!(
+                        "Expected {} or , found {}",
+                        close_symbol(close),
+                        tokens.slice()
+                    ),
+                    tokens.position(),
+                ))
+            }
+        };
+    }
+}
+
+fn parse_new(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    let name = expect_identifier(tokens, "type name")?;
+    tokens.next();
+    if tokens.current() != Token::ParenthesesOpen {
+        return Err(OmgError::new(
+            format!("Expected ( found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    let args = parse_list_items(tokens, Token::ParenthesesClose)?;
+    Ok(Exp::new_new(name, args, pos))
+}
+
+/// Joins the `///` comments in front of the current token.
+fn doc_comment(tokens: &Tokens) -> Option<String> {
+    let docs: Vec<_> = tokens
+        .trivia()
+        .iter()
+        .filter(|trivia| trivia.kind == TriviaKind::DocComment)
+        .collect();
+    if docs.is_empty() {
+        return None;
+    }
+    let lines: Vec<&str> = docs
+        .iter()
+        .map(|trivia| {
+            let line = trivia.slice.trim_start_matches("///");
+            line.strip_prefix(' ').unwrap_or(line)
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+fn expect_identifier(tokens: &mut Tokens, what: &str) -> Result<String> {
+    tokens.next();
+    if tokens.current() != Token::Identifier {
+        return Err(OmgError::new(
+            format!("Expected {} found {}", what, tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    Ok(tokens.slice().to_string())
+}
+
+/// Parses `Type name` or just `name` from the next token, leaving the name as
+/// the current token.
+fn parse_typed_name(tokens: &mut Tokens, what: &str) -> Result<(Option<TypeName>, String)> {
+    let first = expect_identifier(tokens, what)?;
+    match tokens.peek() {
+        Token::Identifier | Token::OpLessThan => {
+            let type_name = parse_type(tokens)?;
+            Ok((Some(type_name), expect_identifier(tokens, what)?))
+        }
+        _ => Ok((None, first)),
+    }
+}
+
+/// Parses a type like `Int` or `List<String>` from the current identifier,
+/// leaving its last token as the current token.
+fn parse_type(tokens: &mut Tokens) -> Result<TypeName> {
+    let name = tokens.slice().to_string();
+    let pos = tokens.position();
+    let mut args = Vec::new();
+    if tokens.expect(Token::OpLessThan) {
+        loop {
+            expect_identifier(tokens, "type")?;
+            args.push(parse_type(tokens)?);
+            tokens.next();
+            match tokens.current() {
+                Token::OpGreaterThan => break,
+                Token::Comma => (),
+                _ => {
+                    return Err(OmgError::new(
+                        format!("Expected > or , found {}", tokens.slice()),
+                        tokens.position(),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(TypeName { name, args, pos })
+}
+
+fn parse_return(tokens: &mut Tokens) -> Result<Exp> {
+    let pos = tokens.position();
+    let value = match tokens.peek() {
+        Token::Semicolon | Token::BraceClose | Token::EndOfFile => None,
+        _ => {
+            tokens.next(); // at value
+            Some(Box::new(parse(tokens)?))
+        }
+    };
+    Ok(Exp::new_return(value, pos))
+}
+
+fn parse_group(tokens: &mut Tokens) -> Result<Exp> {
+    tokens.next(); // at inner expression
+    let exp = parse(tokens)?;
+    tokens.next();
+    if tokens.current() != Token::ParenthesesClose {
+        return Err(OmgError::new(
+            format!("Expected ) found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    Ok(exp)
+}
+
+/// Parses decimal, exponent, hex (`0x`) and binary (`0b`) literals, all of
+/// them may use `_` as a digit separator. Numbers without a fraction or an
+/// exponent are Ints.
+fn parse_number(slice: &str, pos: Position) -> Result<Value> {
+    let digits = slice.replace('_', "");
+    let radix = match digits.get(..2) {
+        Some("0x") | Some("0X") => Some(16),
+        Some("0b") | Some("0B") => Some(2),
+        _ => None,
+    };
+    let invalid = || OmgError::new(format!("{} is not a valid number", slice), pos.clone());
+    let too_large = || OmgError::new(format!("{} is too large for an Int", slice), pos.clone());
+    match radix {
+        Some(radix) => {
+            let n = u64::from_str_radix(&digits[2..], radix).map_err(|_| invalid())?;
+            i64::try_from(n).map(Value::Int).map_err(|_| too_large())
+        }
+        None if digits.bytes().all(|b| b.is_ascii_digit()) => {
+            // Only digits, so the parse can only fail on the size.
+            digits.parse().map(Value::Int).map_err(|_| too_large())
+        }
+        None if digits
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b"eE.+-".contains(&b)) =>
+        {
+            digits.parse().map(Value::Float).map_err(|_| invalid())
+        }
+        None => Err(invalid()),
+    }
+}
+
+/// Strips the quotes from a string literal and resolves its escape sequences.
+fn unescape(slice: &str, pos: Position) -> Result<String> {
+    let inner = &slice[1..slice.len() - 1];
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.char_indices();
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        let (index, escape) = match chars.next() {
+            Some(next) => next,
+            None => break,
+        };
+        // +1 for the opening quote and -1 to land on the backslash.
+        let escape_pos = pos.add(index as u64);
+        match escape {
+            'n' => string.push('\n'),
+            't' => string.push('\t'),
+            '"' => string.push('"'),
+            '\\' => string.push('\\'),
+            'u' => string.push(unescape_unicode(&mut chars, escape_pos)?),
+            _ => {
+                return Err(OmgError::new(
+                    format!("Unknown escape sequence \\{} in string", escape),
+                    escape_pos,
+                ))
+            }
+        }
+    }
+    Ok(string)
+}
+
+fn unescape_unicode(chars: &mut std::str::CharIndices, pos: Position) -> Result<char> {
+    let error = || OmgError::new("Expected unicode escape on the form \\u{...}", pos.clone());
+    if chars.next().map(|(_, c)| c) != Some('{') {
+        return Err(error());
+    }
+    let mut hex = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '}')) => break,
+            Some((_, c)) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+            _ => return Err(error()),
+        }
+    }
+    u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(std::char::from_u32)
+        .ok_or_else(|| {
+            OmgError::new(
+                format!("\\u{{{}}} is not a valid unicode code point", hex),
+                pos.clone(),
+            )
+        })
+}
+
+fn to_op_type(token: Token) -> Option<OpType> {
+    match token {
+        Token::OpAdd => Some(OpType::Add),
+        Token::OpSubtract => Some(OpType::Subtract),
+        Token::OpMultiply => Some(OpType::Multiply),
+        Token::OpDivide => Some(OpType::Divide),
+        Token::OpRemainder => Some(OpType::Remainder),
+        Token::OpPower => Some(OpType::Power),
+        Token::OpEqual => Some(OpType::Equal),
+        Token::OpNotEqual => Some(OpType::NotEqual),
+        Token::OpGreaterThan => Some(OpType::GreaterThan),
+        Token::OpGreaterEqual => Some(OpType::GreaterEqual),
+        Token::OpLessThan => Some(OpType::LessThan),
+        Token::OpLessEqual => Some(OpType::LessEqual),
+        Token::OpAnd => Some(OpType::And),
+        Token::OpOr => Some(OpType::Or),
+        _ => None,
+    }
+}
+
+/// Parses `let name = value` or `var name = value`, the name may have a type
+/// in front of it.
+fn parse_declaration(tokens: &mut Tokens, declaration: Declaration) -> Result<Exp> {
+    let (type_name, name) = parse_typed_name(tokens, "variable")?;
+    let pos = tokens.position();
+    if !tokens.expect(Token::Assignment) {
+        tokens.next();
+        return Err(OmgError::new(
+            format!("Expected = found {}", tokens.slice()),
+            tokens.position(),
+        ));
+    }
+    tokens.next(); // at value
+    let value = parse(tokens)?;
+    Ok(Exp::new_declaration(declaration, type_name, name, Box::new(value), pos))
+}
+
 fn parse_identifier(tokens: &mut Tokens) -> Result<Exp> {
     match tokens.peek() {
         Token::ParenthesesOpen => parse_call(tokens),
@@ -97,23 +849,649 @@ fn parse_call(tokens: &mut Tokens) -> Result<Exp> {
     let pos = tokens.position();
     let name = tokens.slice().to_string();
     tokens.next(); // ParenthesesOpen
-    let mut args = Vec::new();
-    if !tokens.expect(Token::ParenthesesClose) {
-        loop {
-            tokens.next();
-            args.push(parse(tokens)?);
-            tokens.next();
-            match tokens.current() {
-                Token::ParenthesesClose => break,
-                Token::Comma => (),
-                _ => {
+    let args = parse_list_items(tokens, Token::ParenthesesClose)?;
+    Ok(Exp::new_call(name, args, pos))
+}
+
+/// Parses comma separated expressions from the opening token up to and
+/// including the `close` token.
+fn parse_list_items(tokens: &mut Tokens, close: Token) -> Result<Vec<Exp>> {
+    let mut items = Vec::new();
+    if tokens.expect(close) {
+        return Ok(items);
+    }
+    loop {
+        tokens.next();
+        items.push(parse(tokens)?);
+        tokens.next();
+        match tokens.current() {
+            token if token == close => return Ok(items),
+            Token::Comma => (),
+            _ => {
+                return Err(OmgError::new(
+                    format!(
+                        "Expected {} or , found {}",
+                        close_symbol(close),
+                        tokens.slice()
+                    ),
+                    tokens.position(),
+                ))
+            }
+        };
+    }
+}
+
+fn close_symbol(token: Token) -> &'static str {
+    match token {
+        Token::BracketClose => "]",
+        _ => ")",
+    }
+}
+
+/// Parses indexing like `xs[0]` and field access like `p.x` that follows an
+/// expression.
+fn parse_postfix(tokens: &mut Tokens, exp: Exp) -> Result<Exp> {
+    let mut exp = exp;
+    loop {
+        match tokens.peek() {
+            Token::BracketOpen => {
+                tokens.next(); // at [
+                let pos = tokens.position();
+                tokens.next(); // at index
+                let index = parse(tokens)?;
+                tokens.next();
+                if tokens.current() != Token::BracketClose {
                     return Err(OmgError::new(
-                        format!("Expected ) or , found {}", tokens.slice()),
+                        format!("Expected ] found {}", tokens.slice()),
                         tokens.position(),
-                    ))
+                    ));
                 }
-            };
+                exp = Exp::new_index(Box::new(exp), Box::new(index), pos);
+            }
+            Token::Dot => {
+                tokens.next(); // at .
+                let name = expect_identifier(tokens, "field name")?;
+                let pos = tokens.position();
+                exp = match (exp, tokens.peek()) {
+                    (Exp::Variable(variable), Token::ParenthesesOpen) => {
+                        tokens.next(); // ParenthesesOpen
+                        let args = parse_list_items(tokens, Token::ParenthesesClose)?;
+                        Exp::new_method_call(variable.name, name, args, pos)
+                    }
+                    (_, Token::ParenthesesOpen) => {
+                        return Err(OmgError::new(
+                            format!("Methods can only be called on types, {} is not", name),
+                            pos,
+                        ))
+                    }
+                    (exp, _) => Exp::new_field(Box::new(exp), name, pos),
+                };
+            }
+            Token::Question => {
+                tokens.next(); // at ?
+                exp = Exp::new_try(Box::new(exp), tokens.position());
+            }
+            _ => return Ok(exp),
         }
     }
-    Ok(Exp::new_call(name, args, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{lexer, Source};
+
+    fn tokens(source: &str) -> Tokens {
+        lexer(Source {
+            path: "test.omg".to_string(),
+            source: source.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn eval(exp: &Exp) -> f64 {
+        match exp {
+            Exp::Literal(Literal {
+                value: Value::Float(n),
+                ..
+            }) => *n,
+            Exp::Literal(Literal {
+                value: Value::Int(n),
+                ..
+            }) => *n as f64,
+            Exp::Operator(op) => {
+                let (lhs, rhs) = (eval(&op.lhs), eval(&op.rhs));
+                match op.op_type {
+                    OpType::Add => lhs + rhs,
+                    OpType::Subtract => lhs - rhs,
+                    OpType::Multiply => lhs * rhs,
+                    OpType::Divide => lhs / rhs,
+                    OpType::Remainder => lhs % rhs,
+                    _ => panic!("Unexpected operator {:?}", op.op_type),
+                }
+            }
+            Exp::Unary(unary) => match unary.unary_type {
+                UnaryType::Negate => -eval(&unary.exp),
+                UnaryType::Plus => eval(&unary.exp),
+                UnaryType::Not => panic!("Unexpected operator !"),
+            },
+            _ => panic!("Unexpected expression {:?}", exp),
+        }
+    }
+
+    fn parse_str(source: &str) -> Exp {
+        parse(&mut tokens(source)).unwrap()
+    }
+
+    #[test]
+    fn multiply_before_add() {
+        assert_eq!(eval(&parse_str("2 * 3 + 4")), 10.0);
+        assert_eq!(eval(&parse_str("4 + 2 * 3")), 10.0);
+    }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(eval(&parse_str("10 - 4 - 3")), 3.0);
+        assert_eq!(eval(&parse_str("24 / 4 / 2")), 3.0);
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(eval(&parse_str("2 * (3 + 4)")), 14.0);
+        assert_eq!(eval(&parse_str("((1))")), 1.0);
+    }
+
+    #[test]
+    fn comparison_binds_loosest() {
+        match parse_str("1 + 2 == 3") {
+            Exp::Operator(op) => assert_eq!(op.op_type, OpType::Equal),
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn or_binds_looser_than_and() {
+        match parse_str("a || b && c >= 1") {
+            Exp::Operator(op) => {
+                assert_eq!(op.op_type, OpType::Or);
+                match *op.rhs {
+                    Exp::Operator(ref rhs) => assert_eq!(rhs.op_type, OpType::And),
+                    ref exp => panic!("Expected operator got {:?}", exp),
+                }
+            }
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+        match parse_str("!a == b") {
+            Exp::Operator(op) => match *op.lhs {
+                Exp::Unary(ref unary) => assert_eq!(unary.unary_type, UnaryType::Not),
+                ref exp => panic!("Expected unary got {:?}", exp),
+            },
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+    }
+
+    fn parse_string_literal(source: &str) -> Result<Value> {
+        match parse(&mut tokens(source))? {
+            Exp::Literal(literal) => Ok(literal.value),
+            exp => panic!("Expected literal got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn string_literal() {
+        assert_eq!(
+            parse_string_literal(r#""Hello world!""#).unwrap(),
+            Value::from_string("Hello world!")
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            parse_string_literal(r#""a\n\t\"\\\u{1F600}""#).unwrap(),
+            Value::from_string("a\n\t\"\\\u{1F600}")
+        );
+    }
+
+    #[test]
+    fn string_unknown_escape() {
+        let err = parse_string_literal(r#""\q""#).unwrap_err();
+        assert_eq!(err.pos.to_string(), "test.omg:0:1");
+    }
+
+    #[test]
+    fn string_invalid_unicode() {
+        parse_string_literal(r#""\u{D800}""#).unwrap_err();
+        parse_string_literal(r#""\u1234""#).unwrap_err();
+    }
+
+    fn parse_number_literal(source: &str) -> Result<Value> {
+        match parse(&mut tokens(source))? {
+            Exp::Literal(Literal { value, .. }) => Ok(value),
+            exp => panic!("Expected number got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(parse_number_literal("42").unwrap(), Value::Int(42));
+        assert_eq!(parse_number_literal("1.5").unwrap(), Value::Float(1.5));
+        assert_eq!(parse_number_literal("1e9").unwrap(), Value::Float(1e9));
+        assert_eq!(parse_number_literal("2.5E-3").unwrap(), Value::Float(2.5e-3));
+        assert_eq!(parse_number_literal("0xff").unwrap(), Value::Int(255));
+        assert_eq!(parse_number_literal("0b1010").unwrap(), Value::Int(10));
+        assert_eq!(parse_number_literal("1_000_000").unwrap(), Value::Int(1_000_000));
+        assert_eq!(
+            parse_number_literal("9223372036854775807").unwrap(),
+            Value::Int(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn invalid_number_literals() {
+        parse_number_literal("0xfg").unwrap_err();
+        parse_number_literal("0b102").unwrap_err();
+        parse_number_literal("12abc").unwrap_err();
+        parse_number_literal("1e").unwrap_err();
+        parse_number_literal("0x").unwrap_err();
+        let err = parse_number_literal("9223372036854775808").unwrap_err();
+        assert_eq!(err.msg, "9223372036854775808 is too large for an Int");
+        parse_number_literal("0xffffffffffffffff").unwrap_err();
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(eval(&parse_str("2 * 3 % 4")), 2.0);
+        match parse_str("2 ** 3 ** 2") {
+            Exp::Operator(op) => {
+                assert_eq!(op.op_type, OpType::Power);
+                assert!(matches!(*op.rhs, Exp::Operator(_)));
+            }
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval(&parse_str("-3")), -3.0);
+        assert_eq!(eval(&parse_str("2 - -3")), 5.0);
+        assert_eq!(eval(&parse_str("-2 * 3 + +1")), -5.0);
+        assert_eq!(eval(&parse_str("-(1 + 2)")), -3.0);
+    }
+
+    #[test]
+    fn power_binds_tighter_than_minus() {
+        match parse_str("-2 ** 2") {
+            Exp::Unary(unary) => match *unary.exp {
+                Exp::Operator(op) => assert_eq!(op.op_type, OpType::Power),
+                exp => panic!("Expected operator got {:?}", exp),
+            },
+            exp => panic!("Expected unary got {:?}", exp),
+        }
+        match parse_str("2 ** -1 * 3") {
+            Exp::Operator(op) => {
+                assert_eq!(op.op_type, OpType::Multiply);
+                assert!(matches!(*op.lhs, Exp::Operator(_)));
+            }
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn block_value() {
+        match parse_block(&mut tokens("a = 1; a")).unwrap() {
+            Exp::Block(block) => {
+                assert_eq!(block.statements.len(), 1);
+                assert!(block.value.is_some());
+            }
+            exp => panic!("Expected block got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn block_statement_needs_semicolon() {
+        parse_block(&mut tokens("a = 1 b = 2")).unwrap_err();
+    }
+
+    #[test]
+    fn if_else_chain() {
+        let exp = parse_block(&mut tokens(
+            "if a { 1 } else if b { 2 } else { 3 } print(1);",
+        ))
+        .unwrap();
+        let block = match exp {
+            Exp::Block(block) => block,
+            exp => panic!("Expected block got {:?}", exp),
+        };
+        assert_eq!(block.statements.len(), 2);
+        match &block.statements[0] {
+            Exp::If(If {
+                otherwise: Some(otherwise),
+                ..
+            }) => match otherwise.as_ref() {
+                Exp::If(If {
+                    otherwise: Some(_), ..
+                }) => (),
+                exp => panic!("Expected else if got {:?}", exp),
+            },
+            exp => panic!("Expected if got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn if_as_expression() {
+        match parse(&mut tokens("a = if b { 1 } else { 2 }")).unwrap() {
+            Exp::Assignment(assignment) => match *assignment.value {
+                Exp::If(_) => (),
+                exp => panic!("Expected if got {:?}", exp),
+            },
+            exp => panic!("Expected assignment got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn if_unclosed_block() {
+        parse_block(&mut tokens("if a { 1")).unwrap_err();
+    }
+
+    #[test]
+    fn while_loop() {
+        match parse(&mut tokens("while a < 10 { a = a + 1; break; }")).unwrap() {
+            Exp::While(while_exp) => match *while_exp.body {
+                Exp::Block(block) => assert_eq!(block.statements.len(), 2),
+                exp => panic!("Expected block got {:?}", exp),
+            },
+            exp => panic!("Expected while got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn for_loop() {
+        match parse(&mut tokens("for c in \"abc\" { continue }")).unwrap() {
+            Exp::For(for_exp) => assert_eq!(for_exp.name, "c"),
+            exp => panic!("Expected for got {:?}", exp),
+        }
+        parse(&mut tokens("for c \"abc\" { }")).unwrap_err();
+        parse(&mut tokens("for 1 in \"abc\" { }")).unwrap_err();
+    }
+
+    #[test]
+    fn function_def() {
+        let source = "/// Adds two numbers.\n/// Really.\nfn add(a, b) { return a + b; }";
+        match parse(&mut tokens(source)).unwrap() {
+            Exp::FunctionDef(def) => {
+                assert_eq!(def.name, "add");
+                let names: Vec<_> = def.params.iter().map(|p| p.name.as_str()).collect();
+                assert_eq!(names, vec!["a", "b"]);
+                assert_eq!(def.params[0].type_name, None);
+                assert_eq!(def.doc, Some("Adds two numbers.\nReally.".to_string()));
+            }
+            exp => panic!("Expected function got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn function_def_without_params() {
+        match parse(&mut tokens("fn nothing() { return }")).unwrap() {
+            Exp::FunctionDef(def) => {
+                assert!(def.params.is_empty());
+                assert_eq!(def.doc, None);
+            }
+            exp => panic!("Expected function got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn function_def_invalid_params() {
+        parse(&mut tokens("fn add(a, 1) { }")).unwrap_err();
+        parse(&mut tokens("fn add(a b c) { }")).unwrap_err();
+        parse(&mut tokens("fn add(List<a b) { }")).unwrap_err();
+        parse(&mut tokens("fn (a) { }")).unwrap_err();
+    }
+
+    #[test]
+    fn typed_params() {
+        match parse(&mut tokens("fn f(Number a, List<List<String>> b) { }")).unwrap() {
+            Exp::FunctionDef(def) => {
+                assert_eq!(def.params[0].type_name.as_ref().unwrap().name, "Number");
+                let list = def.params[1].type_name.as_ref().unwrap();
+                assert_eq!(list.name, "List");
+                assert_eq!(list.args[0].args[0].name, "String");
+                assert_eq!(def.params[1].name, "b");
+            }
+            exp => panic!("Expected function got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn typed_declaration() {
+        match parse(&mut tokens("var List<Int> xs = []")).unwrap() {
+            Exp::Assignment(assignment) => {
+                assert_eq!(assignment.name, "xs");
+                assert_eq!(assignment.declaration, Some(Declaration::Var));
+                assert_eq!(assignment.type_name.unwrap().args[0].name, "Int");
+            }
+            exp => panic!("Expected assignment got {:?}", exp),
+        }
+        match parse(&mut tokens("let x = 1")).unwrap() {
+            Exp::Assignment(assignment) => {
+                assert_eq!(assignment.declaration, Some(Declaration::Let));
+                assert!(assignment.type_name.is_none());
+            }
+            exp => panic!("Expected assignment got {:?}", exp),
+        }
+        parse(&mut tokens("let x")).unwrap_err();
+        parse(&mut tokens("let 1 = 1")).unwrap_err();
+        match parse(&mut tokens("a < b")).unwrap() {
+            Exp::Operator(_) => (),
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn list_literal() {
+        match parse(&mut tokens("[1, 2 + 3, [], \"a\"]")).unwrap() {
+            Exp::List(list) => {
+                assert_eq!(list.items.len(), 4);
+                match &list.items[2] {
+                    Exp::List(inner) => assert!(inner.items.is_empty()),
+                    exp => panic!("Expected list got {:?}", exp),
+                }
+            }
+            exp => panic!("Expected list got {:?}", exp),
+        }
+        parse(&mut tokens("[1, 2")).unwrap_err();
+        parse(&mut tokens("[1 2]")).unwrap_err();
+    }
+
+    #[test]
+    fn index() {
+        match parse(&mut tokens("xs[1][2] * 2")).unwrap() {
+            Exp::Operator(op) => match *op.lhs {
+                Exp::Index(index) => match *index.exp {
+                    Exp::Index(_) => (),
+                    exp => panic!("Expected index got {:?}", exp),
+                },
+                exp => panic!("Expected index got {:?}", exp),
+            },
+            exp => panic!("Expected operator got {:?}", exp),
+        }
+        parse(&mut tokens("xs[1")).unwrap_err();
+    }
+
+    #[test]
+    fn record_def() {
+        let source = "event Hello { String hello; count; }";
+        match parse(&mut tokens(source)).unwrap() {
+            Exp::RecordDef(def) => {
+                assert_eq!(def.name, "Hello");
+                assert_eq!(def.kind, RecordKind::Event);
+                assert_eq!(def.fields.len(), 2);
+                assert_eq!(def.fields[0].name, "hello");
+                assert_eq!(def.fields[0].type_name.as_ref().unwrap().name, "String");
+                assert_eq!(def.fields[1].name, "count");
+                assert_eq!(def.fields[1].type_name, None);
+            }
+            exp => panic!("Expected record got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn record_def_errors() {
+        parse(&mut tokens("record Empty { }")).unwrap();
+        parse(&mut tokens("record A { a }")).unwrap_err();
+        parse(&mut tokens("record A { a; a; }")).unwrap_err();
+        parse(&mut tokens("record { a; }")).unwrap_err();
+    }
+
+    #[test]
+    fn new_and_field() {
+        match parse(&mut tokens("new Hello(\"Hello \").hello")).unwrap() {
+            Exp::Field(field) => {
+                assert_eq!(field.name, "hello");
+                match *field.exp {
+                    Exp::New(new) => {
+                        assert_eq!(new.name, "Hello");
+                        assert_eq!(new.args.len(), 1);
+                    }
+                    exp => panic!("Expected new got {:?}", exp),
+                }
+            }
+            exp => panic!("Expected field got {:?}", exp),
+        }
+        parse(&mut tokens("new Hello")).unwrap_err();
+        parse(&mut tokens("a.1")).unwrap_err();
+    }
+
+    #[test]
+    fn run_handler() {
+        let exp = parse_block(&mut tokens("run (Main) { print(1); }\nrun (Hello h) { }")).unwrap();
+        let block = match exp {
+            Exp::Block(block) => block,
+            exp => panic!("Expected block got {:?}", exp),
+        };
+        match &block.statements[0] {
+            Exp::Run(def) => {
+                assert_eq!(def.event, "Main");
+                assert_eq!(def.name, None);
+            }
+            exp => panic!("Expected run got {:?}", exp),
+        }
+        match block.value.as_deref() {
+            Some(Exp::Run(def)) => {
+                assert_eq!(def.event, "Hello");
+                assert_eq!(def.name, Some("h".to_string()));
+            }
+            exp => panic!("Expected run got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn run_handler_only_at_top_level() {
+        parse_block(&mut tokens("if true { run (Main) { } }")).unwrap_err();
+        parse_block(&mut tokens("a = run (Main) { };")).unwrap_err();
+        parse_block(&mut tokens("run Main { }")).unwrap_err();
+        parse_block(&mut tokens("run (Main { }")).unwrap_err();
+    }
+
+    #[test]
+    fn method_call() {
+        let exp = parse(&mut tokens("Hello.next().hello")).unwrap();
+        let method = match exp {
+            Exp::Field(field) => *field.exp,
+            exp => panic!("Expected field got {:?}", exp),
+        };
+        match method {
+            Exp::MethodCall(method) => {
+                assert_eq!(method.type_name, "Hello");
+                assert_eq!(method.name, "next");
+                assert!(method.args.is_empty());
+                assert_eq!(method.pos.to_string(), "test.omg:0:6");
+            }
+            exp => panic!("Expected method call got {:?}", exp),
+        }
+        parse(&mut tokens("a.b.emit(1)")).unwrap_err();
+    }
+
+    #[test]
+    fn async_block() {
+        match parse(&mut tokens("async { a = 1; b = 2 }")).unwrap() {
+            Exp::Async(async_exp) => assert_eq!(async_exp.statements.len(), 2),
+            exp => panic!("Expected async got {:?}", exp),
+        }
+        parse(&mut tokens("async a = 1")).unwrap_err();
+    }
+
+    #[test]
+    fn data_def() {
+        let source = "/// Shapes.\ntype Shape<T> = Circle(T r) | Rect(w, h) | Dot";
+        match parse(&mut tokens(source)).unwrap() {
+            Exp::DataDef(def) => {
+                assert_eq!(def.name, "Shape");
+                assert_eq!(def.params, vec!["T".to_string()]);
+                let names: Vec<_> = def.variants.iter().map(|v| v.name.as_str()).collect();
+                assert_eq!(names, vec!["Circle", "Rect", "Dot"]);
+                assert_eq!(def.variants[0].fields[0].type_name.as_ref().unwrap().name, "T");
+                assert_eq!(def.variants[1].fields.len(), 2);
+                assert!(def.variants[2].fields.is_empty());
+                assert_eq!(def.doc, Some("Shapes.".to_string()));
+            }
+            exp => panic!("Expected data type got {:?}", exp),
+        }
+        parse(&mut tokens("type Shape = Dot | Dot")).unwrap_err();
+        parse(&mut tokens("type Shape Dot")).unwrap_err();
+        parse(&mut tokens("type Shape = Circle(r")).unwrap_err();
+    }
+
+    #[test]
+    fn match_arms() {
+        let source = "match s {
+            Circle(r) if r > 1 => { r }
+            Point { x: -1, y, .. } => y,
+            [1, \"a\", ..rest] => rest,
+            [] => 0,
+            _ => 1
+        }";
+        match parse(&mut tokens(source)).unwrap() {
+            Exp::Match(match_exp) => {
+                assert_eq!(match_exp.arms.len(), 5);
+                assert!(match_exp.arms[0].guard.is_some());
+                match &*match_exp.arms[1].pattern {
+                    Pattern::Record { fields, .. } => {
+                        assert_eq!(fields.len(), 2);
+                        let pos = fields[0].1.position();
+                        assert_eq!(fields[0].1, Pattern::Literal(Value::Int(-1), pos));
+                    }
+                    pattern => panic!("Expected record pattern got {:?}", pattern),
+                }
+                match &*match_exp.arms[2].pattern {
+                    Pattern::List { items, rest, .. } => {
+                        assert_eq!(items.len(), 2);
+                        assert!(rest.is_some());
+                    }
+                    pattern => panic!("Expected list pattern got {:?}", pattern),
+                }
+            }
+            exp => panic!("Expected match got {:?}", exp),
+        }
+        parse(&mut tokens("match s { a b }")).unwrap_err();
+        parse(&mut tokens("match s { a => 1 b => 2 }")).unwrap_err();
+        parse(&mut tokens("match s { [..a, b] => 1 }")).unwrap_err();
+    }
+
+    #[test]
+    fn try_operator() {
+        match parse(&mut tokens("read_file(path)?.len")).unwrap() {
+            Exp::Field(field) => match *field.exp {
+                Exp::Try(try_exp) => assert_eq!(try_exp.pos.to_string(), "test.omg:0:15"),
+                exp => panic!("Expected try got {:?}", exp),
+            },
+            exp => panic!("Expected field got {:?}", exp),
+        }
+    }
+
+    #[test]
+    fn unclosed_parentheses() {
+        parse(&mut tokens("(1 + 2")).unwrap_err();
+    }
 }
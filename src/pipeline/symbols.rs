@@ -0,0 +1,705 @@
+use super::ast::*;
+use super::{Function, Module, Token, Tokens};
+use crate::core_lib;
+use crate::error::Position;
+use crate::value::RecordKind;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Type,
+    /// A variant of a data type, like `Some`.
+    Variant,
+    Field,
+}
+
+/// Something a name can refer to.
+#[derive(Debug, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Where the name is declared, `None` for names the module gives, like
+    /// `print`.
+    pub pos: Option<Position>,
+    /// How the declaration reads, like `fn add(Int a, b)`. Empty for names
+    /// that are not declared anywhere, like fields that are read.
+    pub detail: String,
+    /// The `///` comments in front of the declaration.
+    pub doc: Option<String>,
+}
+
+/// A name in the source and what it refers to.
+#[derive(Debug, PartialEq)]
+pub struct Symbol {
+    pub pos: Position,
+    pub declaration: Arc<Definition>,
+}
+
+/// Finds what each name in the file refers to, following the same scopes as
+/// the type checker. Names that can't be found are left out.
+pub fn symbols(exp: &Exp, tokens: &Tokens, module: &Module) -> Vec<Symbol> {
+    let mut resolver = Resolver::new(tokens, module);
+    if let Exp::Block(block) = exp {
+        resolver.block(block);
+    }
+    resolver.symbols
+}
+
+/// The functions and types every file can use, from the module and the
+/// prelude.
+pub fn library(module: &Module) -> Vec<Arc<Definition>> {
+    let mut declarations: Vec<Arc<Definition>> = module
+        .function_names()
+        .filter_map(|name| module_function(module, name))
+        .chain(
+            module
+                .type_names()
+                .filter_map(|name| module_type(module, name)),
+        )
+        .collect();
+    for def in core_lib::prelude() {
+        declarations.push(data_declaration(&def, None));
+        for variant in &def.variants {
+            declarations.push(variant_declaration(&def, variant, None));
+        }
+    }
+    declarations.sort_by(|a, b| a.name.cmp(&b.name));
+    declarations
+}
+
+struct Resolver<'a> {
+    module: &'a Module,
+    /// Every token and its position, to find the names the AST has no
+    /// position for, like the name of a function.
+    tokens: Vec<(Token, Position)>,
+    index: HashMap<(u64, u64), usize>,
+    /// The variables in scope, with the innermost scope last.
+    values: Vec<HashMap<String, Arc<Definition>>>,
+    /// Functions and variants, they are seen everywhere once their block
+    /// is entered.
+    functions: HashMap<String, Arc<Definition>>,
+    types: HashMap<String, Arc<Definition>>,
+    symbols: Vec<Symbol>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(tokens: &Tokens, module: &'a Module) -> Self {
+        let mut tokens = tokens.clone();
+        let mut all = Vec::new();
+        let mut index = HashMap::new();
+        loop {
+            let pos = tokens.position();
+            index.insert((pos.line, pos.column), all.len());
+            all.push((tokens.current(), pos));
+            if tokens.current() == Token::EndOfFile {
+                break;
+            }
+            tokens.next();
+        }
+        let mut resolver = Resolver {
+            module,
+            tokens: all,
+            index,
+            values: vec![HashMap::new()],
+            functions: HashMap::new(),
+            types: HashMap::new(),
+            symbols: Vec::new(),
+        };
+        for def in core_lib::prelude() {
+            resolver.declare_data(&def, false);
+        }
+        resolver
+    }
+
+    /// The position of the `nth` identifier after the token at `pos`.
+    fn name_after(&self, pos: &Position, nth: usize) -> Option<Position> {
+        let index = self.index.get(&(pos.line, pos.column))?;
+        self.tokens[index + 1..]
+            .iter()
+            .filter(|(token, _)| *token == Token::Identifier)
+            .nth(nth)
+            .map(|(_, pos)| pos.clone())
+    }
+
+    fn token_before(&self, pos: &Position, count: usize) -> Option<Position> {
+        let index = self.index.get(&(pos.line, pos.column))?;
+        let before = index.checked_sub(count)?;
+        Some(self.tokens[before].1.clone())
+    }
+
+    fn refer(&mut self, pos: &Position, declaration: Arc<Definition>) {
+        self.symbols.push(Symbol {
+            pos: pos.clone(),
+            declaration,
+        });
+    }
+
+    fn declare_value(&mut self, name: &str, kind: SymbolKind, pos: &Position, detail: String) {
+        let declaration = Arc::new(Definition {
+            name: name.to_string(),
+            kind,
+            pos: Some(pos.clone()),
+            detail,
+            doc: None,
+        });
+        let scope = self.values.last_mut().expect("there are no scopes");
+        scope.insert(name.to_string(), Arc::clone(&declaration));
+        self.refer(pos, declaration);
+    }
+
+    fn value(&mut self, name: &str, pos: &Position) {
+        let found = self
+            .values
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.functions.get(name))
+            .cloned();
+        if let Some(declaration) = found {
+            self.refer(pos, declaration);
+        }
+    }
+
+    fn function(&mut self, name: &str, pos: &Position) {
+        let found = match self.functions.get(name) {
+            Some(declaration) => Some(Arc::clone(declaration)),
+            None => module_function(self.module, name),
+        };
+        if let Some(declaration) = found {
+            self.refer(pos, declaration);
+        }
+    }
+
+    /// Types are always referred to, as built in types like `Int` are not
+    /// declared anywhere.
+    fn type_use(&mut self, name: &str, pos: &Position) {
+        let declaration = match self.types.get(name) {
+            Some(declaration) => Arc::clone(declaration),
+            None => module_type(self.module, name).unwrap_or_else(|| {
+                Arc::new(Definition {
+                    name: name.to_string(),
+                    kind: SymbolKind::Type,
+                    pos: None,
+                    detail: String::new(),
+                    doc: None,
+                })
+            }),
+        };
+        self.refer(pos, declaration);
+    }
+
+    fn type_name(&mut self, type_name: &TypeName) {
+        self.type_use(&type_name.name, &type_name.pos);
+        for arg in &type_name.args {
+            self.type_name(arg);
+        }
+    }
+
+    fn field(&mut self, field: &FieldDef) {
+        if let Some(type_name) = &field.type_name {
+            self.type_name(type_name);
+        }
+        let declaration = Arc::new(Definition {
+            name: field.name.clone(),
+            kind: SymbolKind::Field,
+            pos: Some(field.pos.clone()),
+            detail: typed(&field.type_name, &field.name),
+            doc: None,
+        });
+        self.refer(&field.pos, declaration);
+    }
+
+    /// Declares the functions and types of a block up front, like the type
+    /// checker does.
+    fn hoist<'b, I>(&mut self, statements: I)
+    where
+        I: IntoIterator<Item = &'b Exp>,
+    {
+        for statement in statements {
+            match statement {
+                Exp::FunctionDef(def) => {
+                    let declaration = Definition {
+                        name: def.name.clone(),
+                        kind: SymbolKind::Function,
+                        pos: self.name_after(&def.pos, 0),
+                        detail: function_detail(def),
+                        doc: def.doc.clone(),
+                    };
+                    self.functions
+                        .insert(def.name.clone(), Arc::new(declaration));
+                }
+                Exp::RecordDef(def) => {
+                    let keyword = match def.kind {
+                        RecordKind::Record => "record",
+                        RecordKind::Event => "event",
+                    };
+                    let declaration = Definition {
+                        name: def.name.clone(),
+                        kind: SymbolKind::Type,
+                        pos: self.name_after(&def.pos, 0),
+                        detail: format!("{} {}", keyword, def.name),
+                        doc: def.doc.clone(),
+                    };
+                    self.types.insert(def.name.clone(), Arc::new(declaration));
+                }
+                Exp::DataDef(def) => self.declare_data(def, true),
+                _ => (),
+            }
+        }
+    }
+
+    fn declare_data(&mut self, def: &DataDef, in_file: bool) {
+        let pos = if in_file {
+            self.name_after(&def.pos, 0)
+        } else {
+            None
+        };
+        self.types
+            .insert(def.name.clone(), data_declaration(def, pos));
+        for variant in &def.variants {
+            let pos = if in_file {
+                Some(variant.pos.clone())
+            } else {
+                None
+            };
+            self.functions
+                .insert(variant.name.clone(), variant_declaration(def, variant, pos));
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.hoist(block.statements.iter().chain(block.value.as_deref()));
+        for statement in &block.statements {
+            self.exp(statement);
+        }
+        if let Some(value) = &block.value {
+            self.exp(value);
+        }
+    }
+
+    /// Resolves the expression with a scope of its own.
+    fn scoped<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.values.push(HashMap::new());
+        f(self);
+        self.values.pop();
+    }
+
+    /// Resolves a function or handler body, which sees no variables from
+    /// around it.
+    fn body<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        let values = std::mem::replace(&mut self.values, vec![HashMap::new()]);
+        f(self);
+        self.values = values;
+    }
+
+    fn exps(&mut self, exps: &[Exp]) {
+        for exp in exps {
+            self.exp(exp);
+        }
+    }
+
+    fn exp(&mut self, exp: &Exp) {
+        match exp {
+            Exp::Block(block) => self.scoped(|resolver| resolver.block(block)),
+            Exp::Call(call) => {
+                self.function(&call.name, &call.pos);
+                self.exps(&call.args);
+            }
+            Exp::Literal(_) | Exp::Break(_) | Exp::Continue(_) => (),
+            Exp::Assignment(assignment) => {
+                self.exp(&assignment.value);
+                if let Some(type_name) = &assignment.type_name {
+                    self.type_name(type_name);
+                }
+                let keyword = match assignment.declaration {
+                    Some(Declaration::Let) => "let",
+                    Some(Declaration::Var) => "var",
+                    None => return self.value(&assignment.name, &assignment.pos),
+                };
+                let detail = format!("{} {}", keyword, assignment.name);
+                self.declare_value(
+                    &assignment.name,
+                    SymbolKind::Variable,
+                    &assignment.pos,
+                    detail,
+                );
+            }
+            Exp::Variable(variable) => self.value(&variable.name, &variable.pos),
+            Exp::Operator(op) => {
+                self.exp(&op.lhs);
+                self.exp(&op.rhs);
+            }
+            Exp::Unary(unary) => self.exp(&unary.exp),
+            Exp::If(i) => {
+                self.exp(&i.condition);
+                self.exp(&i.then);
+                if let Some(otherwise) = &i.otherwise {
+                    self.exp(otherwise);
+                }
+            }
+            Exp::While(w) => {
+                self.exp(&w.condition);
+                self.exp(&w.body);
+            }
+            Exp::For(f) => {
+                self.exp(&f.collection);
+                let name = self.name_after(&f.pos, 0);
+                self.scoped(|resolver| {
+                    if let Some(pos) = name {
+                        let detail = format!("for {}", f.name);
+                        resolver.declare_value(&f.name, SymbolKind::Variable, &pos, detail);
+                    }
+                    resolver.exp(&f.body);
+                });
+            }
+            Exp::FunctionDef(def) => {
+                if let Some(pos) = self.name_after(&def.pos, 0) {
+                    self.function(&def.name, &pos);
+                }
+                self.body(|resolver| {
+                    for param in &def.params {
+                        if let Some(type_name) = &param.type_name {
+                            resolver.type_name(type_name);
+                        }
+                        let detail = format!("param {}", param.name);
+                        resolver.declare_value(
+                            &param.name,
+                            SymbolKind::Parameter,
+                            &param.pos,
+                            detail,
+                        );
+                    }
+                    resolver.exp(&def.body);
+                });
+            }
+            Exp::Return(r) => {
+                if let Some(value) = &r.value {
+                    self.exp(value);
+                }
+            }
+            Exp::List(list) => self.exps(&list.items),
+            Exp::Index(index) => {
+                self.exp(&index.exp);
+                self.exp(&index.index);
+            }
+            Exp::RecordDef(def) => {
+                if let Some(pos) = self.name_after(&def.pos, 0) {
+                    self.type_use(&def.name, &pos);
+                }
+                for field in &def.fields {
+                    self.field(field);
+                }
+            }
+            Exp::New(new) => {
+                if let Some(pos) = self.name_after(&new.pos, 0) {
+                    self.type_use(&new.name, &pos);
+                }
+                self.exps(&new.args);
+            }
+            Exp::Field(field) => {
+                self.exp(&field.exp);
+                let declaration = Arc::new(Definition {
+                    name: field.name.clone(),
+                    kind: SymbolKind::Field,
+                    pos: None,
+                    detail: String::new(),
+                    doc: None,
+                });
+                self.refer(&field.pos, declaration);
+            }
+            Exp::MethodCall(method) => {
+                if let Some(pos) = self.token_before(&method.pos, 2) {
+                    self.type_use(&method.type_name, &pos);
+                }
+                self.exps(&method.args);
+            }
+            Exp::Run(run) => {
+                if let Some(pos) = self.name_after(&run.pos, 0) {
+                    self.type_use(&run.event, &pos);
+                }
+                let name = self.name_after(&run.pos, 1);
+                self.body(|resolver| {
+                    if let (Some(name), Some(pos)) = (&run.name, name) {
+                        let detail = format!("let {}", name);
+                        resolver.declare_value(name, SymbolKind::Variable, &pos, detail);
+                    }
+                    resolver.exp(&run.body);
+                });
+            }
+            Exp::Async(a) => self.exps(&a.statements),
+            Exp::DataDef(def) => {
+                if let Some(pos) = self.name_after(&def.pos, 0) {
+                    self.type_use(&def.name, &pos);
+                }
+                for variant in &def.variants {
+                    self.function(&variant.name, &variant.pos);
+                    for field in &variant.fields {
+                        self.field(field);
+                    }
+                }
+            }
+            Exp::Match(m) => {
+                self.exp(&m.exp);
+                for arm in &m.arms {
+                    self.scoped(|resolver| {
+                        resolver.pattern(&arm.pattern);
+                        if let Some(guard) = &arm.guard {
+                            resolver.exp(guard);
+                        }
+                        resolver.exp(&arm.value);
+                    });
+                }
+            }
+            Exp::Try(t) => self.exp(&t.exp),
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard(_) | Pattern::Literal(..) => (),
+            Pattern::Name(name, pos) => match self.functions.get(name) {
+                Some(declaration) if declaration.kind == SymbolKind::Variant => {
+                    self.refer(pos, Arc::clone(declaration))
+                }
+                _ => {
+                    let detail = format!("let {}", name);
+                    self.declare_value(name, SymbolKind::Variable, pos, detail);
+                }
+            },
+            Pattern::Variant { name, args, pos } => {
+                self.function(name, pos);
+                for arg in args {
+                    self.pattern(arg);
+                }
+            }
+            Pattern::Record { name, fields, pos } => {
+                self.type_use(name, pos);
+                for (_, pattern) in fields {
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::List { items, rest, .. } => {
+                for item in items {
+                    self.pattern(item);
+                }
+                if let Some(rest) = rest {
+                    self.pattern(rest);
+                }
+            }
+        }
+    }
+}
+
+fn module_function(module: &Module, name: &str) -> Option<Arc<Definition>> {
+    let detail = match module.get_function(name)? {
+        Function::NativeFunction(native) => {
+            let signature = native.signature();
+            let mut params: Vec<String> = signature.params.iter().map(|t| t.to_string()).collect();
+            if let (Some(constraint), Some(first)) = (native.constraint(), params.first_mut()) {
+                *first = constraint.to_string();
+            }
+            if let Some(rest) = &signature.rest {
+                params.push(format!("{}...", rest));
+            }
+            format!(
+                "fn {}({}) -> {}",
+                name,
+                params.join(", "),
+                signature.returns
+            )
+        }
+        // Variants are declared with their data types in the prelude.
+        Function::Constructor(..) | Function::UserFunction(_) => return None,
+    };
+    Some(Arc::new(Definition {
+        name: name.to_string(),
+        kind: SymbolKind::Function,
+        pos: None,
+        detail,
+        doc: None,
+    }))
+}
+
+fn module_type(module: &Module, name: &str) -> Option<Arc<Definition>> {
+    let record_type = module.get_type(name)?;
+    let keyword = match record_type.kind {
+        RecordKind::Record => "record",
+        RecordKind::Event => "event",
+    };
+    Some(Arc::new(Definition {
+        name: name.to_string(),
+        kind: SymbolKind::Type,
+        pos: None,
+        detail: format!("{} {}", keyword, name),
+        doc: None,
+    }))
+}
+
+fn data_declaration(def: &DataDef, pos: Option<Position>) -> Arc<Definition> {
+    let params = if def.params.is_empty() {
+        String::new()
+    } else {
+        format!("<{}>", def.params.join(", "))
+    };
+    Arc::new(Definition {
+        name: def.name.clone(),
+        kind: SymbolKind::Type,
+        pos,
+        detail: format!("type {}{}", def.name, params),
+        doc: def.doc.clone(),
+    })
+}
+
+fn variant_declaration(
+    def: &DataDef,
+    variant: &VariantDef,
+    pos: Option<Position>,
+) -> Arc<Definition> {
+    Arc::new(Definition {
+        name: variant.name.clone(),
+        kind: SymbolKind::Variant,
+        pos,
+        detail: variant_detail(variant),
+        doc: def.doc.clone(),
+    })
+}
+
+fn type_text(type_name: &TypeName) -> String {
+    if type_name.args.is_empty() {
+        return type_name.name.clone();
+    }
+    let args: Vec<String> = type_name.args.iter().map(type_text).collect();
+    format!("{}<{}>", type_name.name, args.join(", "))
+}
+
+fn typed(type_name: &Option<TypeName>, name: &str) -> String {
+    match type_name {
+        Some(type_name) => format!("{} {}", type_text(type_name), name),
+        None => name.to_string(),
+    }
+}
+
+fn function_detail(def: &FunctionDef) -> String {
+    let params: Vec<String> = def
+        .params
+        .iter()
+        .map(|param| typed(&param.type_name, &param.name))
+        .collect();
+    format!("fn {}({})", def.name, params.join(", "))
+}
+
+fn variant_detail(variant: &VariantDef) -> String {
+    if variant.fields.is_empty() {
+        return variant.name.clone();
+    }
+    let fields: Vec<String> = variant
+        .fields
+        .iter()
+        .map(|field| typed(&field.type_name, &field.name))
+        .collect();
+    format!("{}({})", variant.name, fields.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{lexer, parse_block, Source};
+
+    /// A name with the line and column it is used at and where its
+    /// declaration is.
+    type Resolved = (String, (u64, u64), Option<(u64, u64)>);
+
+    fn resolve(source: &str) -> Vec<Resolved> {
+        let tokens = lexer(Source {
+            path: "test.omg".to_string(),
+            source: source.to_string(),
+        })
+        .unwrap();
+        let exp = parse_block(&mut tokens.clone()).unwrap();
+        let module = core_lib::add_std_lib(&Module::new());
+        symbols(&exp, &tokens, &module)
+            .into_iter()
+            .map(|symbol| {
+                let declaration = &symbol.declaration;
+                (
+                    declaration.name.clone(),
+                    (symbol.pos.line, symbol.pos.column),
+                    declaration.pos.as_ref().map(|pos| (pos.line, pos.column)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scopes() {
+        let names = resolve("let a = 1;\nfn f(a) { a }\n{ let a = 2; a };\nf(a);");
+        assert_eq!(
+            names,
+            vec![
+                ("a".to_string(), (0, 4), Some((0, 4))),
+                ("f".to_string(), (1, 3), Some((1, 3))),
+                ("a".to_string(), (1, 5), Some((1, 5))),
+                ("a".to_string(), (1, 10), Some((1, 5))),
+                ("a".to_string(), (2, 6), Some((2, 6))),
+                ("a".to_string(), (2, 13), Some((2, 6))),
+                ("f".to_string(), (3, 0), Some((1, 3))),
+                ("a".to_string(), (3, 2), Some((0, 4))),
+            ]
+        );
+    }
+
+    #[test]
+    fn used_before_declared() {
+        let names = resolve("print(f(None));\nfn f(Option<Int> o) { o }");
+        let print = &names[0];
+        assert_eq!(print.0, "print");
+        assert_eq!(print.2, None);
+        assert_eq!(names[1], ("f".to_string(), (0, 6), Some((1, 3))));
+        assert_eq!(names[2], ("None".to_string(), (0, 8), None));
+    }
+
+    #[test]
+    fn details() {
+        let tokens = lexer(Source {
+            path: "test.omg".to_string(),
+            source: "/// Docs.\nfn f(Int a, b) { a }\ntype T = A(List<Int> xs) | B;".to_string(),
+        })
+        .unwrap();
+        let exp = parse_block(&mut tokens.clone()).unwrap();
+        let module = core_lib::add_std_lib(&Module::new());
+        let symbols = symbols(&exp, &tokens, &module);
+        let details: Vec<&str> = symbols
+            .iter()
+            .map(|symbol| symbol.declaration.detail.as_str())
+            .collect();
+        assert_eq!(
+            details,
+            vec![
+                "fn f(Int a, b)",
+                "",
+                "param a",
+                "param b",
+                "param a",
+                "type T",
+                "A(List<Int> xs)",
+                "",
+                "",
+                "List<Int> xs",
+                "B"
+            ]
+        );
+        assert_eq!(symbols[0].declaration.doc, Some("Docs.".to_string()));
+        let print = library(&module).into_iter().find(|d| d.name == "print");
+        assert_eq!(print.unwrap().detail, "fn print(Any...) -> Nothing");
+    }
+}
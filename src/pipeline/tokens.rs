@@ -5,20 +5,57 @@ use im::Vector;
 pub enum Token {
     Identifier,
     Number,
+    String,
     True,
     False,
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Fn,
+    Return,
+    Record,
+    Event,
+    New,
+    Run,
+    Async,
+    Type,
+    Match,
+    Let,
+    Var,
     ParenthesesOpen,
     ParenthesesClose,
+    BraceOpen,
+    BraceClose,
+    BracketOpen,
+    BracketClose,
     Comma,
+    Dot,
+    DotDot,
     Semicolon,
+    Colon,
+    Question,
     Assignment,
+    Arrow,
+    Bar,
     OpAdd,
     OpSubtract,
     OpMultiply,
     OpDivide,
+    OpRemainder,
+    OpPower,
     OpEqual,
+    OpNotEqual,
     OpGreaterThan,
+    OpGreaterEqual,
     OpLessThan,
+    OpLessEqual,
+    OpAnd,
+    OpOr,
+    OpNot,
     EndOfFile,
 }
 
@@ -27,13 +64,35 @@ struct MetaData {
     pub slice: String,
     pub line: u64,
     pub column: u64,
+    pub trivia: Vector<Trivia>,
 }
 
-#[derive(Debug)]
+/// Source text that has no meaning to the parser but is kept for tools, like
+/// the comments and blank lines in front of a token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub slice: String,
+    pub line: u64,
+    pub column: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    /// `/// text`, documents the declaration after it.
+    DocComment,
+    /// `// text` or `/* text */`.
+    Comment,
+    /// One or more empty lines, the slice is empty.
+    BlankLine,
+}
+
+#[derive(Debug, Clone)]
 pub struct Tokens {
     path: String,
     tokens: Vector<Token>,
     metadata: Vector<MetaData>,
+    trivia: Vector<Trivia>,
     index: usize,
 }
 
@@ -43,15 +102,27 @@ impl Tokens {
             path,
             tokens: Vector::new(),
             metadata: Vector::new(),
+            trivia: Vector::new(),
             index: 0,
         }
     }
 
     pub fn push(&mut self, token: Token, slice: String, line: u64, column: u64){
+        let trivia = std::mem::replace(&mut self.trivia, Vector::new());
         self.tokens.push_back(token);
-        self.metadata.push_back(MetaData {slice, line, column});
+        self.metadata.push_back(MetaData {slice, line, column, trivia});
     } 
 
+    /// Trivia is attached to the next token that is pushed.
+    pub fn push_trivia(&mut self, kind: TriviaKind, slice: String, line: u64, column: u64) {
+        self.trivia.push_back(Trivia {
+            kind,
+            slice,
+            line,
+            column,
+        });
+    }
+
     pub fn next(&mut self) {
         self.index += 1;
         if self.index >= self.tokens.len() {
@@ -75,15 +146,27 @@ impl Tokens {
         self.get(self.index + 1)
     }
 
+    /// The range of the current token.
     pub fn position(&self) -> Position {
         let meta = self.get_meta(self.index);
-        Position::new(&self.path).with_pos(meta.line, meta.column)
+        let start = Position::new(&self.path).with_pos(meta.line, meta.column);
+        match meta.slice.rfind('\n') {
+            Some(i) => start.with_end(
+                meta.line + meta.slice.matches('\n').count() as u64,
+                meta.slice[i + 1..].chars().count() as u64,
+            ),
+            None => start.with_end(meta.line, meta.column + meta.slice.chars().count() as u64),
+        }
     }
 
     pub fn slice(&self) -> &str {
         &self.get_meta(self.index).slice
     }
 
+    pub fn trivia(&self) -> &Vector<Trivia> {
+        &self.get_meta(self.index).trivia
+    }
+
     fn get(&self, index: usize) -> Token {
         if index >= self.tokens.len() {
             return self.tokens[self.tokens.len() - 1];
@@ -0,0 +1,89 @@
+use std::fmt;
+
+/// The static type of a value, as worked out by the type checker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Fits every type, used by natives that take values of any type.
+    Any,
+    Nothing,
+    Int,
+    Float,
+    Boolean,
+    String,
+    List(Box<Type>),
+    /// A record or event type by name.
+    Record(String),
+    /// A data type by name, with its type arguments.
+    Data(String, Vec<Type>),
+    /// A type that is not known yet.
+    Var(usize),
+    /// The type of expressions that never produce a value, like `return`.
+    Never,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Nothing => write!(f, "Nothing"),
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::Boolean => write!(f, "Boolean"),
+            Type::String => write!(f, "String"),
+            Type::List(item) => write!(f, "List<{}>", item),
+            Type::Record(name) => write!(f, "{}", name),
+            Type::Data(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Data(name, args) => {
+                write!(f, "{}<", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ">")
+            }
+            Type::Var(_) => write!(f, "?"),
+            Type::Never => write!(f, "Never"),
+        }
+    }
+}
+
+/// The types allowed where no single type will do, like the operands of `-`
+/// or the argument of `len`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constraint {
+    /// An Int or a Float.
+    Number,
+    /// A List or a String, which have a length.
+    Sized,
+}
+
+impl Constraint {
+    pub fn allows(self, t: &Type) -> bool {
+        match t {
+            Type::Any | Type::Never => true,
+            Type::Int | Type::Float => self == Constraint::Number,
+            Type::List(_) | Type::String => self == Constraint::Sized,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constraint::Number => write!(f, "Int | Float"),
+            Constraint::Sized => write!(f, "List | String"),
+        }
+    }
+}
+
+/// The declared type of a native function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    /// The type of any number of arguments after `params`.
+    pub rest: Option<Type>,
+    pub returns: Type,
+}
@@ -0,0 +1,199 @@
+use crate::core_lib::add_std_lib;
+use crate::error::OmgError;
+use crate::event_bus::EventBus;
+use crate::pipeline::{self, compile, parse_block, Module, Session, Source, Token};
+use crate::runtime::Runtime;
+use crate::value::Value;
+use std::sync::Arc;
+use tokio::runtime::current_thread;
+
+/// The name errors in code typed into the REPL are reported under.
+const REPL_PATH: &str = "repl";
+
+/// Runs code a piece at a time. The variables, functions and types of each
+/// piece stay around for the pieces after it.
+pub struct Repl {
+    bus: Arc<EventBus>,
+    session: Session,
+    runtime: Runtime,
+    executor: current_thread::Runtime,
+    /// The line the next piece starts at.
+    line: u64,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let module = Arc::new(add_std_lib(&Module::new()));
+        let bus = EventBus::new();
+        Repl {
+            session: Session::new(&module),
+            runtime: Runtime::listening(&module, bus.listen()),
+            bus,
+            executor: current_thread::Runtime::new().expect("can't start an executor"),
+            line: 1,
+        }
+    }
+
+    /// Forgets everything that has been run so far.
+    pub fn reset(&mut self) {
+        *self = Repl::new();
+    }
+
+    /// Type checks and runs the source, returning its value unless it is
+    /// `Nothing`.
+    pub fn eval(&mut self, source: &str) -> Result<Option<String>, Vec<OmgError>> {
+        let exp = self.parse(source).map_err(|e| vec![e])?;
+        // What the piece declares is only kept once it has run.
+        let mut session = self.session.clone();
+        session.check(&exp)?;
+        let program = compile(&exp).map_err(|e| vec![e])?;
+        self.runtime.start(&program.code);
+        let value = self
+            .executor
+            .block_on(&mut self.runtime)
+            .map_err(|e| vec![e])?;
+        for handler in program.handlers {
+            self.bus
+                .add_handler(self.runtime.module(), handler)
+                .map_err(|e| vec![e])?;
+        }
+        self.session = session;
+        Ok(match value {
+            Value::Nothing => None,
+            Value::String(s) => Some(format!("{:?}", s)),
+            value => Some(value.to_string()),
+        })
+    }
+
+    /// Parses the piece as the lines after the pieces before it, failed or
+    /// not, so errors point at the input they are in.
+    fn parse(&mut self, source: &str) -> Result<pipeline::ast::Exp, OmgError> {
+        let tokens = pipeline::lexer_at(repl_source(source), self.line);
+        self.line += source.trim_end_matches('\n').lines().count().max(1) as u64;
+        parse_block(&mut tokens?)
+    }
+
+    /// The type of the source's value, without running it.
+    pub fn type_of(&mut self, source: &str) -> Result<String, Vec<OmgError>> {
+        let exp = self.parse(source).map_err(|e| vec![e])?;
+        Ok(self.session.type_of(&exp)?.to_string())
+    }
+}
+
+/// If the source has brackets that are not closed yet, so it goes on at the
+/// next line.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut tokens = match lex(source) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+    let mut depth = 0;
+    loop {
+        match tokens.current() {
+            Token::ParenthesesOpen | Token::BraceOpen | Token::BracketOpen => depth += 1,
+            Token::ParenthesesClose | Token::BraceClose | Token::BracketClose => depth -= 1,
+            Token::EndOfFile => return depth > 0,
+            _ => (),
+        }
+        tokens.next();
+    }
+}
+
+fn lex(source: &str) -> Result<pipeline::Tokens, OmgError> {
+    pipeline::lexer(repl_source(source))
+}
+
+fn repl_source(source: &str) -> Source {
+    Source {
+        path: REPL_PATH.to_string(),
+        source: source.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(result: Result<Option<String>, Vec<OmgError>>) -> Vec<String> {
+        result
+            .unwrap_err()
+            .into_iter()
+            .map(|e| format!("{}: {}", e.pos, e.msg))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_state_between_pieces() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("var a = 1;").unwrap(), None);
+        assert_eq!(repl.eval("fn double(n) { n * 2 }").unwrap(), None);
+        assert_eq!(
+            repl.eval("a = double(a); a").unwrap(),
+            Some("2".to_string())
+        );
+        assert_eq!(repl.eval("\"a\"").unwrap(), Some("\"a\"".to_string()));
+        assert_eq!(
+            messages(repl.eval("a = \"b\";")),
+            vec!["repl:5:0: Can't assign String to a which is Int"]
+        );
+        repl.reset();
+        assert_eq!(
+            messages(repl.eval("a")),
+            vec!["repl:1:0: Cant find variable named a"]
+        );
+    }
+
+    #[test]
+    fn failed_pieces_leave_no_trace() {
+        let mut repl = Repl::new();
+        messages(repl.eval("let a = 1; let b = c;"));
+        repl.eval("let a = \"a\";").unwrap();
+        assert_eq!(repl.eval("a").unwrap(), Some("\"a\"".to_string()));
+        messages(repl.eval("let x = 1 / 0;"));
+        assert_eq!(
+            messages(repl.eval("x")),
+            vec!["repl:5:0: Cant find variable named x, did you mean a?"]
+        );
+        assert_eq!(repl.eval("1 + 1").unwrap(), Some("2".to_string()));
+    }
+
+    #[test]
+    fn types() {
+        let mut repl = Repl::new();
+        repl.eval("let xs = [1, 2];").unwrap();
+        assert_eq!(repl.type_of("xs").unwrap(), "List<Int>");
+        assert_eq!(
+            repl.type_of("let y = 1; parse_int(\"1\")").unwrap(),
+            "Option<Int>"
+        );
+        assert_eq!(
+            messages(repl.eval("y")),
+            vec!["repl:4:0: Cant find variable named y"]
+        );
+    }
+
+    #[test]
+    fn lines_go_on_between_pieces() {
+        let mut repl = Repl::new();
+        repl.eval("fn f(n) {\n  n + 1\n}\n").unwrap();
+        assert_eq!(
+            messages(repl.eval("let a = 1;\nlet b = zzz;\n")),
+            vec!["repl:5:8: Cant find variable named zzz"]
+        );
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("fn f() {"));
+        assert!(is_incomplete("let xs = [1,\n2"));
+        assert!(!is_incomplete("fn f() { }"));
+        assert!(!is_incomplete("}"));
+        assert!(!is_incomplete("print(1)"));
+    }
+}
@@ -1,92 +1,1031 @@
 use im::Vector;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::prelude::{future, task, Async, Future, Poll};
+use tokio::sync::oneshot;
 
 use super::{
-    error::{OmgError, Result},
-    pipeline::{Function, Module},
-    pipeline::ast::{Exp, OpType, Operator},
-    value::{Scope, Value},
+    core_lib::{OPTION, RESULT},
+    error::{did_you_mean, OmgError, Position, Result},
+    event_bus::{Listener, Next},
+    pipeline::ast::{OpType, Pattern, UnaryType},
+    pipeline::{Code, Function, Instruction, Module, UserFunction},
+    value::{DataType, OpError, Record, RecordKind, Scope, TypeError, Value, Variant},
 };
+#[cfg(test)]
+use super::{
+    event_bus::EventBus,
+    pipeline::ast::{Declaration, Exp},
+    pipeline::compile,
+};
+#[cfg(test)]
+use tokio::runtime::current_thread;
+
+/// How many instructions a runtime runs before it lets other tasks run.
+const STEPS_PER_POLL: usize = 10_000;
 
+/// Runs compiled code. The runtime is a future, so it can wait for events
+/// without blocking the thread it runs on.
 pub struct Runtime {
     module: Arc<Module>,
-    scope: Scope,
+    listener: Listener,
+    scopes: Scopes,
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+    /// The statements of the `async` block that is running.
+    join: Option<Join>,
+    /// The variables of the top level that have been assigned, so an `async`
+    /// block can merge what each of its statements changed.
+    assigned: Assigned,
+}
+
+/// Variables by the level of the scope they are in and their name.
+type Assigned = HashSet<(usize, String)>;
+
+/// A function call in progress.
+struct Frame {
+    code: Arc<Code>,
+    pc: usize,
+    /// The height of the stack when the function was called.
+    stack_base: usize,
+    loops: Vec<Loop>,
+    /// The scopes to return to, the top level has none.
+    caller_scope: Option<Scopes>,
+}
+
+/// The functions a block declares by their name.
+type Functions = HashMap<String, Arc<UserFunction>>;
+
+/// The variables of a function or the top level, one scope for each block it
+/// is in with the innermost last.
+#[derive(Clone)]
+struct Scopes {
+    variables: Vec<Scope>,
+    /// The functions declared by each block, with the innermost last. A
+    /// function starts out with the ones around where it was declared, so
+    /// there can be more of these than scopes of variables.
+    functions: Vec<Functions>,
+}
+
+impl Scopes {
+    fn new() -> Scopes {
+        Scopes::with_functions(Scope::new(), Vec::new())
+    }
+
+    /// The scopes of a function body, which sees the functions given.
+    fn with_functions(scope: Scope, mut functions: Vec<Functions>) -> Scopes {
+        functions.push(Functions::new());
+        Scopes {
+            variables: vec![scope],
+            functions,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.variables
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+    }
+
+    fn declare(&mut self, name: String, value: Value) {
+        let innermost = self.variables.last_mut().expect("there are no scopes");
+        innermost.insert(name, value);
+    }
+
+    /// Changes the innermost variable with the name, returning the level of
+    /// its scope or `None` when there is none.
+    fn assign(&mut self, name: &str, value: Value) -> Option<usize> {
+        let level = self
+            .variables
+            .iter()
+            .rposition(|scope| scope.contains_key(name))?;
+        self.variables[level].insert(name.to_string(), value);
+        Some(level)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.variables
+            .iter()
+            .flat_map(|scope| scope.keys().map(|name| name.as_str()))
+    }
+
+    fn depth(&self) -> usize {
+        self.variables.len()
+    }
+
+    fn push(&mut self) {
+        self.variables.push(Scope::new());
+        self.functions.push(Functions::new());
+    }
+
+    /// Drops the innermost scope with the variables and functions of its
+    /// block.
+    fn pop(&mut self) {
+        self.variables.pop();
+        self.functions.pop();
+    }
+
+    fn truncate(&mut self, depth: usize) {
+        while self.depth() > depth {
+            self.pop();
+        }
+    }
+
+    fn declare_function(&mut self, function: &Arc<UserFunction>) {
+        let innermost = self.functions.last_mut().expect("there are no scopes");
+        innermost.insert(function.def.name.clone(), Arc::clone(function));
+    }
+
+    /// The innermost function with the name, with the functions it can see.
+    fn function(&self, name: &str) -> Option<(Arc<UserFunction>, Vec<Functions>)> {
+        let level = self
+            .functions
+            .iter()
+            .rposition(|functions| functions.contains_key(name))?;
+        let visible = self.functions[..=level].to_vec();
+        Some((Arc::clone(&self.functions[level][name]), visible))
+    }
+
+    fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions
+            .iter()
+            .flat_map(|functions| functions.keys().map(|name| name.as_str()))
+    }
+}
+
+struct Loop {
+    exit: usize,
+    next: usize,
+    stack_height: usize,
+    /// How many scopes there were when the loop started.
+    scopes: usize,
+    /// The items a `for` loop has left.
+    items: Vector<Value>,
+}
+
+/// The statements of an `async` block, they each run in their own runtime
+/// starting out with a copy of the scopes.
+struct Join {
+    statements: Vec<oneshot::Receiver<Result<(Scopes, Assigned)>>>,
+    /// The scopes of the statements that are done and what they assigned.
+    done: Vec<Option<(Scopes, Assigned)>>,
+}
+
+enum Step {
+    Next,
+    /// Waiting for an event, the instruction runs again when woken up.
+    Wait,
+    Done(Value),
 }
 
 impl Runtime {
+    /// A runtime with an event bus of its own.
+    #[cfg(test)]
     pub fn new(module: &Arc<Module>) -> Runtime {
+        Runtime::listening(module, EventBus::new().listen())
+    }
+
+    pub fn listening(module: &Arc<Module>, listener: Listener) -> Runtime {
         Runtime {
             module: module.clone(),
-            scope: Scope::new(),
+            listener,
+            scopes: Scopes::new(),
+            frames: Vec::new(),
+            stack: Vec::new(),
+            join: None,
+            assigned: HashSet::new(),
         }
     }
 
+    /// The module with the functions and types declared by the code that has
+    /// run so far.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    pub fn set_variable<S>(&mut self, name: S, value: Value)
+    where
+        S: Into<String>,
+    {
+        self.scopes.declare(name.into(), value);
+    }
+
+    /// Compiles and runs the expression to the end on an executor of its own.
+    #[cfg(test)]
     pub fn run(&mut self, exp: &Exp) -> Result<Value> {
-        self.run_exp(exp)
+        let program = compile(exp)?;
+        self.start(&program.code);
+        let mut executor = current_thread::Runtime::new().expect("can't start an executor");
+        executor.block_on(future::poll_fn(|| self.resume()))
+    }
+
+    /// Starts running the code, which runs as the runtime is polled.
+    pub fn start(&mut self, code: &Arc<Code>) {
+        self.frames.push(Frame {
+            code: Arc::clone(code),
+            pc: 0,
+            stack_base: self.stack.len(),
+            loops: Vec::new(),
+            caller_scope: None,
+        });
     }
 
-    fn run_exp(&mut self, exp: &Exp) -> Result<Value> {
-        match exp {
-            Exp::Call(call) => {
-                let v = self.module.get_function(&call.name);
-                match v {
-                    Some(Function::NativeFunction(native)) => {
-                        Ok(native.call(self.run_list(&call.args)?))
+    fn resume(&mut self) -> Poll<Value, OmgError> {
+        let result = self.run_steps();
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn run_steps(&mut self) -> Poll<Value, OmgError> {
+        for _ in 0..STEPS_PER_POLL {
+            let (code, pc) = match self.frames.last_mut() {
+                Some(frame) => {
+                    frame.pc += 1;
+                    (Arc::clone(&frame.code), frame.pc - 1)
+                }
+                None => return Ok(Async::Ready(Value::Nothing)),
+            };
+            match self.step(&code.instructions[pc])? {
+                Step::Next => (),
+                Step::Wait => {
+                    self.frame().pc = pc;
+                    return Ok(Async::NotReady);
+                }
+                Step::Done(value) => return Ok(Async::Ready(value)),
+            }
+        }
+        task::current().notify();
+        Ok(Async::NotReady)
+    }
+
+    /// Drops the code that was running after an error, going back to the top
+    /// level scope.
+    fn reset(&mut self) {
+        if let Some(scopes) = self.frames.drain(..).find_map(|frame| frame.caller_scope) {
+            self.scopes = scopes;
+        }
+        self.scopes.truncate(1);
+        self.stack.clear();
+        if self.join.take().is_some() {
+            self.listener.resume();
+        }
+    }
+
+    fn step(&mut self, instruction: &Instruction) -> Result<Step> {
+        match instruction {
+            Instruction::Push(value) => self.stack.push(value.clone()),
+            Instruction::Pop => {
+                self.pop();
+            }
+            Instruction::Load(name, pos) => {
+                if let Some(value) = self.scopes.get(name) {
+                    self.stack.push(value.clone());
+                } else if let Some((data_type, index)) = unit_variant(&self.module, name) {
+                    self.stack
+                        .push(new_variant(data_type, index, Vector::new()));
+                } else {
+                    return Err(self.missing_variable(name, "", pos));
+                }
+            }
+            Instruction::Declare(name) => {
+                let value = self.pop();
+                self.scopes.declare(name.clone(), value);
+            }
+            Instruction::Store(name, pos) => {
+                let value = self.pop();
+                match self.scopes.assign(name, value) {
+                    Some(level) => self.note_assigned(level, name),
+                    None => return Err(self.missing_variable(name, " to assign to", pos)),
+                }
+            }
+            Instruction::PushScope => self.scopes.push(),
+            Instruction::PopScope => self.scopes.pop(),
+            Instruction::Operator(op_type, pos) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                self.stack.push(run_operator(*op_type, &lhs, &rhs, pos)?);
+            }
+            Instruction::ShortCircuit {
+                op_type,
+                target,
+                pos,
+            } => match (op_type, self.stack.last()) {
+                (OpType::And, Some(Value::False)) | (OpType::Or, Some(Value::True)) => {
+                    self.frame().pc = *target
+                }
+                (_, Some(Value::True)) | (_, Some(Value::False)) => (),
+                (_, value) => {
+                    let value = value.expect("short circuit without a value");
+                    let error = OpError::Type(TypeError {
+                        lhs: value.type_name().to_string(),
+                        rhs: None,
+                    });
+                    return Err(op_error(op_type.symbol(), error, pos));
+                }
+            },
+            Instruction::Unary(unary_type, pos) => {
+                let value = self.pop();
+                self.stack.push(run_unary(*unary_type, &value, pos)?);
+            }
+            Instruction::Call { name, args, pos } => self.call(name, *args, pos)?,
+            Instruction::Method {
+                type_name,
+                name,
+                args,
+                pos,
+            } => return self.call_method(type_name, name, *args, pos),
+            Instruction::List(count) => {
+                let items = self.pop_many(*count);
+                self.stack.push(Value::List(items));
+            }
+            Instruction::Index { pos, index_pos } => {
+                let i = self.pop();
+                let value = self.pop();
+                self.stack.push(run_index(value, i, pos, index_pos)?);
+            }
+            Instruction::New { name, args, pos } => {
+                let record = self.new_record(name, *args, pos)?;
+                self.stack.push(record);
+            }
+            Instruction::Field(name, pos) => {
+                let value = self.pop();
+                self.stack.push(run_field(value, name, pos)?);
+            }
+            Instruction::Jump(target) => self.frame().pc = *target,
+            Instruction::JumpUnless { target, name, pos } => match self.pop() {
+                Value::True => (),
+                Value::False => self.frame().pc = *target,
+                value => {
+                    return Err(OmgError::new(
+                        format!(
+                            "Expected {} condition to be a boolean found {}",
+                            name, value
+                        ),
+                        pos.clone(),
+                    ))
+                }
+            },
+            Instruction::Loop { exit, next } => {
+                let stack_height = self.stack.len();
+                let scopes = self.scopes.depth();
+                self.frame().loops.push(Loop {
+                    exit: *exit,
+                    next: *next,
+                    stack_height,
+                    scopes,
+                    items: Vector::new(),
+                });
+            }
+            Instruction::EndLoop => {
+                self.frame().loops.pop();
+            }
+            Instruction::Break => {
+                let (stack_height, scopes, exit) = {
+                    let innermost = self.innermost_loop();
+                    (innermost.stack_height, innermost.scopes, innermost.exit)
+                };
+                self.stack.truncate(stack_height);
+                self.scopes.truncate(scopes);
+                self.frame().pc = exit;
+            }
+            Instruction::Continue => {
+                let (stack_height, scopes, next) = {
+                    let innermost = self.innermost_loop();
+                    (innermost.stack_height, innermost.scopes, innermost.next)
+                };
+                self.stack.truncate(stack_height);
+                self.scopes.truncate(scopes);
+                self.frame().pc = next;
+            }
+            Instruction::Iterate(pos) => {
+                let collection = self.pop();
+                match collection.iterate() {
+                    Some(items) => self.innermost_loop().items = items,
+                    None => {
+                        return Err(OmgError::new(
+                            format!("Can't iterate over {}", collection),
+                            pos.clone(),
+                        ))
                     }
-                    _ => Err(OmgError::new(
-                        format!("Cant find function named {} to call", call.name),
-                        call.pos.clone(),
-                    )),
                 }
             }
-            Exp::Block(block) => {
-                self.run_list(&block.statements)?;
-                Ok(Value::Nothing)
+            Instruction::NextItem { name, exit } => match self.innermost_loop().items.pop_front() {
+                Some(item) => self.scopes.declare(name.clone(), item),
+                None => self.frame().pc = *exit,
+            },
+            Instruction::Return => {
+                let value = self.pop();
+                if let Some(value) = self.return_value(value) {
+                    return Ok(Step::Done(value));
+                }
+            }
+            Instruction::Try(pos) => match run_try(self.pop(), pos)? {
+                Ok(value) => self.stack.push(value),
+                Err(value) => {
+                    if let Some(value) = self.return_value(value) {
+                        return Ok(Step::Done(value));
+                    }
+                }
+            },
+            Instruction::Async(statements, pos) => {
+                if self.join.is_none() {
+                    self.spawn_async(statements);
+                }
+                return self.poll_join(pos);
+            }
+            // Those of the top level go in the module, where the handlers
+            // find them, the others are gone with their block.
+            Instruction::DeclareFunction(function) => {
+                if self.frames.len() == 1 && self.scopes.depth() == 1 {
+                    let name = function.def.name.clone();
+                    let function = Function::UserFunction(Arc::clone(function));
+                    self.module = Arc::new(self.module.add_function(name, function));
+                } else {
+                    self.scopes.declare_function(function);
+                }
+            }
+            Instruction::DeclareType(record_type) => {
+                self.module = Arc::new(self.module.add_type(Arc::clone(record_type)));
+            }
+            Instruction::DeclareData(data_type) => {
+                self.module = Arc::new(self.module.add_data(data_type));
+            }
+            Instruction::Match { pattern, fail } => {
+                let value = self.stack.last().expect("the stack is empty");
+                let mut bindings = Vec::new();
+                if match_pattern(&self.module, pattern, value, &mut bindings) {
+                    for (name, value) in bindings {
+                        self.scopes.declare(name, value);
+                    }
+                } else {
+                    self.frame().pc = *fail;
+                }
+            }
+            Instruction::NoMatch(pos) => {
+                let value = self.pop();
+                return Err(OmgError::new(
+                    format!("No arm of the match matched {}", value),
+                    pos.clone(),
+                ));
+            }
+        }
+        Ok(Step::Next)
+    }
+
+    /// Returns from the current function, giving the value when it was the
+    /// last one.
+    fn return_value(&mut self, value: Value) -> Option<Value> {
+        let frame = self.frames.pop().expect("return without a function");
+        self.stack.truncate(frame.stack_base);
+        if let Some(scopes) = frame.caller_scope {
+            self.scopes = scopes;
+        }
+        if self.frames.is_empty() {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    fn missing_variable(&self, name: &str, to: &str, pos: &Position) -> OmgError {
+        OmgError::new(
+            format!(
+                "Cant find variable named {}{}{}",
+                name,
+                to,
+                did_you_mean(name, self.scopes.names())
+            ),
+            pos.clone(),
+        )
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("no function is running")
+    }
+
+    fn innermost_loop(&mut self) -> &mut Loop {
+        self.frame().loops.last_mut().expect("not inside a loop")
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack is empty")
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vector<Value> {
+        let at = self.stack.len() - count;
+        self.stack.split_off(at).into()
+    }
+
+    fn call(&mut self, name: &str, args: usize, pos: &Position) -> Result<()> {
+        if let Some((function, visible)) = self.scopes.function(name) {
+            return self.call_user_function(&function, visible, args, pos);
+        }
+        match self.module.get_function(name) {
+            Some(Function::NativeFunction(native)) => {
+                let args = self.pop_many(args);
+                let value = native.call(&self.module, args, pos)?;
+                self.stack.push(value);
+            }
+            Some(Function::UserFunction(function)) => {
+                return self.call_user_function(&function, Vec::new(), args, pos);
+            }
+            Some(Function::Constructor(data_type, index)) => {
+                let fields = data_type.variants[index].fields.len();
+                if fields != args {
+                    return Err(OmgError::new(
+                        format!(
+                            "Function {} takes {} arguments but {} were given",
+                            name, fields, args
+                        ),
+                        pos.clone(),
+                    ));
+                }
+                let values = self.pop_many(args);
+                self.stack.push(new_variant(data_type, index, values));
+            }
+            None => {
+                return Err(OmgError::new(
+                    format!(
+                        "Cant find function named {} to call{}",
+                        name,
+                        did_you_mean(
+                            name,
+                            self.scopes
+                                .function_names()
+                                .chain(self.module.function_names())
+                        )
+                    ),
+                    pos.clone(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the function with the functions it sees, as those of the code
+    /// calling it are not in reach of the function.
+    fn call_user_function(
+        &mut self,
+        function: &Arc<UserFunction>,
+        visible: Vec<Functions>,
+        args: usize,
+        pos: &Position,
+    ) -> Result<()> {
+        let def = &function.def;
+        if def.params.len() != args {
+            return Err(OmgError::new(
+                format!(
+                    "Function {} takes {} arguments but {} were given",
+                    def.name,
+                    def.params.len(),
+                    args
+                ),
+                pos.clone(),
+            ));
+        }
+        let args = self.pop_many(args);
+        let scope = def
+            .params
+            .iter()
+            .map(|p| p.name.clone())
+            .zip(args)
+            .collect();
+        let scopes = Scopes::with_functions(scope, visible);
+        let caller_scope = std::mem::replace(&mut self.scopes, scopes);
+        self.frames.push(Frame {
+            code: Arc::clone(&function.code),
+            pc: 0,
+            stack_base: self.stack.len(),
+            loops: Vec::new(),
+            caller_scope: Some(caller_scope),
+        });
+        Ok(())
+    }
+
+    /// Events have the `emit(event)` and `next()` methods.
+    fn call_method(
+        &mut self,
+        type_name: &str,
+        name: &str,
+        args: usize,
+        pos: &Position,
+    ) -> Result<Step> {
+        let record_type = match self.module.get_type(type_name) {
+            Some(record_type) => record_type,
+            None => {
+                return Err(OmgError::new(
+                    format!("Cant find type named {} to call {} on", type_name, name),
+                    pos.clone(),
+                ))
+            }
+        };
+        let expected_args = match name {
+            "emit" if record_type.kind == RecordKind::Event => 1,
+            "next" if record_type.kind == RecordKind::Event => 0,
+            _ => {
+                return Err(OmgError::new(
+                    format!("{} has no method named {}", type_name, name),
+                    pos.clone(),
+                ))
+            }
+        };
+        if args != expected_args {
+            return Err(OmgError::new(
+                format!(
+                    "Method {}.{} takes {} arguments but {} were given",
+                    type_name, name, expected_args, args
+                ),
+                pos.clone(),
+            ));
+        }
+        if name == "next" {
+            return match self.listener.next(type_name) {
+                Next::Event(record) => {
+                    self.stack.push(Value::Record(record));
+                    Ok(Step::Next)
+                }
+                Next::Waiting => Ok(Step::Wait),
+                Next::Closed => Err(OmgError::new(
+                    format!(
+                        "{}.next() is waiting for an event that will never be emitted",
+                        type_name
+                    ),
+                    pos.clone(),
+                )),
+            };
+        }
+        match self.pop() {
+            Value::Record(record) if record.record_type == record_type => {
+                self.listener.emit(record);
+                self.stack.push(Value::Nothing);
+                Ok(Step::Next)
+            }
+            value => Err(OmgError::new(
+                format!(
+                    "Expected {}.emit to get a {} found {}",
+                    type_name, type_name, value
+                ),
+                pos.clone(),
+            )),
+        }
+    }
+
+    fn spawn_async(&mut self, statements: &[Arc<Code>]) {
+        let mut receivers = Vec::new();
+        for code in statements {
+            let (sender, receiver) = oneshot::channel();
+            let mut runtime = Runtime::listening(&self.module, self.listener.fork());
+            runtime.scopes = self.scopes.clone();
+            runtime.start(code);
+            let mut runtime = Some(runtime);
+            let mut sender = Some(sender);
+            tokio::spawn(future::poll_fn(move || {
+                let sending = sender.as_mut().expect("polled after completion");
+                // The block is gone when another statement failed, the
+                // statement stops and its runtime leaves the bus.
+                if let Ok(Async::Ready(())) = sending.poll_close() {
+                    runtime = None;
+                    return Ok(Async::Ready(()));
+                }
+                let running = runtime.as_mut().expect("polled after completion");
+                // Leave the bus before the result is sent, so the events seen
+                // are passed on before the block continues.
+                let result = match running.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(_)) => {
+                        let done = runtime.take().unwrap();
+                        Ok((done.scopes, done.assigned))
+                    }
+                    Err(error) => {
+                        runtime = None;
+                        Err(error)
+                    }
+                };
+                let _ = sender.take().unwrap().send(result);
+                Ok(Async::Ready(()))
+            }));
+            receivers.push(receiver);
+        }
+        self.listener.suspend();
+        self.join = Some(Join {
+            done: vec![None; receivers.len()],
+            statements: receivers,
+        });
+    }
+
+    /// Waits for every statement of the `async` block and then merges their
+    /// assignments into the scope, in the order the statements are written.
+    /// When a statement fails the others are stopped.
+    fn poll_join(&mut self, pos: &Position) -> Result<Step> {
+        let join = self.join.as_mut().expect("not in an async block");
+        let mut waiting = false;
+        let mut failed = None;
+        for (receiver, done) in join.statements.iter_mut().zip(join.done.iter_mut()) {
+            if done.is_some() {
+                continue;
+            }
+            match receiver.poll() {
+                Ok(Async::Ready(Ok(scope))) => *done = Some(scope),
+                Ok(Async::Ready(Err(error))) => {
+                    failed = Some(error);
+                    break;
+                }
+                Ok(Async::NotReady) => waiting = true,
+                Err(_) => {
+                    failed = Some(OmgError::new(
+                        "A statement of the async block stopped without finishing",
+                        pos.clone(),
+                    ));
+                    break;
+                }
+            }
+        }
+        if let Some(error) = failed {
+            // Dropping the receivers tells the other statements to stop.
+            self.join = None;
+            self.listener.resume();
+            return Err(error);
+        }
+        if waiting {
+            return Ok(Step::Wait);
+        }
+        let join = self.join.take().unwrap();
+        self.listener.resume();
+        for (scopes, assigned) in join.done.into_iter().flatten() {
+            for (level, name) in assigned {
+                // The variables a statement declares stay in its own scopes.
+                let value = scopes
+                    .variables
+                    .get(level)
+                    .and_then(|scope| scope.get(&name));
+                if let (Some(scope), Some(value)) = (self.scopes.variables.get_mut(level), value) {
+                    scope.insert(name.clone(), value.clone());
+                    self.note_assigned(level, &name);
+                }
+            }
+        }
+        self.stack.push(Value::Nothing);
+        Ok(Step::Next)
+    }
+
+    /// Assignments in a function body are to variables of its own, so only
+    /// those of the top level are noted.
+    fn note_assigned(&mut self, level: usize, name: &str) {
+        if self.frame().caller_scope.is_none() {
+            self.assigned.insert((level, name.to_string()));
+        }
+    }
+
+    fn new_record(&mut self, name: &str, args: usize, pos: &Position) -> Result<Value> {
+        let record_type = match self.module.get_type(name) {
+            Some(record_type) => record_type,
+            None => {
+                return Err(OmgError::new(
+                    format!("Cant find type named {} to create", name),
+                    pos.clone(),
+                ))
+            }
+        };
+        if record_type.fields.len() != args {
+            return Err(OmgError::new(
+                format!(
+                    "{} has {} fields but {} values were given",
+                    record_type.name,
+                    record_type.fields.len(),
+                    args
+                ),
+                pos.clone(),
+            ));
+        }
+        let values = self.pop_many(args);
+        Ok(Value::Record(Record {
+            record_type,
+            values,
+        }))
+    }
+}
+
+impl Future for Runtime {
+    type Item = Value;
+    type Error = OmgError;
+
+    fn poll(&mut self) -> Poll<Value, OmgError> {
+        self.resume()
+    }
+}
+
+fn run_operator(op_type: OpType, lhs: &Value, rhs: &Value, pos: &Position) -> Result<Value> {
+    let result = match op_type {
+        OpType::Add => lhs.add(rhs),
+        OpType::Subtract => lhs.subtract(rhs),
+        OpType::Multiply => lhs.multiply(rhs),
+        OpType::Divide => lhs.divide(rhs),
+        OpType::Remainder => lhs.remainder(rhs),
+        OpType::Power => lhs.power(rhs),
+        OpType::Equal => lhs.equal(rhs),
+        OpType::NotEqual => lhs.not_equal(rhs),
+        OpType::GreaterThan => lhs.greater_than(rhs),
+        OpType::GreaterEqual => lhs.greater_equal(rhs),
+        OpType::LessThan => lhs.less_than(rhs),
+        OpType::LessEqual => lhs.less_equal(rhs),
+        OpType::And => lhs.and(rhs),
+        OpType::Or => lhs.or(rhs),
+    };
+    result.map_err(|error| op_error(op_type.symbol(), error, pos))
+}
+
+fn run_unary(unary_type: UnaryType, value: &Value, pos: &Position) -> Result<Value> {
+    let result = match unary_type {
+        UnaryType::Negate => value.negate(),
+        UnaryType::Plus => value.plus(),
+        UnaryType::Not => value.not(),
+    };
+    result.map_err(|error| op_error(unary_type.symbol(), error, pos))
+}
+
+fn op_error(symbol: &str, error: OpError, pos: &Position) -> OmgError {
+    let msg = match error {
+        OpError::Type(TypeError {
+            lhs,
+            rhs: Some(rhs),
+        }) => format!("Can't use {} on {} and {}", symbol, lhs, rhs),
+        OpError::Type(TypeError { lhs, rhs: None }) => {
+            format!("Can't use {} on {}", symbol, lhs)
+        }
+        OpError::Overflow => format!("The result of {} is too large for an Int", symbol),
+        OpError::DivisionByZero => format!("Can't use {} to divide by zero", symbol),
+        OpError::NegativeExponent => "Can't raise an Int to a negative power".to_string(),
+    };
+    OmgError::new(msg, pos.clone())
+}
+
+fn new_variant(data_type: Arc<DataType>, index: usize, values: Vector<Value>) -> Value {
+    Value::Variant(Variant {
+        data_type,
+        index,
+        values,
+    })
+}
+
+/// The data type of a variant without fields, as they are used like
+/// variables.
+fn unit_variant(module: &Module, name: &str) -> Option<(Arc<DataType>, usize)> {
+    match module.get_function(name) {
+        Some(Function::Constructor(data_type, index))
+            if data_type.variants[index].fields.is_empty() =>
+        {
+            Some((data_type, index))
+        }
+        _ => None,
+    }
+}
+
+fn is_variant(value: &Value, data_type: &DataType, index: usize) -> bool {
+    match value {
+        Value::Variant(variant) => {
+            variant.data_type.name == data_type.name && variant.index == index
+        }
+        _ => false,
+    }
+}
+
+/// Collects the variables the pattern binds, if the value matches it.
+fn match_pattern(
+    module: &Module,
+    pattern: &Pattern,
+    value: &Value,
+    bindings: &mut Vec<(String, Value)>,
+) -> bool {
+    match pattern {
+        Pattern::Wildcard(_) => true,
+        Pattern::Name(name, _) => match unit_variant(module, name) {
+            Some((data_type, index)) => is_variant(value, &data_type, index),
+            None => {
+                bindings.push((name.clone(), value.clone()));
+                true
+            }
+        },
+        Pattern::Literal(literal, _) => literal == value,
+        Pattern::Variant { name, args, .. } => match (module.get_function(name), value) {
+            (Some(Function::Constructor(data_type, index)), Value::Variant(variant))
+                if is_variant(value, &data_type, index) && args.len() == variant.values.len() =>
+            {
+                args.iter()
+                    .zip(variant.values.iter())
+                    .all(|(arg, value)| match_pattern(module, arg, value, bindings))
+            }
+            _ => false,
+        },
+        Pattern::Record { name, fields, .. } => match value {
+            Value::Record(record) if record.record_type.name == *name => {
+                fields
+                    .iter()
+                    .all(|(field, pattern)| match record.get(field) {
+                        Some(value) => match_pattern(module, pattern, value, bindings),
+                        None => false,
+                    })
             }
-            Exp::Literal(literal) => Ok(literal.value),
-            Exp::Assignment(assignment) => {
-                let value = self.run_exp(&assignment.value)?;
-                self.scope.insert(assignment.name.clone(), value);
-                Ok(Value::Nothing)
+            _ => false,
+        },
+        Pattern::List { items, rest, .. } => match value {
+            Value::List(values) => {
+                let fits = match rest {
+                    Some(_) => values.len() >= items.len(),
+                    None => values.len() == items.len(),
+                };
+                let items_match = fits
+                    && items
+                        .iter()
+                        .zip(values.iter())
+                        .all(|(item, value)| match_pattern(module, item, value, bindings));
+                match rest {
+                    Some(rest) if items_match => {
+                        let rest_values = Value::List(values.skip(items.len()));
+                        match_pattern(module, rest, &rest_values, bindings)
+                    }
+                    _ => items_match,
+                }
+            }
+            _ => false,
+        },
+    }
+}
+
+/// The value to continue with for `Ok` and `Some`, or the value to return.
+fn run_try(value: Value, pos: &Position) -> Result<std::result::Result<Value, Value>> {
+    match value {
+        Value::Variant(variant)
+            if variant.data_type.name == RESULT || variant.data_type.name == OPTION =>
+        {
+            if variant.index == 0 {
+                Ok(Ok(variant.values[0].clone()))
+            } else {
+                Ok(Err(Value::Variant(variant)))
             }
-            Exp::Variable(variable) => Ok(*self
-                .scope
-                .get(&variable.name)
-                .unwrap_or(&Value::Nothing)),
-            Exp::Operator(op) => self.run_operator(op),
         }
+        value => Err(OmgError::new(
+            format!("Can't use ? on {}", value.type_name()),
+            pos.clone(),
+        )),
     }
+}
 
-    fn run_list(&mut self, expressions: &[Exp]) -> Result<Vector<Value>> {
-        expressions.iter().map(|exp| self.run_exp(exp)).collect()
+fn run_field(value: Value, name: &str, pos: &Position) -> Result<Value> {
+    match value {
+        Value::Record(record) => match record.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(OmgError::new(
+                format!("{} has no field named {}", record.record_type.name, name),
+                pos.clone(),
+            )),
+        },
+        value => Err(OmgError::new(
+            format!("Can't read field {} from {}", name, value),
+            pos.clone(),
+        )),
     }
+}
 
-    fn run_operator(&mut self, op: &Operator) -> Result<Value> {
-        let lhs = self.run_exp(&op.lhs)?;
-        let rhs = self.run_exp(&op.rhs)?;
-        Ok(match op.op_type {
-            OpType::Add => lhs.add(&rhs),
-            OpType::Subtract => lhs.subtract(&rhs),
-            OpType::Multiply => lhs.multiply(&rhs),
-            OpType::Divide => lhs.divide(&rhs),
-            OpType::Equal => lhs.equal(&rhs),
-            OpType::GreaterThan => lhs.greater_than(&rhs),
-            OpType::LessThan => lhs.less_than(&rhs),
-        })
+fn run_index(value: Value, i: Value, pos: &Position, index_pos: &Position) -> Result<Value> {
+    let items = match value {
+        Value::List(items) => items,
+        value => {
+            return Err(OmgError::new(
+                format!("Can't index into {}", value),
+                pos.clone(),
+            ))
+        }
+    };
+    match i {
+        Value::Int(n) if n >= 0 && (n as usize) < items.len() => Ok(items[n as usize].clone()),
+        Value::Int(n) => Err(OmgError::new(
+            format!(
+                "Index {} is out of bounds for list of length {}",
+                n,
+                items.len()
+            ),
+            index_pos.clone(),
+        )),
+        i => Err(OmgError::new(
+            format!("Expected list index to be an Int found {}", i),
+            index_pos.clone(),
+        )),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::Position;
 
     #[test]
     fn literal() {
         let mut run = Runtime::new(&Arc::new(Module::new()));
 
-        let value = Value::Number(42.0);
-        let exp = Exp::new_literal(value, Position::new("test"));
+        let value = Value::Int(42);
+        let exp = Exp::new_literal(value.clone(), Position::new("test"));
         assert_eq!(run.run(&exp).unwrap(), value);
     }
 
@@ -95,7 +1034,7 @@ mod tests {
         let mut run = Runtime::new(&Arc::new(Module::new()));
 
         let exp = Exp::new_block(
-            vec![Exp::new_literal(Value::Number(42.0), Position::new("test"))],
+            vec![Exp::new_literal(Value::Int(42), Position::new("test"))],
             Position::new("test"),
         );
         assert_eq!(run.run(&exp).unwrap(), Value::Nothing);
@@ -112,13 +1051,544 @@ mod tests {
     #[test]
     fn set_get_variable() {
         let mut run = Runtime::new(&Arc::new(Module::new()));
-        let set = Exp::new_assignment(
+        let set = Exp::new_declaration(
+            Declaration::Let,
+            None,
             "test".to_string(),
-            Box::new(Exp::new_literal(Value::Number(42.0), Position::new("test"))),
+            Box::new(Exp::new_literal(Value::Int(42), Position::new("test"))),
             Position::new("test"),
         );
         run.run(&set).unwrap();
         let get = Exp::new_variable("test".to_string(), Position::new("test"));
-        assert_eq!(run.run(&get).unwrap(), Value::Number(42.0));
+        assert_eq!(run.run(&get).unwrap(), Value::Int(42));
+    }
+
+    #[test]
+    fn negate() {
+        let mut run = Runtime::new(&Arc::new(Module::new()));
+        let exp = Exp::new_unary(
+            UnaryType::Negate,
+            Box::new(Exp::new_literal(Value::Int(42), Position::new("test"))),
+            Position::new("test"),
+        );
+        assert_eq!(run.run(&exp).unwrap(), Value::Int(-42));
+    }
+
+    fn number(n: i64) -> Box<Exp> {
+        Box::new(Exp::new_literal(Value::Int(n), Position::new("test")))
+    }
+
+    #[test]
+    fn if_else() {
+        let mut run = Runtime::new(&Arc::new(Module::new()));
+        let exp = |condition| {
+            Exp::new_if(
+                Box::new(Exp::new_literal(condition, Position::new("test"))),
+                number(1),
+                Some(number(2)),
+                Position::new("test"),
+            )
+        };
+        assert_eq!(run.run(&exp(Value::True)).unwrap(), Value::Int(1));
+        assert_eq!(run.run(&exp(Value::False)).unwrap(), Value::Int(2));
+    }
+
+    #[test]
+    fn if_without_else() {
+        let mut run = Runtime::new(&Arc::new(Module::new()));
+        let exp = Exp::new_if(
+            Box::new(Exp::new_literal(Value::False, Position::new("test"))),
+            number(1),
+            None,
+            Position::new("test"),
+        );
+        assert_eq!(run.run(&exp).unwrap(), Value::Nothing);
+    }
+
+    #[test]
+    fn if_condition_not_boolean() {
+        let mut run = Runtime::new(&Arc::new(Module::new()));
+        let exp = Exp::new_if(
+            number(1),
+            number(1),
+            None,
+            Position::new("test").with_pos(3, 4),
+        );
+        let err = run.run(&exp).unwrap_err();
+        assert_eq!(err.pos.to_string(), "test:1:1");
+    }
+
+    #[test]
+    fn block_value() {
+        let mut run = Runtime::new(&Arc::new(Module::new()));
+        let exp = Exp::new_block_value(vec![*number(1)], number(2), Position::new("test"));
+        assert_eq!(run.run(&exp).unwrap(), Value::Int(2));
+    }
+
+    fn run_source(source: &str) -> Result<Runtime> {
+        use crate::pipeline::{lexer, parse_block, Source};
+        let mut tokens = lexer(Source {
+            path: "test".to_string(),
+            source: source.to_string(),
+        })?;
+        let exp = parse_block(&mut tokens)?;
+        let mut run = Runtime::new(&Arc::new(Module::new()));
+        run.run(&exp)?;
+        Ok(run)
+    }
+
+    fn get(run: &Runtime, name: &str) -> Value {
+        run.scopes.get(name).cloned().unwrap_or(Value::Nothing)
+    }
+
+    #[test]
+    fn while_loop() {
+        let run = run_source("var i = 0; while i < 5 { i = i + 1 }").unwrap();
+        assert_eq!(get(&run, "i"), Value::Int(5));
+    }
+
+    #[test]
+    fn while_break_continue() {
+        let run = run_source(
+            "var i = 0; var sum = 0;
+            while true {
+                i = i + 1;
+                if i > 10 { break }
+                if i > 3 { continue }
+                sum = sum + i;
+            }",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "i"), Value::Int(11));
+        assert_eq!(get(&run, "sum"), Value::Int(6));
+    }
+
+    #[test]
+    fn for_loop() {
+        let run =
+            run_source(r#"var s = ""; for c in "abc" { if c == "b" { continue } s = c + s; }"#)
+                .unwrap();
+        assert_eq!(get(&run, "s"), Value::from_string("ca"));
+    }
+
+    #[test]
+    fn for_not_iterable() {
+        let err = run_source("for c in 1 { }").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:0:9");
+    }
+
+    #[test]
+    fn break_outside_loop() {
+        let err = run_source("let a = 1;\nbreak;").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:0");
+    }
+
+    #[test]
+    fn user_function() {
+        let run = run_source(
+            "fn add(a, b) { a + b }
+            fn sub(a, b) { return a - b; 0 }
+            let a = 1;
+            let x = add(2, 3);
+            let y = sub(2, 3);",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "x"), Value::Int(5));
+        assert_eq!(get(&run, "y"), Value::Int(-1));
+        assert_eq!(get(&run, "a"), Value::Int(1));
+    }
+
+    #[test]
+    fn user_function_recursion_before_declaration() {
+        let run = run_source(
+            "let x = fib(10);
+            fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "x"), Value::Int(55));
+    }
+
+    #[test]
+    fn user_function_own_scope() {
+        let run = run_source(
+            "let a = 1;
+            fn set() { let a = 2; a }
+            let b = set();",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(1));
+        assert_eq!(get(&run, "b"), Value::Int(2));
+    }
+
+    #[test]
+    fn user_function_in_block() {
+        let run = run_source(
+            "fn f() { 1 }
+            fn g() { f() }
+            let a = if true {
+                fn f() { 2 }
+                fn twice(n) { if n == 0 { 0 } else { f() + twice(n - 1) } }
+                twice(2) + g()
+            } else {
+                0
+            };
+            let b = f();",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(5));
+        assert_eq!(get(&run, "b"), Value::Int(1));
+        let err = run_source("if true { fn inner() { 1 } }\nprint(inner());")
+            .err()
+            .unwrap();
+        assert_eq!(err.msg, "Cant find function named inner to call");
+        assert_eq!(err.pos.to_string(), "test:1:6");
+    }
+
+    #[test]
+    fn user_function_wrong_arity() {
+        let err = run_source("fn add(a, b) { a + b }\nadd(1);").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:0");
+    }
+
+    #[test]
+    fn return_outside_function() {
+        run_source("return 1;").err().unwrap();
+    }
+
+    #[test]
+    fn break_out_of_function() {
+        run_source("fn f() { break } while true { f() }")
+            .err()
+            .unwrap();
+    }
+
+    #[test]
+    fn list_index() {
+        let run = run_source("let xs = [1, [2, 3]]; let a = xs[0]; let b = xs[1][1];").unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(1));
+        assert_eq!(get(&run, "b"), Value::Int(3));
+    }
+
+    #[test]
+    fn list_index_out_of_bounds() {
+        let err = run_source("let xs = [1, 2];\nxs[2];").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:3");
+        run_source("[1, 2][-1];").err().unwrap();
+        run_source("[1, 2][0.5];").err().unwrap();
+        run_source("[1, 2][true];").err().unwrap();
+        run_source("1[0];").err().unwrap();
+    }
+
+    #[test]
+    fn for_over_list() {
+        let run = run_source("var sum = 0; for x in [1, 2, 3] { sum = sum + x }").unwrap();
+        assert_eq!(get(&run, "sum"), Value::Int(6));
+    }
+
+    #[test]
+    fn record() {
+        let run = run_source(
+            r#"let p = new Point(1, "a");
+            record Point { Number x; y; }
+            let x = p.x;
+            let y = p.y;"#,
+        )
+        .unwrap();
+        assert_eq!(get(&run, "x"), Value::Int(1));
+        assert_eq!(get(&run, "y"), Value::from_string("a"));
+    }
+
+    #[test]
+    fn record_unknown_field() {
+        let err = run_source("record A { a; }\nnew A(1).b;").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:9");
+        run_source("1.b;").err().unwrap();
+    }
+
+    #[test]
+    fn record_wrong_arity() {
+        let err = run_source("record A { a; }\nnew A(1, 2);").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:0");
+    }
+
+    #[test]
+    fn record_unknown_type() {
+        run_source("new A(1);").err().unwrap();
+    }
+
+    #[test]
+    fn emit_next() {
+        let run = run_source(
+            "event A { a; }
+            A.emit(new A(1));
+            A.emit(new A(2));
+            let x = A.next().a;
+            let y = A.next().a;",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "x"), Value::Int(1));
+        assert_eq!(get(&run, "y"), Value::Int(2));
+    }
+
+    #[test]
+    fn next_never_emitted() {
+        let err = run_source("event A { }\nA.next();").err().unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:2");
+    }
+
+    #[test]
+    fn method_errors() {
+        run_source("record A { }\nA.next();").err().unwrap();
+        run_source("event A { }\nA.emit(1);").err().unwrap();
+        run_source("event A { }\nA.emit();").err().unwrap();
+        run_source("event A { }\nA.nope();").err().unwrap();
+        run_source("B.next();").err().unwrap();
+    }
+
+    #[test]
+    fn long_loop_yields() {
+        let run = run_source("var i = 0; while i < 50000 { i = i + 1 }").unwrap();
+        assert_eq!(get(&run, "i"), Value::Int(50000));
+    }
+
+    #[test]
+    fn async_block() {
+        let run = run_source(
+            "var a = 1; var b = 2; var c = 3;
+            async {
+                a = b + 10;
+                b = a + 10;
+                c = 30;
+                c = 40;
+            }",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(12));
+        assert_eq!(get(&run, "b"), Value::Int(11));
+        assert_eq!(get(&run, "c"), Value::Int(40));
+        let run =
+            run_source("var a = 1; var b = 1;\nasync { a = 2; { b = 3; a = 1; } b = 2; }").unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(1));
+        assert_eq!(get(&run, "b"), Value::Int(2));
+    }
+
+    #[test]
+    fn async_next() {
+        let run = run_source(
+            "event A { a; }
+            event B { b; }
+            var a = 0;
+            var b = 0;
+            async {
+                a = A.next().a;
+                b = B.next().b;
+                { B.emit(new B(2)); A.emit(new A(1)); }
+            }
+            A.emit(new A(3));
+            let c = A.next().a;",
+        )
+        .unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(1));
+        assert_eq!(get(&run, "b"), Value::Int(2));
+        assert_eq!(get(&run, "c"), Value::Int(3));
+    }
+
+    #[test]
+    fn async_error() {
+        let err = run_source("var a = 1;\nasync { a = 2; let b = 1[0]; }")
+            .err()
+            .unwrap();
+        assert_eq!(err.pos.to_string(), "test:1:24");
+        let run = run_source("event A { }\nasync { A.next(); }")
+            .err()
+            .unwrap();
+        assert_eq!(run.pos.to_string(), "test:1:10");
+    }
+
+    #[test]
+    fn operator_type_error() {
+        let err = run_source("let a = 1;\nlet b = a + true;").err().unwrap();
+        assert_eq!(err.msg, "Can't use + on Int and Boolean");
+        assert_eq!(err.pos.to_string(), "test:1:10");
+        let err = run_source("let x = -\"a\";").err().unwrap();
+        assert_eq!(err.msg, "Can't use - on String");
+        assert_eq!(err.pos.to_string(), "test:0:8");
+        run_source("1 < \"a\";").err().unwrap();
+        run_source("[1] * 2;").err().unwrap();
+    }
+
+    #[test]
+    fn unknown_variable() {
+        let err = run_source("let hello = 1;\nprint(helo);").err().unwrap();
+        assert_eq!(
+            err.msg,
+            "Cant find variable named helo, did you mean hello?"
+        );
+        assert_eq!(err.pos.to_string(), "test:1:6");
+        let err = run_source("x;").err().unwrap();
+        assert_eq!(err.msg, "Cant find variable named x");
+    }
+
+    #[test]
+    fn block_scopes() {
+        let source = "
+            var a = 1;
+            let b = 1;
+            if true { a = 2; let b = 2; let c = 3; }
+            var i = 0;
+            while i < 3 { let d = i; i = i + 1; if d == 1 { break } }
+            for x in [1] { }
+        ";
+        let run = run_source(source).unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(2));
+        assert_eq!(get(&run, "b"), Value::Int(1));
+        assert_eq!(get(&run, "c"), Value::Nothing);
+        assert_eq!(get(&run, "d"), Value::Nothing);
+        assert_eq!(get(&run, "i"), Value::Int(2));
+        assert_eq!(get(&run, "x"), Value::Nothing);
+        assert_eq!(run.scopes.depth(), 1);
+        let err = run_source("let a = 1;\nfn f() { a = 2 }\nf();")
+            .err()
+            .unwrap();
+        assert_eq!(err.msg, "Cant find variable named a to assign to");
+        assert_eq!(err.pos.to_string(), "test:1:9");
+        let err = run_source("{ let a = 1; }\na;").err().unwrap();
+        assert_eq!(err.msg, "Cant find variable named a");
+    }
+
+    #[test]
+    fn unknown_function() {
+        let err = run_source("fn hello() { }\nhelo();").err().unwrap();
+        assert_eq!(
+            err.msg,
+            "Cant find function named helo to call, did you mean hello?"
+        );
+        assert_eq!(err.pos.to_string(), "test:1:0");
+    }
+
+    #[test]
+    fn match_variants() {
+        let source = "
+            type Shape = Circle(r) | Rect(w, h) | Dot;
+            fn area(s) {
+                match s {
+                    Circle(r) => 3 * r * r,
+                    Rect(w, h) if w == h => 0,
+                    Rect(w, h) => w * h,
+                    Dot => 1,
+                }
+            }
+            let a = area(Circle(2));
+            let b = area(Rect(2, 3));
+            let c = area(Rect(2, 2));
+            let d = area(Dot);
+        ";
+        let run = run_source(source).unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(12));
+        assert_eq!(get(&run, "b"), Value::Int(6));
+        assert_eq!(get(&run, "c"), Value::Int(0));
+        assert_eq!(get(&run, "d"), Value::Int(1));
+    }
+
+    #[test]
+    fn match_lists_and_records() {
+        let source = "
+            record Point { x; y; }
+            let a = match [1, 2, 3] { [] => 0, [first, ..rest] => rest };
+            let b = match new Point(1, 2) { Point { x: 2 } => 0, Point { y, .. } => y };
+            let c = match \"b\" { \"a\" => 1, _ => 2 };
+        ";
+        let run = run_source(source).unwrap();
+        let rest = Value::List(vec![Value::Int(2), Value::Int(3)].into());
+        assert_eq!(get(&run, "a"), rest);
+        assert_eq!(get(&run, "first"), Value::Nothing);
+        assert_eq!(get(&run, "b"), Value::Int(2));
+        assert_eq!(get(&run, "c"), Value::Int(2));
+    }
+
+    #[test]
+    fn no_match() {
+        let err = run_source("match 3 { 1 => 1, 2 => 2 }").err().unwrap();
+        assert_eq!(err.msg, "No arm of the match matched 3");
+        assert_eq!(err.pos.to_string(), "test:0:0");
+    }
+
+    #[test]
+    fn int_arithmetic() {
+        let source = "
+            let a = 7 / 2;
+            let b = 7 % 2;
+            let c = 2 ** 3 ** 2;
+            let d = 7.0 / 2.0;
+            let e = [1, 2, 3][2];
+            let f = -2 ** 2;
+        ";
+        let run = run_source(source).unwrap();
+        assert_eq!(get(&run, "a"), Value::Int(3));
+        assert_eq!(get(&run, "b"), Value::Int(1));
+        assert_eq!(get(&run, "c"), Value::Int(512));
+        assert_eq!(get(&run, "d"), Value::Float(3.5));
+        assert_eq!(get(&run, "e"), Value::Int(3));
+        assert_eq!(get(&run, "f"), Value::Int(-4));
+        let err = run_source("let x = 9223372036854775807;\nlet y = x + 1;")
+            .err()
+            .unwrap();
+        assert_eq!(err.msg, "The result of + is too large for an Int");
+        assert_eq!(err.pos.to_string(), "test:1:10");
+        let err = run_source("let x = 1 / 0;").err().unwrap();
+        assert_eq!(err.msg, "Can't use / to divide by zero");
+        assert_eq!(err.pos.to_string(), "test:0:10");
+        let err = run_source("let x = 1.0 % 0.0;").err().unwrap();
+        assert_eq!(err.msg, "Can't use % to divide by zero");
+        let err = run_source("let x = 1 + 1.0;").err().unwrap();
+        assert_eq!(err.msg, "Can't use + on Int and Float");
+        let err = run_source("let x = [1][1.0];").err().unwrap();
+        assert_eq!(err.msg, "Expected list index to be an Int found 1.0");
+    }
+
+    #[test]
+    fn short_circuit() {
+        // Dividing by zero fails, so these only run when the right hand side
+        // is skipped.
+        let source = "
+            let a = false && 1 / 0 == 1;
+            let b = true || 1 / 0 == 1;
+            let c = true && !false && 2 >= 2 && 1 != 2;
+            let d = false || 1 <= 0;
+        ";
+        let run = run_source(source).unwrap();
+        assert_eq!(get(&run, "a"), Value::False);
+        assert_eq!(get(&run, "b"), Value::True);
+        assert_eq!(get(&run, "c"), Value::True);
+        assert_eq!(get(&run, "d"), Value::False);
+        let err = run_source("let a = true && 1 / 0 == 1;").err().unwrap();
+        assert_eq!(err.msg, "Can't use / to divide by zero");
+        let err = run_source("let b = false || 1 / 0 == 1;").err().unwrap();
+        assert_eq!(err.msg, "Can't use / to divide by zero");
+        let err = run_source("1 && true;").err().unwrap();
+        assert_eq!(err.msg, "Can't use && on Int");
+        let err = run_source("false || 1;").err().unwrap();
+        assert_eq!(err.msg, "Can't use || on Boolean and Int");
+    }
+
+    #[test]
+    fn try_returns_early() {
+        let source = "
+            type Option<T> = Some(T value) | None;
+            fn add(a, b) { Some(a? + b?) }
+            let a = add(Some(1), Some(2));
+            let b = add(Some(1), None);
+        ";
+        let run = run_source(source).unwrap();
+        assert_eq!(get(&run, "a").to_string(), "Some(3)");
+        assert_eq!(get(&run, "b").to_string(), "None");
+        let err = run_source(
+            "fn f() { 1? }
+f();",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.msg, "Can't use ? on Int");
+        assert_eq!(err.pos.to_string(), "test:0:10");
     }
 }
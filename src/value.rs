@@ -1,11 +1,75 @@
-use im::HashMap;
+use im::{HashMap, Vector};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Nothing,
-    Number(f64),
+    Int(i64),
+    Float(f64),
     True,
     False,
+    String(Arc<str>),
+    List(Vector<Value>),
+    Record(Record),
+    Variant(Variant),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RecordKind {
+    Record,
+    Event,
+}
+
+/// The declaration of a record or event, shared by all values of the type.
+#[derive(Debug, PartialEq)]
+pub struct RecordType {
+    pub name: String,
+    pub kind: RecordKind,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub record_type: Arc<RecordType>,
+    pub values: Vector<Value>,
+}
+
+/// The declaration of a data type like `type Shape = Circle(r) | Rect(w, h)`,
+/// shared by all values of the type.
+#[derive(Debug, PartialEq)]
+pub struct DataType {
+    pub name: String,
+    pub variants: Vec<VariantType>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VariantType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+/// A value of a data type, `index` is which of its variants it is.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variant {
+    pub data_type: Arc<DataType>,
+    pub index: usize,
+    pub values: Vector<Value>,
+}
+
+impl Variant {
+    pub fn name(&self) -> &str {
+        &self.data_type.variants[self.index].name
+    }
+}
+
+impl Record {
+    pub fn get(&self, field: &str) -> Option<&Value> {
+        let index = self.record_type.fields.iter().position(|f| f == field)?;
+        self.values.get(index)
+    }
 }
 
 impl Value {
@@ -13,64 +77,283 @@ impl Value {
         if v { Value::True } else { Value::False }
     }
 
-    pub fn to_string(&self) -> String {
+    pub fn from_string<S>(v: S) -> Value
+    where
+        S: Into<Arc<str>>,
+    {
+        Value::String(v.into())
+    }
+
+    /// The name of the type of the value, used in error messages.
+    pub fn type_name(&self) -> &str {
         match self {
-            Value::Number(i) => format!("{}", i),
-            Value::Nothing => "Nothing".to_string(),
-            Value::True => "True".to_string(),
-            Value::False => "False".to_string(),
+            Value::Nothing => "Nothing",
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::True | Value::False => "Boolean",
+            Value::String(_) => "String",
+            Value::List(_) => "List",
+            Value::Record(record) => &record.record_type.name,
+            Value::Variant(variant) => &variant.data_type.name,
         }
     }
 
-    pub fn add(&self, other: &Value) -> Value {
-        let values = (self, other);
-        match values {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
-            _ => Value::Nothing,
+    fn type_error(&self, other: Option<&Value>) -> OpError {
+        OpError::Type(TypeError {
+            lhs: self.type_name().to_string(),
+            rhs: other.map(|other| other.type_name().to_string()),
+        })
+    }
+
+    /// Applies `int` to two Ints, failing when it overflows, or `float` to
+    /// two Floats.
+    fn arithmetic(
+        &self,
+        other: &Value,
+        int: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> OpResult {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => int(*a, *b).map(Value::Int).ok_or(OpError::Overflow),
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(float(*a, *b))),
+            _ => Err(self.type_error(Some(other))),
         }
     }
 
-    pub fn subtract(&self, other: &Value) -> Value {
-        let values = (self, other);
-        match values {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a - b),
-            _ => Value::Nothing,
+    fn is_zero(&self) -> bool {
+        match self {
+            Value::Int(n) => *n == 0,
+            Value::Float(n) => *n == 0.0,
+            _ => false,
         }
     }
 
-    pub fn multiply(&self, other: &Value) -> Value {
+    pub fn add(&self, other: &Value) -> OpResult {
         let values = (self, other);
         match values {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a * b),
-            _ => Value::Nothing,
+            (Value::String(a), Value::String(b)) => {
+                let mut string = String::with_capacity(a.len() + b.len());
+                string.push_str(a);
+                string.push_str(b);
+                Ok(Value::from_string(string))
+            }
+            _ => self.arithmetic(other, i64::checked_add, |a, b| a + b),
         }
     }
 
-    pub fn divide(&self, other: &Value) -> Value {
-        let values = (self, other);
-        match values {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a / b),
-            _ => Value::Nothing,
+    pub fn subtract(&self, other: &Value) -> OpResult {
+        self.arithmetic(other, i64::checked_sub, |a, b| a - b)
+    }
+
+    pub fn multiply(&self, other: &Value) -> OpResult {
+        self.arithmetic(other, i64::checked_mul, |a, b| a * b)
+    }
+
+    /// Like `arithmetic`, but dividing by zero is an error for Floats too.
+    fn division(
+        &self,
+        other: &Value,
+        int: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> OpResult {
+        match self.arithmetic(other, int, float) {
+            Err(OpError::Type(error)) => Err(OpError::Type(error)),
+            _ if other.is_zero() => Err(OpError::DivisionByZero),
+            result => result,
         }
     }
 
-    pub fn equal(&self, other: &Value) -> Value {
-        Value::from_bool(self == other)
+    /// Ints are divided into an Int, rounding towards zero.
+    pub fn divide(&self, other: &Value) -> OpResult {
+        self.division(other, i64::checked_div, |a, b| a / b)
     }
 
-    pub fn greater_than(&self, other: &Value) -> Value {
-        let values = (self, other);
-        match values {
-            (Value::Number(a), Value::Number(b)) => Value::from_bool(a > b),
-            _ => Value::Nothing,
+    /// The remainder has the sign of the left hand side.
+    pub fn remainder(&self, other: &Value) -> OpResult {
+        self.division(other, i64::checked_rem, |a, b| a % b)
+    }
+
+    /// Ints can only be raised to a power that is not negative.
+    pub fn power(&self, other: &Value) -> OpResult {
+        if let (Value::Int(_), Value::Int(b)) = (self, other) {
+            if *b < 0 {
+                return Err(OpError::NegativeExponent);
+            }
         }
+        self.arithmetic(
+            other,
+            |a, b| u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+            f64::powf,
+        )
     }
 
-    pub fn less_than(&self, other: &Value) -> Value {
-        let values = (self, other);
-        match values {
-            (Value::Number(a), Value::Number(b)) => Value::from_bool(a < b),
-            _ => Value::Nothing,
+    /// The values a `for` loop visits, strings are visited one character at a
+    /// time.
+    pub fn iterate(&self) -> Option<Vector<Value>> {
+        match self {
+            Value::String(s) => Some(
+                s.chars()
+                    .map(|c| Value::from_string(c.to_string()))
+                    .collect(),
+            ),
+            Value::List(items) => Some(items.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn negate(&self) -> OpResult {
+        match self {
+            Value::Int(a) => a.checked_neg().map(Value::Int).ok_or(OpError::Overflow),
+            Value::Float(a) => Ok(Value::Float(-a)),
+            _ => Err(self.type_error(None)),
+        }
+    }
+
+    pub fn plus(&self) -> OpResult {
+        match self {
+            Value::Int(_) | Value::Float(_) => Ok(self.clone()),
+            _ => Err(self.type_error(None)),
+        }
+    }
+
+    pub fn not(&self) -> OpResult {
+        match self {
+            Value::True => Ok(Value::False),
+            Value::False => Ok(Value::True),
+            _ => Err(self.type_error(None)),
+        }
+    }
+
+    pub fn and(&self, other: &Value) -> OpResult {
+        match (self, other) {
+            (Value::True, Value::True) => Ok(Value::True),
+            (Value::True, Value::False)
+            | (Value::False, Value::True)
+            | (Value::False, Value::False) => Ok(Value::False),
+            _ => Err(self.type_error(Some(other))),
+        }
+    }
+
+    pub fn or(&self, other: &Value) -> OpResult {
+        match (self, other) {
+            (Value::False, Value::False) => Ok(Value::False),
+            (Value::True, Value::True)
+            | (Value::True, Value::False)
+            | (Value::False, Value::True) => Ok(Value::True),
+            _ => Err(self.type_error(Some(other))),
+        }
+    }
+
+    /// Values of any types can be compared, values of different types are
+    /// never equal.
+    pub fn equal(&self, other: &Value) -> OpResult {
+        Ok(Value::from_bool(self == other))
+    }
+
+    pub fn not_equal(&self, other: &Value) -> OpResult {
+        Ok(Value::from_bool(self != other))
+    }
+
+    /// Ints are compared with Ints and Floats with Floats.
+    fn compare(&self, other: &Value, test: fn(Ordering) -> bool) -> OpResult {
+        let ordering = match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            _ => return Err(self.type_error(Some(other))),
+        };
+        Ok(Value::from_bool(matches!(ordering, Some(o) if test(o))))
+    }
+
+    pub fn greater_than(&self, other: &Value) -> OpResult {
+        self.compare(other, Ordering::is_gt)
+    }
+
+    pub fn less_than(&self, other: &Value) -> OpResult {
+        self.compare(other, Ordering::is_lt)
+    }
+
+    pub fn greater_equal(&self, other: &Value) -> OpResult {
+        self.compare(other, Ordering::is_ge)
+    }
+
+    pub fn less_equal(&self, other: &Value) -> OpResult {
+        self.compare(other, Ordering::is_le)
+    }
+}
+
+/// The types of the operands when an operator can't be used with them, `rhs`
+/// is missing for prefix operators.
+#[derive(Debug, PartialEq)]
+pub struct TypeError {
+    pub lhs: String,
+    pub rhs: Option<String>,
+}
+
+/// Why an operator couldn't produce a value.
+#[derive(Debug, PartialEq)]
+pub enum OpError {
+    Type(TypeError),
+    /// The result doesn't fit in an Int.
+    Overflow,
+    DivisionByZero,
+    /// An Int raised to a negative power is not an Int.
+    NegativeExponent,
+}
+
+pub type OpResult = Result<Value, OpError>;
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            // A fraction is always shown, so a Float never reads as an Int.
+            Value::Float(n) if n.fract() == 0.0 => write!(f, "{}.0", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Nothing => write!(f, "Nothing"),
+            Value::True => write!(f, "True"),
+            Value::False => write!(f, "False"),
+            Value::String(s) => write!(f, "{}", s),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match item {
+                        Value::String(s) => write!(f, "{:?}", s)?,
+                        item => write!(f, "{}", item)?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Value::Record(record) => {
+                write!(f, "{} {{", record.record_type.name)?;
+                let fields = record.record_type.fields.iter().zip(record.values.iter());
+                for (i, (name, value)) in fields.enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    match value {
+                        Value::String(s) => write!(f, "{} {}: {:?}", separator, name, s)?,
+                        value => write!(f, "{} {}: {}", separator, name, value)?,
+                    }
+                }
+                write!(f, " }}")
+            }
+            Value::Variant(variant) if variant.values.is_empty() => {
+                write!(f, "{}", variant.name())
+            }
+            Value::Variant(variant) => {
+                write!(f, "{}(", variant.name())?;
+                for (i, value) in variant.values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match value {
+                        Value::String(s) => write!(f, "{:?}", s)?,
+                        value => write!(f, "{}", value)?,
+                    }
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -88,7 +371,10 @@ mod tests {
 
     #[test]
     fn number_to_string() {
-        assert_eq!(Value::Number(42.0).to_string(), "42")
+        assert_eq!(Value::Float(42.0).to_string(), "42.0");
+        assert_eq!(Value::Float(1000.0).to_string(), "1000.0");
+        assert_eq!(Value::Float(2.5).to_string(), "2.5");
+        assert_eq!(Value::Int(42).to_string(), "42")
     }
 
     #[test]
@@ -101,16 +387,175 @@ mod tests {
         assert_eq!(Value::False.to_string(), "False")
     }
 
+    #[test]
+    fn string_to_string() {
+        assert_eq!(Value::from_string("Hello").to_string(), "Hello")
+    }
+
+    #[test]
+    fn list_to_string() {
+        let list = Value::List(
+            vec![
+                Value::Float(1.0),
+                Value::from_string("a"),
+                Value::List(Vector::new()),
+            ]
+            .into(),
+        );
+        assert_eq!(list.to_string(), "[1.0, \"a\", []]")
+    }
+
+    #[test]
+    fn record_to_string() {
+        let record_type = Arc::new(RecordType {
+            name: "Hello".to_string(),
+            kind: RecordKind::Event,
+            fields: vec!["hello".to_string(), "count".to_string()],
+        });
+        let record = Value::Record(Record {
+            record_type,
+            values: vec![Value::from_string("world"), Value::Float(1.0)].into(),
+        });
+        assert_eq!(record.to_string(), "Hello { hello: \"world\", count: 1.0 }")
+    }
+
+    #[test]
+    fn variant_to_string() {
+        let data_type = Arc::new(DataType {
+            name: "Shape".to_string(),
+            variants: vec![
+                VariantType {
+                    name: "Empty".to_string(),
+                    fields: Vec::new(),
+                },
+                VariantType {
+                    name: "Named".to_string(),
+                    fields: vec!["name".to_string(), "size".to_string()],
+                },
+            ],
+        });
+        let empty = Value::Variant(Variant {
+            data_type: Arc::clone(&data_type),
+            index: 0,
+            values: Vector::new(),
+        });
+        assert_eq!(empty.to_string(), "Empty");
+        let named = Value::Variant(Variant {
+            data_type,
+            index: 1,
+            values: vec![Value::from_string("a"), Value::Float(2.0)].into(),
+        });
+        assert_eq!(named.to_string(), "Named(\"a\", 2.0)");
+        assert_eq!(named.type_name(), "Shape");
+    }
+
     #[test]
     fn add_numbers() {
         assert_eq!(
-            Value::Number(5.0).add(&Value::Number(10.0)),
-            Value::Number(15.0)
+            Value::Float(5.0).add(&Value::Float(10.0)),
+            Ok(Value::Float(15.0))
         )
     }
 
     #[test]
     fn add_wrong_type() {
-        assert_eq!(Value::Number(5.0).add(&Value::Nothing), Value::Nothing)
+        assert_eq!(
+            Value::Float(5.0).add(&Value::Nothing),
+            Err(OpError::Type(TypeError {
+                lhs: "Float".to_string(),
+                rhs: Some("Nothing".to_string()),
+            }))
+        )
+    }
+
+    #[test]
+    fn add_strings() {
+        assert_eq!(
+            Value::from_string("Hello ").add(&Value::from_string("world!")),
+            Ok(Value::from_string("Hello world!"))
+        )
+    }
+
+    #[test]
+    fn add_string_and_number() {
+        assert_eq!(
+            Value::from_string("Hello").add(&Value::Float(1.0)),
+            Err(OpError::Type(TypeError {
+                lhs: "String".to_string(),
+                rhs: Some("Float".to_string()),
+            }))
+        )
+    }
+
+    #[test]
+    fn negate_wrong_type() {
+        assert_eq!(
+            Value::True.negate(),
+            Err(OpError::Type(TypeError {
+                lhs: "Boolean".to_string(),
+                rhs: None,
+            }))
+        )
+    }
+
+    #[test]
+    fn int_arithmetic() {
+        let (seven, two) = (Value::Int(7), Value::Int(2));
+        assert_eq!(seven.divide(&two), Ok(Value::Int(3)));
+        assert_eq!(Value::Int(-7).remainder(&two), Ok(Value::Int(-1)));
+        assert_eq!(two.power(&Value::Int(10)), Ok(Value::Int(1024)));
+        assert_eq!(
+            Value::Float(7.0).divide(&Value::Float(2.0)),
+            Ok(Value::Float(3.5))
+        );
+        assert!(seven.add(&Value::Float(1.0)).is_err());
+    }
+
+    #[test]
+    fn int_errors() {
+        let max = Value::Int(i64::MAX);
+        assert_eq!(max.add(&Value::Int(1)), Err(OpError::Overflow));
+        assert_eq!(Value::Int(i64::MIN).negate(), Err(OpError::Overflow));
+        assert_eq!(max.power(&Value::Int(2)), Err(OpError::Overflow));
+        assert_eq!(
+            Value::Int(2).power(&Value::Int(-1)),
+            Err(OpError::NegativeExponent)
+        );
+        assert_eq!(
+            Value::Int(1).divide(&Value::Int(0)),
+            Err(OpError::DivisionByZero)
+        );
+        assert_eq!(
+            Value::Int(1).remainder(&Value::Int(0)),
+            Err(OpError::DivisionByZero)
+        );
+        assert_eq!(
+            Value::Float(1.0).divide(&Value::Float(0.0)),
+            Err(OpError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let (one, two) = (Value::Int(1), Value::Int(2));
+        assert_eq!(one.less_equal(&two), Ok(Value::True));
+        assert_eq!(one.less_equal(&one), Ok(Value::True));
+        assert_eq!(one.greater_equal(&two), Ok(Value::False));
+        assert_eq!(one.not_equal(&Value::from_string("1")), Ok(Value::True));
+        assert!(one.greater_equal(&Value::Nothing).is_err());
+    }
+
+    #[test]
+    fn boolean_logic() {
+        assert_eq!(Value::True.and(&Value::False), Ok(Value::False));
+        assert_eq!(Value::False.or(&Value::True), Ok(Value::True));
+        assert_eq!(Value::True.not(), Ok(Value::False));
+        assert_eq!(
+            Value::True.or(&Value::Float(1.0)),
+            Err(OpError::Type(TypeError {
+                lhs: "Boolean".to_string(),
+                rhs: Some("Float".to_string()),
+            }))
+        );
     }
 }